@@ -0,0 +1,13 @@
+-- CreateTable
+CREATE TABLE IF NOT EXISTS instance_stats (
+    instance_uuid String,
+    product String,
+    version String,
+    commit_sha Nullable(String),
+    build_date Nullable(String),
+    snapshot_date DateTime('UTC'),
+    build_flavour LowCardinality(String),
+    data String,
+    collected_at DateTime('UTC')
+) ENGINE = MergeTree()
+ORDER BY (instance_uuid, snapshot_date);
@@ -0,0 +1,10 @@
+-- CreateTable
+CREATE TABLE IF NOT EXISTS events (
+    instance_uuid String,
+    name LowCardinality(String),
+    timestamp DateTime('UTC'),
+    properties String,
+    received_at DateTime('UTC')
+) ENGINE = MergeTree()
+PARTITION BY toYYYYMM(timestamp)
+ORDER BY (instance_uuid, name, timestamp);
@@ -0,0 +1,12 @@
+-- CreateTable
+CREATE TABLE IF NOT EXISTS instance_health (
+    instance_uuid String,
+    status LowCardinality(String),
+    previous_status LowCardinality(String),
+    latency_ms UInt64,
+    error String,
+    checked_at DateTime('UTC')
+) ENGINE = MergeTree()
+PARTITION BY toYYYYMM(checked_at)
+ORDER BY (instance_uuid, checked_at)
+TTL checked_at + INTERVAL 90 DAY;
@@ -0,0 +1,18 @@
+-- CreateTable
+CREATE TABLE IF NOT EXISTS instance_metrics (
+    instance_uuid String,
+    name LowCardinality(String),
+    type LowCardinality(String),
+    label_names Array(String),
+    label_values Array(String),
+    value Float64,
+    count UInt64,
+    sum Float64,
+    bucket_bounds Array(Float64),
+    bucket_counts Array(UInt64),
+    quantiles Array(Float64),
+    quantile_values Array(Float64),
+    snapshot_date DateTime('UTC')
+) ENGINE = MergeTree()
+PARTITION BY toYYYYMM(snapshot_date)
+ORDER BY (instance_uuid, name, snapshot_date);
@@ -0,0 +1,13 @@
+-- CreateTable
+CREATE TABLE IF NOT EXISTS remote_write_samples (
+    instance_uuid String,
+    name LowCardinality(String),
+    label_names Array(String),
+    label_values Array(String),
+    value Float64,
+    timestamp_ms Int64,
+    timestamp DateTime('UTC') MATERIALIZED toDateTime(intDiv(timestamp_ms, 1000), 'UTC')
+) ENGINE = MergeTree()
+PARTITION BY toYYYYMM(timestamp)
+ORDER BY (instance_uuid, name, timestamp_ms)
+TTL timestamp + INTERVAL 90 DAY;
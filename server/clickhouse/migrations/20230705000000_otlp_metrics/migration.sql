@@ -0,0 +1,27 @@
+-- CreateTable
+CREATE TABLE IF NOT EXISTS otlp_metrics (
+    instance_uuid String,
+    service_name LowCardinality(String),
+    resource_keys Array(String),
+    resource_values Array(String),
+    scope_name LowCardinality(String),
+    name LowCardinality(String),
+    unit LowCardinality(String),
+    type LowCardinality(String),
+    temporality LowCardinality(String),
+    is_monotonic UInt8,
+    attribute_keys Array(String),
+    attribute_values Array(String),
+    value Float64,
+    count UInt64,
+    sum Float64,
+    bucket_bounds Array(Float64),
+    bucket_counts Array(UInt64),
+    quantiles Array(Float64),
+    quantile_values Array(Float64),
+    timestamp_ns UInt64,
+    timestamp DateTime('UTC') MATERIALIZED toDateTime(intDiv(timestamp_ns, 1000000000), 'UTC')
+) ENGINE = MergeTree()
+PARTITION BY toYYYYMM(timestamp)
+ORDER BY (service_name, instance_uuid, name, timestamp_ns)
+TTL timestamp + INTERVAL 90 DAY;
@@ -0,0 +1,20 @@
+-- CreateTable
+CREATE TABLE IF NOT EXISTS otlp_logs (
+    instance_uuid String,
+    service_name LowCardinality(String),
+    resource_keys Array(String),
+    resource_values Array(String),
+    scope_name LowCardinality(String),
+    severity_number Int32,
+    severity_text LowCardinality(String),
+    body String,
+    attribute_keys Array(String),
+    attribute_values Array(String),
+    trace_id String,
+    span_id String,
+    timestamp_ns UInt64,
+    timestamp DateTime('UTC') MATERIALIZED toDateTime(intDiv(timestamp_ns, 1000000000), 'UTC')
+) ENGINE = MergeTree()
+PARTITION BY toYYYYMM(timestamp)
+ORDER BY (service_name, instance_uuid, timestamp_ns)
+TTL timestamp + INTERVAL 90 DAY;
@@ -0,0 +1,16 @@
+-- CreateTable
+CREATE TABLE IF NOT EXISTS logs (
+    instance_uuid String,
+    timestamp_ms Int64,
+    timestamp DateTime('UTC') MATERIALIZED toDateTime(intDiv(timestamp_ms, 1000), 'UTC'),
+    level LowCardinality(String),
+    severity UInt8,
+    message String,
+    field_keys Array(String),
+    field_values Array(String),
+    received_at DateTime('UTC'),
+    INDEX message_tokens lowerUTF8(message) TYPE tokenbf_v1(32768, 3, 0) GRANULARITY 4
+) ENGINE = MergeTree()
+PARTITION BY toYYYYMM(timestamp)
+ORDER BY (instance_uuid, timestamp_ms)
+TTL timestamp + INTERVAL 30 DAY;
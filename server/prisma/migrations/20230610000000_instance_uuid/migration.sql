@@ -0,0 +1,12 @@
+/*
+  Warnings:
+
+  - A unique constraint covering the columns `[uuid]` on the table `instances` will be added. If there are existing duplicate values, this will fail.
+  - Added the required column `uuid` to the `instances` table without a default value. This is not possible if the table is not empty.
+
+*/
+-- AlterTable
+ALTER TABLE "instances" ADD COLUMN     "uuid" TEXT NOT NULL;
+
+-- CreateIndex
+CREATE UNIQUE INDEX "instances_uuid_key" ON "instances"("uuid");
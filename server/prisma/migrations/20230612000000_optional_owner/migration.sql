@@ -0,0 +1,14 @@
+-- DropForeignKey
+ALTER TABLE "dashboards" DROP CONSTRAINT "dashboards_instance_id_fkey";
+
+-- DropForeignKey
+ALTER TABLE "instances" DROP CONSTRAINT "instances_owner_id_fkey";
+
+-- AlterTable
+ALTER TABLE "instances" ALTER COLUMN "owner_id" DROP NOT NULL;
+
+-- AddForeignKey
+ALTER TABLE "instances" ADD CONSTRAINT "instances_owner_id_fkey" FOREIGN KEY ("owner_id") REFERENCES "users"("id") ON DELETE SET NULL ON UPDATE CASCADE;
+
+-- AddForeignKey
+ALTER TABLE "dashboards" ADD CONSTRAINT "dashboards_instance_id_fkey" FOREIGN KEY ("instance_id") REFERENCES "instances"("id") ON DELETE CASCADE ON UPDATE CASCADE;
@@ -0,0 +1,18 @@
+-- CreateTable
+CREATE TABLE "alert_rules" (
+    "name" TEXT NOT NULL,
+    "expression" TEXT NOT NULL,
+    "channels" TEXT[],
+    "state" TEXT NOT NULL DEFAULT 'inactive',
+    "active_since" TIMESTAMP(3),
+    "last_value" DOUBLE PRECISION,
+    "last_evaluated_at" TIMESTAMP(3),
+    "instance_id" BIGINT NOT NULL,
+    "created_at" TIMESTAMP(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "id" BIGINT NOT NULL,
+
+    CONSTRAINT "alert_rules_pkey" PRIMARY KEY ("id")
+);
+
+-- AddForeignKey
+ALTER TABLE "alert_rules" ADD CONSTRAINT "alert_rules_instance_id_fkey" FOREIGN KEY ("instance_id") REFERENCES "instances"("id") ON DELETE CASCADE ON UPDATE CASCADE;
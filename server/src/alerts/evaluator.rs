@@ -0,0 +1,259 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::alerts::notifier::{AlertNotification, Notifiers};
+use crate::clickhouse::client::ClickHouse;
+use crate::config::AlertsConfig;
+use crate::models::alert::{parse_channels, AlertCondition, AlertState};
+use crate::prisma::{alert_rule, PrismaClient};
+
+/// Background job that evaluates every alert rule against the latest stats snapshot of its
+/// instance on an interval, stores the state that the rule ends up in and notifies the rule's
+/// channels whenever it starts firing or is resolved.
+#[derive(Debug, Clone)]
+pub struct AlertEvaluator {
+    clickhouse: Arc<ClickHouse>,
+    prisma: Arc<PrismaClient>,
+    notifiers: Notifiers,
+    interval: Duration,
+    max_snapshot_age: u64,
+}
+
+impl AlertEvaluator {
+    pub fn new(
+        clickhouse: Arc<ClickHouse>,
+        prisma: Arc<PrismaClient>,
+        notifiers: Notifiers,
+        config: AlertsConfig,
+    ) -> AlertEvaluator {
+        let defaults = AlertsConfig::default();
+
+        AlertEvaluator {
+            clickhouse,
+            prisma,
+            notifiers,
+            interval: Duration::from_secs(config.interval.or(defaults.interval).unwrap().max(1)),
+            max_snapshot_age: config
+                .max_snapshot_age
+                .or(defaults.max_snapshot_age)
+                .unwrap()
+                .max(1),
+        }
+    }
+
+    /// Spawns the evaluator in the background. The first evaluation happens right away.
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            info!("evaluating alert rules every {:?}", self.interval);
+
+            let mut ticker = interval(self.interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                ticker.tick().await;
+                match self.evaluate_all().await {
+                    Ok(count) => debug!("evaluated {count} alert rules"),
+                    Err(e) => error!("unable to evaluate alert rules: {e}"),
+                }
+            }
+        })
+    }
+
+    /// Evaluates every rule once, returning how many were evaluated.
+    pub async fn evaluate_all(&self) -> Result<usize> {
+        let rules = self.prisma.alert_rule().find_many(vec![]).exec().await?;
+        if rules.is_empty() {
+            return Ok(0);
+        }
+
+        let instances = self
+            .prisma
+            .instance()
+            .find_many(vec![])
+            .exec()
+            .await?
+            .into_iter()
+            .map(|i| (i.id, i.uuid))
+            .collect::<HashMap<_, _>>();
+
+        let mut evaluated = 0;
+        for rule in rules {
+            let instance = match instances.get(&rule.instance_id) {
+                Some(instance) => instance,
+                None => continue,
+            };
+
+            match self.evaluate(&rule, instance, Utc::now()).await {
+                Ok(()) => evaluated += 1,
+                Err(e) => warn!("unable to evaluate alert rule {}: {e}", rule.id),
+            }
+        }
+
+        Ok(evaluated)
+    }
+
+    async fn evaluate(
+        &self,
+        rule: &alert_rule::Data,
+        instance: &str,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        // rules are validated when they're stored, so this only fails if the syntax or
+        // its limits changed since then
+        let condition = AlertCondition::parse(&rule.expression).map_err(anyhow::Error::msg)?;
+
+        let block = self
+            .clickhouse
+            .query(condition.to_sql(instance, self.max_snapshot_age))
+            .await?;
+
+        let (value, threshold) = match block.rows().next() {
+            Some(row) => {
+                let value: Result<f64, _> = row.get("lhs");
+                let threshold: Result<f64, _> = row.get("rhs");
+                (
+                    value.ok().filter(|v| v.is_finite()),
+                    threshold.ok().filter(|v| v.is_finite()),
+                )
+            }
+            None => (None, None),
+        };
+
+        let previous = rule
+            .state
+            .parse::<AlertState>()
+            .unwrap_or(AlertState::Inactive);
+        let (state, since) = transition(
+            previous,
+            &condition,
+            value.zip(threshold),
+            rule.active_since.map(|since| since.with_timezone(&Utc)),
+            now,
+        );
+
+        self.prisma
+            .alert_rule()
+            .update(
+                alert_rule::id::equals(rule.id),
+                vec![
+                    alert_rule::state::set(state.as_str().to_string()),
+                    alert_rule::active_since::set(since.map(Into::into)),
+                    alert_rule::last_value::set(value),
+                    alert_rule::last_evaluated_at::set(Some(now.into())),
+                ],
+            )
+            .exec()
+            .await?;
+
+        if state == previous || !matches!(state, AlertState::Firing | AlertState::Resolved) {
+            return Ok(());
+        }
+
+        info!(
+            "alert rule {} ({}) of instance {instance} is now {state}",
+            rule.id, rule.name
+        );
+
+        self.notifiers
+            .notify(
+                &parse_channels(&rule.channels),
+                &AlertNotification {
+                    rule_id: rule.id.to_string(),
+                    name: rule.name.clone(),
+                    instance: instance.to_string(),
+                    expression: rule.expression.clone(),
+                    state,
+                    value,
+                    threshold,
+                    active_since: since,
+                    evaluated_at: now,
+                },
+            )
+            .await;
+
+        Ok(())
+    }
+}
+
+/// Returns the state that a rule in the `previous` state moves to, given the value and
+/// threshold of the latest snapshot. Instances without recent stats can't tell if the
+/// condition holds, so their rules stay in the state they're in until they can, instead
+/// of resolving alerts that might still be firing.
+fn transition(
+    previous: AlertState,
+    condition: &AlertCondition,
+    latest: Option<(f64, f64)>,
+    since: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> (AlertState, Option<DateTime<Utc>>) {
+    match latest {
+        Some((value, threshold)) => previous.next(
+            condition.comparison.holds(value, threshold),
+            since,
+            condition.duration,
+            now,
+        ),
+        None => (previous, since),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn keeps_the_state_without_recent_stats() {
+        let condition = AlertCondition::parse("data.heap_used > 100 for 5m").unwrap();
+        let start = Utc.timestamp_opt(1685577600, 0).unwrap();
+        let later = |secs| start + chrono::Duration::seconds(secs);
+
+        let (state, since) = transition(
+            AlertState::Firing,
+            &condition,
+            None,
+            Some(start),
+            later(600),
+        );
+        assert_eq!((state, since), (AlertState::Firing, Some(start)));
+
+        let (state, since) = transition(
+            AlertState::Pending,
+            &condition,
+            None,
+            Some(start),
+            later(600),
+        );
+        assert_eq!((state, since), (AlertState::Pending, Some(start)));
+
+        let (state, since) = transition(
+            AlertState::Firing,
+            &condition,
+            Some((50.0, 100.0)),
+            Some(start),
+            later(600),
+        );
+        assert_eq!((state, since), (AlertState::Resolved, None));
+    }
+}
@@ -0,0 +1,412 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use crate::config::{AlertEmailConfig, AlertWebhookConfig, AlertsConfig};
+use crate::models::alert::{AlertChannel, AlertState};
+use crate::sinks::webhook::sign;
+
+/// How long a single notification can take before it is given up on.
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sent when an alert rule starts firing or is resolved.
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertNotification {
+    pub rule_id: String,
+    pub name: String,
+    pub instance: String,
+    pub expression: String,
+    pub state: AlertState,
+
+    /// Both sides of the condition in the evaluation that changed the state, if the
+    /// instance had any recent stats.
+    pub value: Option<f64>,
+    pub threshold: Option<f64>,
+
+    /// When the condition started to hold, if it still does.
+    pub active_since: Option<DateTime<Utc>>,
+    pub evaluated_at: DateTime<Utc>,
+}
+
+impl AlertNotification {
+    /// Returns a one-line summary of the notification. Control characters are replaced, so
+    /// that it can't break out of the `Subject` header of emails.
+    pub fn subject(&self) -> String {
+        format!(
+            "[{}] {} on instance {}",
+            self.state.as_str().to_uppercase(),
+            self.name,
+            self.instance
+        )
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+    }
+}
+
+/// Somewhere that alert notifications are sent to.
+#[async_trait]
+pub trait Notifier: Debug + Send + Sync {
+    async fn notify(&self, notification: &AlertNotification) -> Result<()>;
+}
+
+/// POSTs every notification as JSON, signed the same way as the webhook sink's deliveries.
+#[derive(Debug, Clone)]
+pub struct WebhookNotifier {
+    client: Client,
+    url: String,
+    secret: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(config: &AlertWebhookConfig) -> Result<WebhookNotifier> {
+        Ok(WebhookNotifier {
+            client: Client::builder().timeout(NOTIFY_TIMEOUT).build()?,
+            url: config.url.clone(),
+            secret: config.secret.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    async fn notify(&self, notification: &AlertNotification) -> Result<()> {
+        let body = serde_json::to_string(notification)?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system clock is before the UNIX epoch")
+            .as_secs();
+
+        let response = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header("X-Analytics-Timestamp", timestamp.to_string())
+            .header(
+                "X-Analytics-Signature",
+                format!("sha256={}", sign(&self.secret, timestamp, &body)),
+            )
+            .body(body)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(anyhow!("webhook responded with {status}"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Sends every notification as a plain text email through an SMTP relay. This only speaks
+/// enough SMTP to hand mail to a local relay or a test server like MailHog, which have to
+/// accept it without TLS or authentication.
+#[derive(Debug, Clone)]
+pub struct EmailNotifier {
+    host: String,
+    port: u16,
+    from: String,
+    to: Vec<String>,
+}
+
+impl EmailNotifier {
+    pub fn new(config: &AlertEmailConfig) -> Result<EmailNotifier> {
+        if config.to.is_empty() {
+            return Err(anyhow!(
+                "at least one address has to be configured in `alerts.email.to`"
+            ));
+        }
+
+        Ok(EmailNotifier {
+            host: config.host(),
+            port: config.port(),
+            from: config.from(),
+            to: config.to.clone(),
+        })
+    }
+
+    /// Builds the message that is sent after `DATA`, with every line that starts with a
+    /// `.` escaped so it doesn't end the message early.
+    pub fn message(&self, notification: &AlertNotification) -> String {
+        let mut body = format!(
+            "Alert rule \"{}\" is now {} on instance {}.\n\nExpression: {}\n",
+            notification.name, notification.state, notification.instance, notification.expression
+        );
+
+        if let Some(value) = notification.value {
+            body.push_str(&format!("Value: {value}\n"));
+        }
+
+        if let Some(threshold) = notification.threshold {
+            body.push_str(&format!("Threshold: {threshold}\n"));
+        }
+
+        if let Some(since) = notification.active_since {
+            body.push_str(&format!("Active since: {}\n", since.to_rfc3339()));
+        }
+
+        body.push_str(&format!(
+            "Evaluated at: {}\n",
+            notification.evaluated_at.to_rfc3339()
+        ));
+
+        let headers = format!(
+            "From: <{}>\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+            self.from,
+            self.to
+                .iter()
+                .map(|to| format!("<{to}>"))
+                .collect::<Vec<_>>()
+                .join(", "),
+            notification.subject(),
+            notification.evaluated_at.to_rfc2822()
+        );
+
+        let body = body
+            .lines()
+            .map(|line| match line.starts_with('.') {
+                true => format!(".{line}\r\n"),
+                false => format!("{line}\r\n"),
+            })
+            .collect::<String>();
+
+        format!("{headers}{body}")
+    }
+
+    async fn send(&self, message: String) -> Result<()> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        let (read, mut write) = stream.into_split();
+        let mut read = BufReader::new(read);
+
+        reply(&mut read, 220).await?;
+        command(&mut write, &mut read, "EHLO analytics-server".into(), 250).await?;
+        command(
+            &mut write,
+            &mut read,
+            format!("MAIL FROM:<{}>", self.from),
+            250,
+        )
+        .await?;
+        for to in &self.to {
+            command(&mut write, &mut read, format!("RCPT TO:<{to}>"), 250).await?;
+        }
+
+        command(&mut write, &mut read, "DATA".into(), 354).await?;
+        command(&mut write, &mut read, format!("{message}."), 250).await?;
+        command(&mut write, &mut read, "QUIT".into(), 221).await
+    }
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    async fn notify(&self, notification: &AlertNotification) -> Result<()> {
+        match timeout(NOTIFY_TIMEOUT, self.send(self.message(notification))).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow!("SMTP relay {}:{} timed out", self.host, self.port)),
+        }
+    }
+}
+
+/// Writes a single SMTP command and waits for its reply.
+async fn command<W: AsyncWriteExt + Unpin, R: AsyncBufReadExt + Unpin>(
+    write: &mut W,
+    read: &mut R,
+    command: String,
+    expected: u16,
+) -> Result<()> {
+    write.write_all(format!("{command}\r\n").as_bytes()).await?;
+    reply(read, expected).await
+}
+
+/// Reads a reply, which can span multiple lines like `250-first`, `250 last`, and checks
+/// that it has the expected code.
+async fn reply<R: AsyncBufReadExt + Unpin>(read: &mut R, expected: u16) -> Result<()> {
+    loop {
+        let mut line = String::new();
+        if read.read_line(&mut line).await? == 0 {
+            return Err(anyhow!("SMTP relay closed the connection"));
+        }
+
+        let code = line.get(..3).and_then(|code| code.parse::<u16>().ok());
+        if code != Some(expected) {
+            return Err(anyhow!("SMTP relay replied with `{}`", line.trim_end()));
+        }
+
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+/// The notifiers of every channel that was configured in `alerts`.
+#[derive(Debug, Clone, Default)]
+pub struct Notifiers {
+    notifiers: HashMap<AlertChannel, Arc<dyn Notifier>>,
+}
+
+impl Notifiers {
+    pub fn from_config(config: &AlertsConfig) -> Result<Notifiers> {
+        let mut notifiers: HashMap<AlertChannel, Arc<dyn Notifier>> = HashMap::new();
+        if let Some(webhook) = &config.webhook {
+            notifiers.insert(
+                AlertChannel::Webhook,
+                Arc::new(WebhookNotifier::new(webhook)?),
+            );
+        }
+
+        if let Some(email) = &config.email {
+            notifiers.insert(AlertChannel::Email, Arc::new(EmailNotifier::new(email)?));
+        }
+
+        Ok(Notifiers { notifiers })
+    }
+
+    /// Returns if the channel was configured, so rules can't be created with channels
+    /// that their notifications would never be sent to.
+    pub fn has(&self, channel: AlertChannel) -> bool {
+        self.notifiers.contains_key(&channel)
+    }
+
+    /// Sends the notification to every given channel. Failures are only logged, since the
+    /// rule's state was already stored.
+    pub async fn notify(&self, channels: &[AlertChannel], notification: &AlertNotification) {
+        for channel in channels {
+            let notifier = match self.notifiers.get(channel) {
+                Some(notifier) => notifier,
+                None => {
+                    warn!(
+                        "alert rule {} uses the {channel} channel, which isn't configured",
+                        notification.rule_id
+                    );
+
+                    continue;
+                }
+            };
+
+            if let Err(e) = notifier.notify(notification).await {
+                error!(
+                    "unable to send {channel} notification for alert rule {}: {e}",
+                    notification.rule_id
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn sends_emails_through_an_smtp_relay() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let relay = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut read = BufReader::new(read);
+            let mut transcript = String::new();
+
+            write.write_all(b"220 relay ready\r\n").await.unwrap();
+            loop {
+                let mut line = String::new();
+                read.read_line(&mut line).await.unwrap();
+                transcript.push_str(&line);
+
+                let reply: &[u8] = match line.trim_end() {
+                    l if l.starts_with("EHLO") => b"250-relay\r\n250 8BITMIME\r\n",
+                    "DATA" => b"354 go ahead\r\n",
+                    "." => b"250 queued\r\n",
+                    "QUIT" => {
+                        write.write_all(b"221 bye\r\n").await.unwrap();
+                        break;
+                    }
+                    l if l.starts_with("MAIL") || l.starts_with("RCPT") => b"250 ok\r\n",
+                    _ => continue,
+                };
+
+                write.write_all(reply).await.unwrap();
+            }
+
+            transcript
+        });
+
+        let notifier = EmailNotifier::new(&AlertEmailConfig {
+            host: Some("127.0.0.1".into()),
+            port: Some(port),
+            from: None,
+            to: vec!["oncall@noelware.org".into()],
+        })
+        .unwrap();
+
+        notifier
+            .notify(&AlertNotification {
+                rule_id: "1".into(),
+                name: "heap usage".into(),
+                instance: "waff".into(),
+                expression: "data.heap_used > 0.9 * data.heap_max for 5m".into(),
+                state: AlertState::Firing,
+                value: Some(950.0),
+                threshold: Some(900.0),
+                active_since: Some(Utc.timestamp_opt(1685577600, 0).unwrap()),
+                evaluated_at: Utc.timestamp_opt(1685577900, 0).unwrap(),
+            })
+            .await
+            .unwrap();
+
+        let transcript = relay.await.unwrap();
+        assert!(transcript.contains("MAIL FROM:<analytics@localhost>\r\n"));
+        assert!(transcript.contains("RCPT TO:<oncall@noelware.org>\r\n"));
+        assert!(transcript.contains("Subject: [FIRING] heap usage on instance waff\r\n"));
+        assert!(transcript.contains("Value: 950\r\n"));
+        assert!(transcript.ends_with("QUIT\r\n"));
+    }
+    #[test]
+    fn keeps_subjects_on_a_single_line() {
+        let notification = AlertNotification {
+            rule_id: "1".into(),
+            name: "heap\r\nBcc: everyone@noelware.org".into(),
+            instance: "waff".into(),
+            expression: "data.heap_used > 1".into(),
+            state: AlertState::Firing,
+            value: None,
+            threshold: None,
+            active_since: None,
+            evaluated_at: Utc.timestamp_opt(1685577900, 0).unwrap(),
+        };
+
+        assert_eq!(
+            notification.subject(),
+            "[FIRING] heap  Bcc: everyone@noelware.org on instance waff"
+        );
+    }
+}
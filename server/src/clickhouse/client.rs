@@ -19,8 +19,10 @@ use std::sync::{
 };
 
 use anyhow::Result;
-use clickhouse_rs::Pool;
-use futures::lock::Mutex;
+use clickhouse_rs::{
+    types::{Block, Complex},
+    Pool,
+};
 
 use crate::config::ClickHouseConfig;
 
@@ -29,39 +31,127 @@ use crate::config::ClickHouseConfig;
 #[derive(Debug, Clone)]
 pub struct ClickHouse {
     /// How many database calls have been used during the server's lifetime.
-    calls: Arc<Mutex<AtomicUsize>>,
+    calls: Arc<AtomicUsize>,
+
+    /// How many database calls have failed during the server's lifetime.
+    errors: Arc<AtomicUsize>,
+
+    /// How many calls are currently running, including the ones that are
+    /// waiting on a connection from the pool.
+    in_flight: Arc<AtomicUsize>,
+
+    /// The bounds of the pool, as `(min, max)`.
+    pool_size: (u16, u16),
 
     /// The connection pool itself.
     pool: Pool,
 }
 
+/// Keeps a call counted as in-flight until it is dropped.
+struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    fn new(counter: &Arc<AtomicUsize>) -> InFlight {
+        counter.fetch_add(1, Ordering::SeqCst);
+        InFlight(counter.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 impl ClickHouse {
     pub fn new(config: ClickHouseConfig) -> Result<ClickHouse> {
         let url = config.to_string();
         let pool = Pool::new(url);
 
         Ok(ClickHouse {
-            calls: Arc::new(Mutex::new(AtomicUsize::new(0))),
+            calls: Arc::new(AtomicUsize::new(0)),
+            errors: Arc::new(AtomicUsize::new(0)),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            pool_size: (
+                config.min_connections_in_pool.unwrap_or(10),
+                config.max_connections_in_pool.unwrap_or(20),
+            ),
             pool,
         })
     }
 
     pub fn calls(&self) -> usize {
-        self.calls.try_lock().unwrap().load(Ordering::SeqCst)
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    pub fn errors(&self) -> usize {
+        self.errors.load(Ordering::SeqCst)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    pub fn pool_size(&self) -> (u16, u16) {
+        self.pool_size
+    }
+
+    /// Counts the call as failed if it returned an error.
+    fn track<T, E: Into<anyhow::Error>>(&self, result: std::result::Result<T, E>) -> Result<T> {
+        result.map_err(|e| {
+            self.errors.fetch_add(1, Ordering::SeqCst);
+            e.into()
+        })
     }
 
     pub async fn ping(&self) -> Result<()> {
         debug!("retrieving a connection...");
 
         let pool = self.pool.clone();
-        let mut handle = pool.get_handle().await?;
+        let _in_flight = InFlight::new(&self.in_flight);
+        let mut handle = self.track(pool.get_handle().await)?;
+
+        self.calls.fetch_add(1, Ordering::SeqCst);
+
+        self.track(handle.ping().await)
+    }
+
+    /// Executes a single SQL statement that doesn't return any rows, like DDL.
+    pub async fn execute<Q: Into<String>>(&self, sql: Q) -> Result<()> {
+        let pool = self.pool.clone();
+        let _in_flight = InFlight::new(&self.in_flight);
+        let mut handle = self.track(pool.get_handle().await)?;
+
+        self.calls.fetch_add(1, Ordering::SeqCst);
+
+        self.track(handle.execute(sql.into()).await)
+    }
+
+    /// Inserts a [`Block`] of rows into the given `table`.
+    pub async fn insert<T: Into<String>>(&self, table: T, block: Block) -> Result<()> {
+        let pool = self.pool.clone();
+        let _in_flight = InFlight::new(&self.in_flight);
+        let mut handle = self.track(pool.get_handle().await)?;
 
-        self.calls
-            .try_lock()
-            .unwrap()
-            .fetch_add(1, Ordering::SeqCst);
+        self.calls.fetch_add(1, Ordering::SeqCst);
 
-        handle.ping().await?;
-        Ok(())
+        self.track(handle.insert(table.into(), block).await)
     }
+
+    /// Runs a `SELECT` query and returns every row that was fetched.
+    pub async fn query<Q: Into<String>>(&self, sql: Q) -> Result<Block<Complex>> {
+        let pool = self.pool.clone();
+        let _in_flight = InFlight::new(&self.in_flight);
+        let mut handle = self.track(pool.get_handle().await)?;
+
+        self.calls.fetch_add(1, Ordering::SeqCst);
+
+        self.track(handle.query(sql.into()).fetch_all().await)
+    }
+}
+
+/// Quotes a value as a ClickHouse string literal, so values that weren't validated can be
+/// inlined into a query.
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
 }
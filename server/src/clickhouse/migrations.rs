@@ -0,0 +1,266 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashSet;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use chrono_tz::Tz;
+use clickhouse_rs::types::Block;
+
+use crate::clickhouse::client::ClickHouse;
+
+/// Represents a versioned set of DDL statements that is embedded in the binary.
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    /// The version of this migration, which is the timestamp prefix of the
+    /// migration's directory. Migrations are applied in ascending order.
+    pub version: &'static str,
+
+    /// Name of the migration, which is the rest of the directory name.
+    pub name: &'static str,
+
+    /// The SQL itself, which can contain more than one statement.
+    pub sql: &'static str,
+}
+
+macro_rules! migration {
+    ($version:literal, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            sql: include_str!(concat!(
+                "../../clickhouse/migrations/",
+                $version,
+                "_",
+                $name,
+                "/migration.sql"
+            )),
+        }
+    };
+}
+
+/// All the migrations that ship with the server, in the order they should be applied.
+pub const MIGRATIONS: &[Migration] = &[
+    migration!("20230601000000", "instance_stats"),
+    migration!("20230605000000", "events"),
+    migration!("20230620000000", "instance_health"),
+    migration!("20230628000000", "instance_metrics"),
+    migration!("20230701000000", "remote_write_samples"),
+    migration!("20230705000000", "otlp_metrics"),
+    migration!("20230705000001", "otlp_logs"),
+    migration!("20230710000000", "logs"),
+];
+
+/// Name of the table that keeps track of which migrations were applied.
+pub const MIGRATIONS_TABLE: &str = "schema_migrations";
+
+/// Applies the embedded [`MIGRATIONS`] to the configured ClickHouse database.
+#[derive(Debug, Clone)]
+pub struct Migrator {
+    clickhouse: ClickHouse,
+    database: String,
+}
+
+impl Migrator {
+    pub fn new<S: Into<String>>(clickhouse: ClickHouse, database: S) -> Migrator {
+        Migrator {
+            clickhouse,
+            database: database.into(),
+        }
+    }
+
+    /// Returns the migrations that haven't been applied yet, creating the
+    /// bookkeeping table if it doesn't exist.
+    pub async fn pending(&self) -> Result<Vec<Migration>> {
+        self.clickhouse
+            .execute(format!(
+                "CREATE TABLE IF NOT EXISTS {}.{MIGRATIONS_TABLE} (version String, name String, applied_at DateTime('UTC')) ENGINE = ReplacingMergeTree() ORDER BY version",
+                self.database
+            ))
+            .await?;
+
+        let block = self
+            .clickhouse
+            .query(format!(
+                "SELECT version FROM {}.{MIGRATIONS_TABLE} FINAL",
+                self.database
+            ))
+            .await?;
+
+        let mut applied = HashSet::new();
+        for row in block.rows() {
+            let version: String = row.get("version")?;
+            applied.insert(version);
+        }
+
+        Ok(MIGRATIONS
+            .iter()
+            .filter(|m| !applied.contains(m.version))
+            .copied()
+            .collect())
+    }
+
+    /// Applies every pending migration. If `dry_run` is true, the pending migrations are
+    /// only printed and nothing is executed. Returns the migrations that were (or would be)
+    /// applied.
+    pub async fn run(&self, dry_run: bool) -> Result<Vec<Migration>> {
+        let pending = self.pending().await?;
+        if pending.is_empty() {
+            info!("clickhouse schema is up to date!");
+            return Ok(pending);
+        }
+
+        for migration in pending.iter() {
+            if dry_run {
+                info!(
+                    "[dry run] pending migration {}_{}:\n{}",
+                    migration.version,
+                    migration.name,
+                    migration.sql.trim()
+                );
+
+                continue;
+            }
+
+            info!(
+                "applying clickhouse migration {}_{}",
+                migration.version, migration.name
+            );
+
+            for statement in split_statements(migration.sql) {
+                self.clickhouse.execute(statement).await.with_context(|| {
+                    format!(
+                        "unable to apply migration {}_{}",
+                        migration.version, migration.name
+                    )
+                })?;
+            }
+
+            let block = Block::new()
+                .column("version", vec![migration.version.to_string()])
+                .column("name", vec![migration.name.to_string()])
+                .column("applied_at", vec![Utc::now().with_timezone(&Tz::UTC)]);
+
+            self.clickhouse
+                .insert(format!("{}.{MIGRATIONS_TABLE}", self.database), block)
+                .await?;
+        }
+
+        if dry_run {
+            warn!(
+                "{} clickhouse migration(s) are pending, but weren't applied since dry run mode is enabled",
+                pending.len()
+            );
+        }
+
+        Ok(pending)
+    }
+}
+
+/// Splits a migration file into single statements, since ClickHouse can only run
+/// one statement per query. Comments are stripped and semicolons inside string
+/// literals are left alone.
+pub fn split_statements(sql: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut in_string = false;
+    let mut chars = sql.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_string = !in_string;
+                current.push(c);
+            }
+
+            '\\' if in_string => {
+                current.push(c);
+                if let Some(escaped) = chars.next() {
+                    current.push(escaped);
+                }
+            }
+
+            '-' if !in_string && chars.peek() == Some(&'-') => {
+                // skip until the end of the line
+                for next in chars.by_ref() {
+                    if next == '\n' {
+                        current.push('\n');
+                        break;
+                    }
+                }
+            }
+
+            ';' if !in_string => {
+                let statement = current.trim();
+                if !statement.is_empty() {
+                    statements.push(statement.to_string());
+                }
+
+                current.clear();
+            }
+
+            _ => current.push(c),
+        }
+    }
+
+    let statement = current.trim();
+    if !statement.is_empty() {
+        statements.push(statement.to_string());
+    }
+
+    statements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrations_are_ordered() {
+        let versions = MIGRATIONS.iter().map(|m| m.version).collect::<Vec<_>>();
+        let mut sorted = versions.clone();
+        sorted.sort();
+        sorted.dedup();
+
+        assert_eq!(versions, sorted);
+    }
+
+    #[test]
+    fn split_statements_strips_comments() {
+        let statements = split_statements(
+            "-- CreateTable\nCREATE TABLE a (x String) ENGINE = Memory;\n\n-- CreateTable\nCREATE TABLE b (y String) ENGINE = Memory;\n",
+        );
+
+        assert_eq!(
+            statements,
+            vec![
+                "CREATE TABLE a (x String) ENGINE = Memory",
+                "CREATE TABLE b (y String) ENGINE = Memory"
+            ]
+        );
+    }
+
+    #[test]
+    fn split_statements_keeps_semicolons_in_strings() {
+        let statements =
+            split_statements("ALTER TABLE a COMMENT COLUMN x 'a;b -- c\\'d'; SELECT 1");
+
+        assert_eq!(
+            statements,
+            vec!["ALTER TABLE a COMMENT COLUMN x 'a;b -- c\\'d'", "SELECT 1"]
+        );
+    }
+}
@@ -0,0 +1,316 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use analytics_protobufs::{metric::Value as ProtoMetricValue, Metrics};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use clickhouse_rs::types::Block;
+use serde::{Deserialize, Serialize};
+
+/// The ClickHouse table that every [`MetricSample`] is inserted into.
+pub const METRICS_TABLE: &str = "instance_metrics";
+
+/// The type of a metric, which decides which columns of a [`MetricSample`] are filled in.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+    Summary,
+}
+
+impl MetricKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+            MetricKind::Histogram => "histogram",
+            MetricKind::Summary => "summary",
+        }
+    }
+}
+
+impl Display for MetricKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for MetricKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "counter" => Ok(MetricKind::Counter),
+            "gauge" => Ok(MetricKind::Gauge),
+            "histogram" => Ok(MetricKind::Histogram),
+            "summary" => Ok(MetricKind::Summary),
+            _ => Err(format!("unknown metric type: {s}")),
+        }
+    }
+}
+
+/// Represents a single typed metric of a stats snapshot, flattened so it can be stored
+/// as a row in ClickHouse.
+///
+/// Counters and gauges only have a `value`, while histograms and summaries have their
+/// `count`, `sum` and buckets or quantiles filled in.
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricSample {
+    pub instance_uuid: String,
+    pub name: String,
+
+    #[serde(rename = "type")]
+    pub kind: MetricKind,
+
+    /// labels of this sample, sorted by their name.
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+    pub count: u64,
+    pub sum: f64,
+    pub bucket_bounds: Vec<f64>,
+    pub bucket_counts: Vec<u64>,
+    pub quantiles: Vec<f64>,
+    pub quantile_values: Vec<f64>,
+    pub snapshot_date: DateTime<Utc>,
+}
+
+impl MetricSample {
+    /// Flattens the typed metrics of a snapshot into samples. Metrics without a name or
+    /// a value are skipped, since there is nothing to chart them by.
+    pub fn from_metrics<S: Into<String>>(
+        instance_uuid: S,
+        metrics: Metrics,
+        snapshot_date: DateTime<Utc>,
+    ) -> Vec<MetricSample> {
+        let instance_uuid = instance_uuid.into();
+        metrics
+            .metrics
+            .into_iter()
+            .filter(|metric| !metric.name.is_empty())
+            .filter_map(|metric| {
+                let mut labels = metric.labels.into_iter().collect::<Vec<_>>();
+                labels.sort();
+
+                let mut sample = MetricSample {
+                    instance_uuid: instance_uuid.clone(),
+                    name: metric.name,
+                    kind: MetricKind::Gauge,
+                    labels,
+                    value: 0.0,
+                    count: 0,
+                    sum: 0.0,
+                    bucket_bounds: vec![],
+                    bucket_counts: vec![],
+                    quantiles: vec![],
+                    quantile_values: vec![],
+                    snapshot_date,
+                };
+
+                match metric.value? {
+                    ProtoMetricValue::Counter(counter) => {
+                        sample.kind = MetricKind::Counter;
+                        sample.value = counter.value;
+                    }
+                    ProtoMetricValue::Gauge(gauge) => {
+                        sample.value = gauge.value;
+                    }
+                    ProtoMetricValue::Histogram(mut histogram) => {
+                        histogram
+                            .buckets
+                            .sort_by(|a, b| a.upper_bound.total_cmp(&b.upper_bound));
+
+                        sample.kind = MetricKind::Histogram;
+                        sample.count = histogram.count;
+                        sample.sum = histogram.sum;
+                        (sample.bucket_bounds, sample.bucket_counts) = histogram
+                            .buckets
+                            .into_iter()
+                            .map(|b| (b.upper_bound, b.count))
+                            .unzip();
+                    }
+                    ProtoMetricValue::Summary(mut summary) => {
+                        summary
+                            .quantiles
+                            .sort_by(|a, b| a.quantile.total_cmp(&b.quantile));
+
+                        sample.kind = MetricKind::Summary;
+                        sample.count = summary.count;
+                        sample.sum = summary.sum;
+                        (sample.quantiles, sample.quantile_values) = summary
+                            .quantiles
+                            .into_iter()
+                            .map(|q| (q.quantile, q.value))
+                            .unzip();
+                    }
+                }
+
+                Some(sample)
+            })
+            .collect()
+    }
+}
+
+/// Builds a ClickHouse [`Block`] out of the given samples, with the columns
+/// laid out the same way as the [`METRICS_TABLE`] table.
+pub fn to_block(samples: &[MetricSample]) -> Block {
+    Block::new()
+        .column(
+            "instance_uuid",
+            samples
+                .iter()
+                .map(|s| s.instance_uuid.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "name",
+            samples.iter().map(|s| s.name.clone()).collect::<Vec<_>>(),
+        )
+        .column(
+            "type",
+            samples
+                .iter()
+                .map(|s| s.kind.to_string())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "label_names",
+            samples
+                .iter()
+                .map(|s| s.labels.iter().map(|(k, _)| k.clone()).collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "label_values",
+            samples
+                .iter()
+                .map(|s| s.labels.iter().map(|(_, v)| v.clone()).collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+        )
+        .column("value", samples.iter().map(|s| s.value).collect::<Vec<_>>())
+        .column("count", samples.iter().map(|s| s.count).collect::<Vec<_>>())
+        .column("sum", samples.iter().map(|s| s.sum).collect::<Vec<_>>())
+        .column(
+            "bucket_bounds",
+            samples
+                .iter()
+                .map(|s| s.bucket_bounds.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "bucket_counts",
+            samples
+                .iter()
+                .map(|s| s.bucket_counts.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "quantiles",
+            samples
+                .iter()
+                .map(|s| s.quantiles.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "quantile_values",
+            samples
+                .iter()
+                .map(|s| s.quantile_values.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "snapshot_date",
+            samples
+                .iter()
+                .map(|s| s.snapshot_date.with_timezone(&Tz::UTC))
+                .collect::<Vec<_>>(),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analytics_protobufs::{Counter, Histogram, HistogramBucket, Metric};
+    use std::collections::HashMap;
+
+    fn metric(name: &str, labels: &[(&str, &str)], value: ProtoMetricValue) -> Metric {
+        Metric {
+            name: name.into(),
+            help: String::new(),
+            labels: labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>(),
+            value: Some(value),
+        }
+    }
+
+    #[test]
+    fn flattens_typed_metrics() {
+        let metrics = Metrics {
+            metrics: vec![
+                metric(
+                    "http_requests_total",
+                    &[("status", "200"), ("method", "GET")],
+                    ProtoMetricValue::Counter(Counter { value: 42.0 }),
+                ),
+                metric(
+                    "http_request_duration_seconds",
+                    &[],
+                    ProtoMetricValue::Histogram(Histogram {
+                        buckets: vec![
+                            HistogramBucket {
+                                upper_bound: f64::INFINITY,
+                                count: 10,
+                            },
+                            HistogramBucket {
+                                upper_bound: 0.5,
+                                count: 8,
+                            },
+                        ],
+                        count: 10,
+                        sum: 3.2,
+                    }),
+                ),
+                Metric {
+                    name: "no_value".into(),
+                    ..Default::default()
+                },
+            ],
+        };
+
+        let samples = MetricSample::from_metrics("uuid", metrics, Utc::now());
+        assert_eq!(samples.len(), 2);
+
+        assert_eq!(samples[0].kind, MetricKind::Counter);
+        assert_eq!(samples[0].value, 42.0);
+        assert_eq!(
+            samples[0].labels,
+            vec![
+                ("method".to_string(), "GET".to_string()),
+                ("status".to_string(), "200".to_string())
+            ]
+        );
+
+        assert_eq!(samples[1].kind, MetricKind::Histogram);
+        assert_eq!(samples[1].bucket_bounds, vec![0.5, f64::INFINITY]);
+        assert_eq!(samples[1].bucket_counts, vec![8, 10]);
+        assert_eq!(samples[1].count, 10);
+    }
+}
@@ -0,0 +1,380 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use futures::future::join_all;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, timeout, MissedTickBehavior};
+use tonic::Code;
+
+use crate::collector::snapshot::StatsSnapshot;
+use crate::config::CollectorConfig;
+use crate::endpoints::capabilities::Feature;
+use crate::endpoints::endpoint::Endpoint;
+use crate::endpoints::endpoint_manager::EndpointManager;
+use crate::prometheus::registry::ServerMetrics;
+use crate::sinks::sink::{Batch, Sinks};
+
+/// How long to wait before trying to subscribe to an instance again after it said
+/// that it doesn't support `StreamStats`, in case it was upgraded since.
+const STREAM_RETRY_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// How many streamed snapshots are kept until the next collection before the oldest
+/// ones are dropped, so a chatty instance can't grow the buffer forever.
+const MAX_STREAMED_SNAPSHOTS: usize = 10_000;
+
+#[derive(Debug)]
+enum Subscription {
+    /// A task is reading the instance's `StreamStats` stream.
+    Active(JoinHandle<()>),
+
+    /// The instance returned `UNIMPLEMENTED`, so it is polled instead.
+    Unsupported(Instant),
+}
+
+/// What [`StatsCollector::subscribe`] does with an instance that might support streaming.
+#[derive(Debug, PartialEq, Eq)]
+enum NextStep {
+    /// Its stream is still being read.
+    Stream,
+
+    /// It is polled, since it said that it doesn't support streaming a short while ago.
+    Poll,
+
+    /// It is subscribed to, since it wasn't yet, its stream was dropped, or it said that
+    /// it doesn't support streaming more than [`STREAM_RETRY_AFTER`] ago.
+    Subscribe,
+}
+
+fn next_step(subscription: Option<&Subscription>, now: Instant) -> NextStep {
+    match subscription {
+        Some(Subscription::Active(handle)) if !handle.is_finished() => NextStep::Stream,
+        Some(Subscription::Unsupported(since))
+            if now.saturating_duration_since(*since) < STREAM_RETRY_AFTER =>
+        {
+            NextStep::Poll
+        }
+        _ => NextStep::Subscribe,
+    }
+}
+
+/// Checks if subscribing failed because the instance doesn't implement `StreamStats`.
+fn is_unimplemented(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<tonic::Status>()
+        .map(|status| status.code() == Code::Unimplemented)
+        .unwrap_or(false)
+}
+
+/// Keeps a streamed snapshot until the next collection, dropping the oldest one if
+/// [`MAX_STREAMED_SNAPSHOTS`] are kept already.
+fn push_streamed(streamed: &mut VecDeque<StatsSnapshot>, snapshot: StatsSnapshot) {
+    if streamed.len() >= MAX_STREAMED_SNAPSHOTS {
+        streamed.pop_front();
+    }
+
+    streamed.push_back(snapshot);
+}
+
+/// Background job that walks every registered endpoint on an interval and writes
+/// their stats to the configured sinks. Instances that advertise the `streaming` feature are
+/// subscribed to once and push their snapshots, every other instance is polled with
+/// `RetrieveStats`.
+#[derive(Debug, Clone)]
+pub struct StatsCollector {
+    sinks: Sinks,
+    endpoints: Arc<Mutex<EndpointManager>>,
+    interval: Duration,
+    timeout: Duration,
+    streaming: bool,
+    subscriptions: Arc<Mutex<HashMap<String, Subscription>>>,
+    streamed: Arc<Mutex<VecDeque<StatsSnapshot>>>,
+}
+
+impl StatsCollector {
+    pub fn new(
+        sinks: Sinks,
+        endpoints: Arc<Mutex<EndpointManager>>,
+        config: CollectorConfig,
+    ) -> StatsCollector {
+        let defaults = CollectorConfig::default();
+
+        StatsCollector {
+            sinks,
+            endpoints,
+            interval: Duration::from_secs(config.interval.or(defaults.interval).unwrap().max(1)),
+            timeout: Duration::from_secs(config.timeout.or(defaults.timeout).unwrap().max(1)),
+            streaming: config.streaming.or(defaults.streaming).unwrap(),
+            subscriptions: Arc::new(Mutex::new(HashMap::new())),
+            streamed: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Spawns the collector in the background. The first collection happens right away.
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            info!(
+                "collecting stats from all instances every {:?}",
+                self.interval
+            );
+
+            let mut ticker = interval(self.interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                ticker.tick().await;
+                match self.collect().await {
+                    Ok(count) => debug!("collected {count} stats snapshots"),
+                    Err(e) => error!("unable to collect stats: {e}"),
+                }
+            }
+        })
+    }
+
+    /// Polls every finalized endpoint that isn't streaming its stats once, and inserts
+    /// the snapshots that were received since the last collection, returning how many
+    /// were stored.
+    pub async fn collect(&self) -> Result<usize> {
+        let endpoints = {
+            let mut manager = self.endpoints.lock().await;
+            let mut endpoints = manager.get_endpoints().await?;
+            endpoints.retain(|e| e.api_token.is_some());
+            for endpoint in endpoints.iter_mut() {
+                endpoint.keys = manager.get_keys(endpoint.instance_name.clone()).await.ok();
+            }
+
+            endpoints
+        };
+
+        self.prune_subscriptions(&endpoints).await;
+
+        let streaming = join_all(endpoints.iter().map(|e| self.subscribe(e))).await;
+        let polls = endpoints
+            .iter()
+            .zip(streaming)
+            .filter(|(_, streaming)| !streaming)
+            .map(|(e, _)| self.poll(e));
+
+        let mut snapshots = join_all(polls)
+            .await
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        snapshots.extend(self.streamed.lock().await.drain(..));
+        if snapshots.is_empty() {
+            return Ok(0);
+        }
+
+        self.sinks.write(Batch::Stats(&snapshots)).await?;
+
+        Ok(snapshots.len())
+    }
+
+    /// Makes sure that the endpoint is subscribed to if it supports `StreamStats`,
+    /// returning `false` if it needs to be polled instead.
+    async fn subscribe(&self, endpoint: &Endpoint) -> bool {
+        if !self.streaming {
+            return false;
+        }
+
+        // instances that negotiated their capabilities tell us if they can stream, the
+        // rest are asked and fall back to polling if they say that they can't
+        if let Some(capabilities) = &endpoint.capabilities {
+            if !capabilities.supports(Feature::Streaming) {
+                return false;
+            }
+        }
+
+        let name = endpoint.instance_name.clone();
+        match next_step(self.subscriptions.lock().await.get(&name), Instant::now()) {
+            NextStep::Stream => return true,
+            NextStep::Poll => return false,
+            NextStep::Subscribe => {}
+        }
+
+        let mut stream = match timeout(self.timeout, endpoint.stream_stats()).await {
+            Ok(Ok(stream)) => stream,
+            Ok(Err(e)) => {
+                if is_unimplemented(&e) {
+                    debug!("instance {name} doesn't support streaming stats, polling it instead");
+                    self.subscriptions
+                        .lock()
+                        .await
+                        .insert(name, Subscription::Unsupported(Instant::now()));
+                } else {
+                    warn!("unable to subscribe to stats of instance {name}: {e}");
+                    self.subscriptions.lock().await.remove(&name);
+                }
+
+                return false;
+            }
+            Err(_) => return false,
+        };
+
+        info!("subscribed to stats of instance {name}");
+
+        let streamed = self.streamed.clone();
+        let instance = name.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                match stream.message().await {
+                    Ok(Some(res)) => push_streamed(
+                        &mut *streamed.lock().await,
+                        StatsSnapshot::from_response(instance.clone(), res),
+                    ),
+                    Ok(None) => {
+                        debug!("instance {instance} closed its stats stream");
+                        break;
+                    }
+                    Err(e) => {
+                        warn!("stats stream of instance {instance} failed: {e}");
+                        break;
+                    }
+                }
+            }
+        });
+
+        self.subscriptions
+            .lock()
+            .await
+            .insert(name, Subscription::Active(handle));
+
+        true
+    }
+
+    /// Stops reading the streams of instances that were deregistered.
+    async fn prune_subscriptions(&self, endpoints: &[Endpoint]) {
+        let names = endpoints
+            .iter()
+            .map(|e| e.instance_name.as_str())
+            .collect::<HashSet<_>>();
+
+        self.subscriptions
+            .lock()
+            .await
+            .retain(|name, subscription| {
+                let keep = names.contains(name.as_str());
+                if let (false, Subscription::Active(handle)) = (keep, subscription) {
+                    handle.abort();
+                }
+
+                keep
+            });
+    }
+
+    async fn poll(&self, endpoint: &Endpoint) -> Option<StatsSnapshot> {
+        let result = timeout(self.timeout, endpoint.retrieve_stats()).await;
+        ServerMetrics::global().record_poll(matches!(result, Ok(Ok(_))));
+
+        match result {
+            Ok(Ok(res)) => Some(StatsSnapshot::from_response(
+                endpoint.instance_name.clone(),
+                res,
+            )),
+            Ok(Err(e)) => {
+                warn!(
+                    "unable to retrieve stats from instance {} ({}): {e}",
+                    endpoint.instance_name, endpoint.addr
+                );
+
+                None
+            }
+            Err(_) => {
+                warn!(
+                    "instance {} ({}) didn't respond within {:?}",
+                    endpoint.instance_name, endpoint.addr, self.timeout
+                );
+
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analytics_protobufs::ReceiveStatsResponse;
+    use anyhow::anyhow;
+
+    #[test]
+    fn polls_instances_without_streaming_until_the_retry_window_elapsed() {
+        let since = Instant::now();
+        let unsupported = Subscription::Unsupported(since);
+
+        assert_eq!(next_step(None, since), NextStep::Subscribe);
+        assert_eq!(next_step(Some(&unsupported), since), NextStep::Poll);
+        assert_eq!(
+            next_step(Some(&unsupported), since + STREAM_RETRY_AFTER / 2),
+            NextStep::Poll
+        );
+        assert_eq!(
+            next_step(Some(&unsupported), since + STREAM_RETRY_AFTER),
+            NextStep::Subscribe
+        );
+
+        assert!(is_unimplemented(
+            &tonic::Status::unimplemented("nope").into()
+        ));
+        assert!(!is_unimplemented(
+            &tonic::Status::unavailable("down").into()
+        ));
+        assert!(!is_unimplemented(&anyhow!("connection refused")));
+    }
+
+    #[tokio::test]
+    async fn resubscribes_once_the_stream_was_dropped() {
+        let active = Subscription::Active(tokio::spawn(std::future::pending::<()>()));
+        assert_eq!(next_step(Some(&active), Instant::now()), NextStep::Stream);
+
+        let handle = tokio::spawn(async {});
+        while !handle.is_finished() {
+            tokio::task::yield_now().await;
+        }
+
+        let dropped = Subscription::Active(handle);
+        assert_eq!(
+            next_step(Some(&dropped), Instant::now()),
+            NextStep::Subscribe
+        );
+
+        if let Subscription::Active(handle) = active {
+            handle.abort();
+        }
+    }
+
+    #[test]
+    fn drops_the_oldest_streamed_snapshots() {
+        let mut streamed = VecDeque::new();
+        for i in 0..=MAX_STREAMED_SNAPSHOTS {
+            push_streamed(
+                &mut streamed,
+                StatsSnapshot::from_response(i.to_string(), ReceiveStatsResponse::default()),
+            );
+        }
+
+        assert_eq!(streamed.len(), MAX_STREAMED_SNAPSHOTS);
+        assert_eq!(streamed.front().unwrap().instance_uuid, "1");
+        assert_eq!(
+            streamed.back().unwrap().instance_uuid,
+            MAX_STREAMED_SNAPSHOTS.to_string()
+        );
+    }
+}
@@ -0,0 +1,271 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use analytics_protobufs::{receive_stats_response::Payload, BuildFlavour, ReceiveStatsResponse};
+use anyhow::Result;
+use chrono::{DateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use clickhouse_rs::types::Block;
+use prost_types::{value::Kind, Struct, Timestamp, Value as ProtoValue};
+use serde::Serialize;
+use serde_json::{Map, Number, Value};
+
+use crate::clickhouse::client::ClickHouse;
+use crate::collector::metrics::{self, MetricSample, METRICS_TABLE};
+
+/// The ClickHouse table that every [`StatsSnapshot`] is inserted into.
+pub const STATS_TABLE: &str = "instance_stats";
+
+/// Represents a single `RetrieveStats` result from an instance, flattened so it can be
+/// stored as a row in ClickHouse.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsSnapshot {
+    pub instance_uuid: String,
+    pub product: String,
+    pub version: String,
+    pub commit_sha: Option<String>,
+    pub build_date: Option<String>,
+    pub snapshot_date: DateTime<Utc>,
+    pub build_flavour: String,
+    pub data: Value,
+    pub collected_at: DateTime<Utc>,
+
+    /// typed metrics of the snapshot, which are stored in the [`METRICS_TABLE`] table
+    /// instead of `data`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub metrics: Vec<MetricSample>,
+}
+
+impl StatsSnapshot {
+    pub fn from_response<S: Into<String>>(instance_uuid: S, res: ReceiveStatsResponse) -> Self {
+        let collected_at = Utc::now();
+        let build_flavour = BuildFlavour::from_i32(res.build_flavour)
+            .unwrap_or(BuildFlavour::Docker)
+            .as_str_name()
+            .to_string();
+
+        let instance_uuid = instance_uuid.into();
+        let snapshot_date = res
+            .snapshot_date
+            .and_then(timestamp_to_datetime)
+            .unwrap_or(collected_at);
+
+        let (data, metrics) = match res.payload {
+            Some(Payload::Data(data)) => (struct_to_json(data), vec![]),
+            Some(Payload::Metrics(metrics)) => (
+                Value::Null,
+                MetricSample::from_metrics(instance_uuid.clone(), metrics, snapshot_date),
+            ),
+            None => (Value::Null, vec![]),
+        };
+
+        StatsSnapshot {
+            instance_uuid,
+            product: res.product,
+            version: res.version,
+            commit_sha: res.commit_sha,
+            build_date: res.build_date,
+            snapshot_date,
+            build_flavour,
+            data,
+            collected_at,
+            metrics,
+        }
+    }
+}
+
+/// Inserts the given snapshots into the [`STATS_TABLE`] table, and their typed metrics
+/// into the [`METRICS_TABLE`] table.
+pub async fn store(clickhouse: &ClickHouse, snapshots: &[StatsSnapshot]) -> Result<()> {
+    clickhouse.insert(STATS_TABLE, to_block(snapshots)).await?;
+
+    let samples = snapshots
+        .iter()
+        .flat_map(|s| s.metrics.iter().cloned())
+        .collect::<Vec<_>>();
+
+    if !samples.is_empty() {
+        clickhouse
+            .insert(METRICS_TABLE, metrics::to_block(&samples))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Builds a ClickHouse [`Block`] out of the given snapshots, with the columns
+/// laid out the same way as the [`STATS_TABLE`] table.
+pub fn to_block(snapshots: &[StatsSnapshot]) -> Block {
+    let tz = |d: &DateTime<Utc>| d.with_timezone(&Tz::UTC);
+
+    Block::new()
+        .column(
+            "instance_uuid",
+            snapshots
+                .iter()
+                .map(|s| s.instance_uuid.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "product",
+            snapshots
+                .iter()
+                .map(|s| s.product.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "version",
+            snapshots
+                .iter()
+                .map(|s| s.version.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "commit_sha",
+            snapshots
+                .iter()
+                .map(|s| s.commit_sha.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "build_date",
+            snapshots
+                .iter()
+                .map(|s| s.build_date.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "snapshot_date",
+            snapshots
+                .iter()
+                .map(|s| tz(&s.snapshot_date))
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "build_flavour",
+            snapshots
+                .iter()
+                .map(|s| s.build_flavour.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "data",
+            snapshots
+                .iter()
+                .map(|s| s.data.to_string())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "collected_at",
+            snapshots
+                .iter()
+                .map(|s| tz(&s.collected_at))
+                .collect::<Vec<_>>(),
+        )
+}
+
+/// Converts a protobuf [`Timestamp`] into a UTC [`DateTime`], if it is in range.
+pub fn timestamp_to_datetime(ts: Timestamp) -> Option<DateTime<Utc>> {
+    Utc.timestamp_opt(ts.seconds, ts.nanos.max(0) as u32)
+        .single()
+}
+
+/// Converts a `google.protobuf.Struct` into a JSON object.
+pub fn struct_to_json(s: Struct) -> Value {
+    let mut map = Map::new();
+    for (key, value) in s.fields {
+        map.insert(key, proto_value_to_json(value));
+    }
+
+    Value::Object(map)
+}
+
+fn proto_value_to_json(value: ProtoValue) -> Value {
+    match value.kind {
+        None | Some(Kind::NullValue(_)) => Value::Null,
+        Some(Kind::BoolValue(b)) => Value::Bool(b),
+        Some(Kind::StringValue(s)) => Value::String(s),
+        Some(Kind::NumberValue(n)) => Number::from_f64(n)
+            .map(Value::Number)
+            .unwrap_or(Value::Null),
+        Some(Kind::StructValue(s)) => struct_to_json(s),
+        Some(Kind::ListValue(list)) => {
+            Value::Array(list.values.into_iter().map(proto_value_to_json).collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost_types::ListValue;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    fn value(kind: Kind) -> ProtoValue {
+        ProtoValue { kind: Some(kind) }
+    }
+
+    #[test]
+    fn converts_nested_structs_to_json() {
+        let mut inner = BTreeMap::new();
+        inner.insert("used".to_string(), value(Kind::NumberValue(512.0)));
+
+        let mut fields = BTreeMap::new();
+        fields.insert(
+            "heap".to_string(),
+            value(Kind::StructValue(Struct { fields: inner })),
+        );
+        fields.insert("healthy".to_string(), value(Kind::BoolValue(true)));
+        fields.insert("nothing".to_string(), value(Kind::NullValue(0)));
+        fields.insert(
+            "tags".to_string(),
+            value(Kind::ListValue(ListValue {
+                values: vec![value(Kind::StringValue("a".into()))],
+            })),
+        );
+
+        assert_eq!(
+            struct_to_json(Struct { fields }),
+            json!({
+                "heap": { "used": 512.0 },
+                "healthy": true,
+                "nothing": null,
+                "tags": ["a"]
+            })
+        );
+    }
+
+    #[test]
+    fn snapshot_falls_back_to_collection_date() {
+        let snapshot = StatsSnapshot::from_response(
+            "waff",
+            ReceiveStatsResponse {
+                product: "charted-server".into(),
+                version: "0.1.0".into(),
+                commit_sha: None,
+                build_date: None,
+                snapshot_date: None,
+                build_flavour: BuildFlavour::Git as i32,
+                payload: None,
+            },
+        );
+
+        assert_eq!(snapshot.snapshot_date, snapshot.collected_at);
+        assert_eq!(snapshot.build_flavour, "GIT");
+        assert_eq!(snapshot.data, Value::Null);
+        assert!(snapshot.metrics.is_empty());
+    }
+}
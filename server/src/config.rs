@@ -17,22 +17,45 @@ use anyhow::Result;
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 use std::env::var;
-use std::fmt::Write as _;
+use std::fmt::{Display, Write as _};
 use std::fs::File;
 use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
 
 pub static CONFIG: OnceCell<Config> = OnceCell::new();
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
-    // The secret key  to use when requests happen
+    // The secret key that acts as the root credential, used to bootstrap users and their API tokens
     pub secret_key: Option<String>,
     /// The DSN to connect to Sentry for error handling.
     pub sentry_dsn: Option<String>,
 
+    /// Configuration for the alert rules that are evaluated against collected stats.
+    pub alerts: Option<AlertsConfig>,
+
     /// Configuration for ClickHouse, which is used to enable the Events API.
     pub clickhouse: Option<ClickHouseConfig>,
 
+    /// Configuration for the stats collector, which polls every registered instance.
+    pub collector: Option<CollectorConfig>,
+
+    /// Configuration for the Events API.
+    pub events: Option<EventsConfig>,
+
+    /// Configuration for the gRPC channels that are kept open to instances.
+    pub grpc: Option<GrpcConfig>,
+
+    /// Configuration for how the server connects to the gRPC endpoints of instances.
+    pub grpc_tls: Option<GrpcTlsConfig>,
+
+    /// Configuration for the health checks that are run against every registered instance.
+    pub health: Option<HealthConfig>,
+
+    /// Configuration for how registered instances are managed.
+    pub instances: Option<InstancesConfig>,
+
     /// If the web UI should be enabled when running the server. If this is disabled,
     /// all rest APIs will land on `/` instead of `/api`.
     pub frontend: Option<bool>,
@@ -43,6 +66,9 @@ pub struct Config {
     /// Configuration for the server itself.
     pub server: Option<ServerConfig>,
 
+    /// Configuration for where ingested data and collected stats are written to.
+    pub sinks: Option<SinksConfig>,
+
     /// Configuration for redis (REQUIRED).
     pub redis: RedisConfig,
 }
@@ -73,6 +99,136 @@ pub struct ClickHouseConfig {
     pub password: Option<String>,
     pub host: Option<String>,
     pub port: Option<u16>,
+    pub dry_run_migrations: Option<bool>, // defaults to "false"
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CollectorConfig {
+    /// If the collector should poll instances at all. Default is `true`.
+    pub enabled: Option<bool>,
+
+    /// How often (in seconds) every instance should be polled. Default is `60`.
+    pub interval: Option<u64>,
+
+    /// How long (in seconds) to wait on a single instance before giving up. Default is `10`.
+    pub timeout: Option<u64>,
+
+    /// If instances that support the `StreamStats` RPC should push their stats instead of
+    /// being polled. Default is `true`.
+    pub streaming: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EventsConfig {
+    /// How many events to buffer before they are inserted into ClickHouse. Default is `500`.
+    pub batch_size: Option<usize>,
+
+    /// How often (in seconds) the buffer is flushed, even if it isn't full. Default is `5`.
+    pub flush_interval: Option<u64>,
+
+    /// The maximum size (in bytes) of a single `POST /api/events` body. Default is 5MiB.
+    pub max_body_size: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GrpcConfig {
+    /// How many channels can be open at once, the least recently used ones are closed first. Default is `512`.
+    pub max_channels: Option<usize>,
+
+    /// How long (in seconds) to wait for a connection to an instance. Default is `5`.
+    pub connect_timeout: Option<u64>,
+
+    /// How often (in seconds) HTTP/2 and TCP keepalives are sent on idle channels. Default is `30`.
+    pub keepalive_interval: Option<u64>,
+
+    /// How long (in seconds) to wait for a keepalive to be acknowledged. Default is `10`.
+    pub keepalive_timeout: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct GrpcTlsConfig {
+    /// The TLS mode for instances that didn't register with their own. Default is `plaintext`.
+    pub mode: Option<TlsMode>,
+
+    /// Path to a PEM encoded CA to verify instances with, if they didn't register with their own.
+    pub ca_cert: Option<String>,
+
+    /// Path to the PEM encoded client certificate that is presented to instances in the `mutual` mode.
+    pub client_cert: Option<String>,
+
+    /// Path to the PEM encoded private key of `client_cert`.
+    pub client_key: Option<String>,
+}
+
+/// How the server connects to the gRPC endpoint of an instance.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    /// No TLS at all, which is only fine on trusted networks.
+    #[default]
+    Plaintext,
+
+    /// TLS, where the instance's certificate is verified against the system's roots.
+    Tls,
+
+    /// TLS, where the instance's certificate is only verified against a pinned CA.
+    Pinned,
+
+    /// Same as [`TlsMode::Pinned`] (or [`TlsMode::Tls`] if no CA was configured), but the
+    /// server also presents the client certificate from `grpc_tls.client_cert`.
+    Mutual,
+}
+
+impl TlsMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TlsMode::Plaintext => "plaintext",
+            TlsMode::Tls => "tls",
+            TlsMode::Pinned => "pinned",
+            TlsMode::Mutual => "mutual",
+        }
+    }
+}
+
+impl Display for TlsMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TlsMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "plaintext" => Ok(TlsMode::Plaintext),
+            "tls" => Ok(TlsMode::Tls),
+            "pinned" => Ok(TlsMode::Pinned),
+            "mutual" => Ok(TlsMode::Mutual),
+            _ => Err(format!("unknown TLS mode `{s}`")),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HealthConfig {
+    /// If instances should be health checked at all. Default is `true`.
+    pub enabled: Option<bool>,
+
+    /// How often (in seconds) every instance should be checked. Default is `30`.
+    pub interval: Option<u64>,
+
+    /// How long (in seconds) to wait for `ConnectionAck` before an instance is down. Default is `5`.
+    pub timeout: Option<u64>,
+
+    /// How long (in milliseconds) `ConnectionAck` can take before an instance is degraded. Default is `1000`.
+    pub degraded_latency: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InstancesConfig {
+    /// How often (in seconds) the Redis `endpoints` hash is rebuilt from Postgres. Default is `60`.
+    pub reconcile_interval: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -85,6 +241,176 @@ pub struct ServerConfig {
 
     /// The host the server should bind to. Default is `0.0.0.0` or `::`.
     pub host: Option<String>,
+
+    /// The port that the gRPC ingest service, which instances can push their stats to, should
+    /// bind to on the same host. Default is `9293`.
+    pub grpc_port: Option<u16>,
+
+    /// The ID that this server mixes into the snowflakes it generates, between `0` and `1023`. It
+    /// has to be unique across every server that shares the same Postgres database. Default is `0`.
+    pub worker_id: Option<u16>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SinksConfig {
+    /// The sinks that data is written to. The first one is the primary sink, which can't be `webhook`,
+    /// the rest are written to on a best effort basis. Default is `[clickhouse]`.
+    pub outputs: Option<Vec<SinkKind>>,
+
+    /// Configuration for the `elasticsearch` sink.
+    pub elasticsearch: Option<ElasticsearchSinkConfig>,
+
+    /// Configuration for the `file` sink.
+    pub file: Option<FileSinkConfig>,
+
+    /// Configuration for the `webhook` sink.
+    pub webhook: Option<WebhookSinkConfig>,
+}
+
+/// Represents the kinds of sinks that can be configured in `sinks.outputs`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkKind {
+    ClickHouse,
+    Elasticsearch,
+    File,
+    Webhook,
+}
+
+impl SinkKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SinkKind::ClickHouse => "clickhouse",
+            SinkKind::Elasticsearch => "elasticsearch",
+            SinkKind::File => "file",
+            SinkKind::Webhook => "webhook",
+        }
+    }
+}
+
+impl Display for SinkKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "clickhouse" => Ok(SinkKind::ClickHouse),
+            "elasticsearch" => Ok(SinkKind::Elasticsearch),
+            "file" => Ok(SinkKind::File),
+            "webhook" => Ok(SinkKind::Webhook),
+            _ => Err(format!("unknown sink `{s}`")),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ElasticsearchSinkConfig {
+    /// The URL of the Elasticsearch cluster. Default is `http://localhost:9200`.
+    pub url: Option<String>,
+
+    /// What the name of every index starts with, followed by the kind of data. Default is `analytics-`.
+    pub index_prefix: Option<String>,
+
+    /// The username to authenticate with, if the cluster has security enabled.
+    pub username: Option<String>,
+
+    /// The password of `username`.
+    pub password: Option<String>,
+
+    /// How long (in seconds) to wait on a single bulk request. Default is `10`.
+    pub timeout: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileSinkConfig {
+    /// The directory that the files are written to. Default is `./data/sinks`.
+    pub directory: Option<String>,
+
+    /// How large (in bytes) a file can get before it is rotated. Default is 100MiB.
+    pub max_size: Option<u64>,
+
+    /// How many rotated files are kept for every kind of data. Default is `5`.
+    pub max_files: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookSinkConfig {
+    /// The webhooks that events and stats snapshots are sent to.
+    pub endpoints: Option<Vec<WebhookEndpointConfig>>,
+
+    /// How many times a failed delivery is retried before it is put in the dead-letter list. Default is `5`.
+    pub max_retries: Option<u32>,
+
+    /// How long (in milliseconds) to wait before the first retry, which doubles on every retry. Default is `500`.
+    pub retry_backoff: Option<u64>,
+
+    /// How long (in seconds) to wait on a single delivery. Default is `10`.
+    pub timeout: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookEndpointConfig {
+    /// The URL that batches are POSTed to.
+    pub url: String,
+
+    /// The secret that the `X-Analytics-Signature` header of every delivery is signed with.
+    pub secret: String,
+
+    /// Only events with these names are sent, or every event if this isn't set.
+    pub events: Option<Vec<String>>,
+
+    /// Only events and stats of these instances are sent, or every instance if this isn't set.
+    pub instances: Option<Vec<String>>,
+
+    /// If stats snapshots are sent as well as events. Default is `true`.
+    pub stats: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AlertsConfig {
+    /// If alert rules should be evaluated at all. Default is `true`.
+    pub enabled: Option<bool>,
+
+    /// How often (in seconds) every rule is evaluated. Default is `60`.
+    pub interval: Option<u64>,
+
+    /// How old (in seconds) the latest snapshot of an instance can be, its rules don't hold on older snapshots. Default is `600`.
+    pub max_snapshot_age: Option<u64>,
+
+    /// Where rules with the `webhook` channel are sent to.
+    pub webhook: Option<AlertWebhookConfig>,
+
+    /// Where rules with the `email` channel are sent to.
+    pub email: Option<AlertEmailConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AlertWebhookConfig {
+    /// The URL that notifications are POSTed to.
+    pub url: String,
+
+    /// The secret that the `X-Analytics-Signature` header of every notification is signed with.
+    pub secret: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AlertEmailConfig {
+    /// The host of the SMTP relay, which has to accept mail without TLS or authentication. Default is `localhost`.
+    pub host: Option<String>,
+
+    /// The port of the SMTP relay. Default is `25`.
+    pub port: Option<u16>,
+
+    /// The address that notifications are sent from. Default is `analytics@localhost`.
+    pub from: Option<String>,
+
+    /// The addresses that notifications are sent to.
+    pub to: Vec<String>,
 }
 
 impl Default for ClickHouseConfig {
@@ -98,6 +424,72 @@ impl Default for ClickHouseConfig {
             password: None,
             host: Some("127.0.0.1".into()),
             port: Some(9000),
+            dry_run_migrations: Some(false),
+        }
+    }
+}
+
+impl Default for CollectorConfig {
+    fn default() -> Self {
+        CollectorConfig {
+            enabled: Some(true),
+            interval: Some(60),
+            timeout: Some(10),
+            streaming: Some(true),
+        }
+    }
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        EventsConfig {
+            batch_size: Some(500),
+            flush_interval: Some(5),
+            max_body_size: Some(5 * 1024 * 1024),
+        }
+    }
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        GrpcConfig {
+            max_channels: Some(512),
+            connect_timeout: Some(5),
+            keepalive_interval: Some(30),
+            keepalive_timeout: Some(10),
+        }
+    }
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            enabled: Some(true),
+            interval: Some(30),
+            timeout: Some(5),
+            degraded_latency: Some(1000),
+        }
+    }
+}
+
+impl HealthConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval.unwrap_or(30).max(1))
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.unwrap_or(5).max(1))
+    }
+
+    pub fn degraded_latency(&self) -> Duration {
+        Duration::from_millis(self.degraded_latency.unwrap_or(1000))
+    }
+}
+
+impl Default for InstancesConfig {
+    fn default() -> Self {
+        InstancesConfig {
+            reconcile_interval: Some(60),
         }
     }
 }
@@ -118,10 +510,91 @@ impl Default for ServerConfig {
             log_requests: Some(true),
             port: Some(9292),
             host: Some("0.0.0.0".into()),
+            grpc_port: Some(9293),
+            worker_id: Some(0),
         }
     }
 }
 
+impl Default for SinksConfig {
+    fn default() -> Self {
+        SinksConfig {
+            outputs: Some(vec![SinkKind::ClickHouse]),
+            elasticsearch: None,
+            file: None,
+            webhook: None,
+        }
+    }
+}
+
+impl Default for ElasticsearchSinkConfig {
+    fn default() -> Self {
+        ElasticsearchSinkConfig {
+            url: Some("http://localhost:9200".into()),
+            index_prefix: Some("analytics-".into()),
+            username: None,
+            password: None,
+            timeout: Some(10),
+        }
+    }
+}
+
+impl Default for FileSinkConfig {
+    fn default() -> Self {
+        FileSinkConfig {
+            directory: Some("./data/sinks".into()),
+            max_size: Some(100 * 1024 * 1024),
+            max_files: Some(5),
+        }
+    }
+}
+
+impl Default for WebhookSinkConfig {
+    fn default() -> Self {
+        WebhookSinkConfig {
+            endpoints: None,
+            max_retries: Some(5),
+            retry_backoff: Some(500),
+            timeout: Some(10),
+        }
+    }
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        AlertsConfig {
+            enabled: Some(true),
+            interval: Some(60),
+            max_snapshot_age: Some(600),
+            webhook: None,
+            email: None,
+        }
+    }
+}
+
+impl AlertEmailConfig {
+    pub fn host(&self) -> String {
+        self.host.clone().unwrap_or_else(|| "localhost".into())
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(25)
+    }
+
+    pub fn from(&self) -> String {
+        self.from
+            .clone()
+            .unwrap_or_else(|| "analytics@localhost".into())
+    }
+}
+
+impl ClickHouseConfig {
+    /// Returns the name of the database that the server connects to.
+    pub fn database(&self) -> String {
+        self.database.clone().unwrap_or_else(|| "telemetry".into())
+    }
+}
+
 impl ToString for ClickHouseConfig {
     #[allow(unused_assignments)]
     fn to_string(&self) -> String {
@@ -154,12 +627,7 @@ impl ToString for ClickHouseConfig {
         //
         // now we need to append db name and parameters. this is fine.
         url.push('/');
-        url.push_str(
-            self.database
-                .as_ref()
-                .unwrap_or(&"telemetry".to_string())
-                .as_str(),
-        );
+        url.push_str(self.database().as_str());
 
         let mut prefix = '?';
         match self.use_lz4_compression {
@@ -201,6 +669,15 @@ impl Config {
     /// | Name                                 | Environment Variable Key                    | Required? | Type     |
     /// | :----------------------------------- | :------------------------------------------ | :-------- | :------- |
     /// | `secret_key`                         | ANALYTICS_SECERT_KEY                        | false     | String   |
+    /// | `alerts.enabled`                     | ANALYTICS_SERVER_ALERTS_ENABLED             | false     | bool     |
+    /// | `alerts.interval`                    | ANALYTICS_SERVER_ALERTS_INTERVAL            | false     | u64      |
+    /// | `alerts.max_snapshot_age`            | ANALYTICS_SERVER_ALERTS_MAX_SNAPSHOT_AGE    | false     | u64      |
+    /// | `alerts.webhook.url`                 | ANALYTICS_SERVER_ALERTS_WEBHOOK_URL         | false     | URL      |
+    /// | `alerts.webhook.secret`              | ANALYTICS_SERVER_ALERTS_WEBHOOK_SECRET      | false     | String   |
+    /// | `alerts.email.host`                  | ANALYTICS_SERVER_ALERTS_EMAIL_HOST          | false     | String   |
+    /// | `alerts.email.port`                  | ANALYTICS_SERVER_ALERTS_EMAIL_PORT          | false     | u16      |
+    /// | `alerts.email.from`                  | ANALYTICS_SERVER_ALERTS_EMAIL_FROM          | false     | String   |
+    /// | `alerts.email.to`                    | ANALYTICS_SERVER_ALERTS_EMAIL_TO            | false     | String[] |
     /// | `clickhouse.min_connections_in_pool` | ANALYTICS_SERVER_CLICKHOUSE_MIN_CONNECTIONS | false     | u16      |
     /// | `clickhouse.max_connections_in_pool` | ANALYTICS_SERVER_CLICKHOUSE_MAX_CONNECTIONS | false     | u16      |
     /// | `clickhouse.use_lz4_compression`     | ANALYTICS_SERVER_CLICKHOUSE_LZ4_COMPRESSION | false     | bool     |
@@ -209,12 +686,52 @@ impl Config {
     /// | `clickhouse.password`                | ANALYTICS_SERVER_CLICKHOUSE_PASSWORD        | false     | String   |
     /// | `clickhouse.host`                    | ANALYTICS_SERVER_CLICKHOUSE_HOST            | false     | String   |
     /// | `clickhouse.port`                    | ANALYTICS_SERVER_CLICKHOUSE_PORT            | false     | String   |
+    /// | `clickhouse.dry_run_migrations`      | ANALYTICS_SERVER_CLICKHOUSE_DRY_RUN         | false     | bool     |
+    /// | `collector.enabled`                  | ANALYTICS_SERVER_COLLECTOR_ENABLED          | false     | bool     |
+    /// | `collector.interval`                 | ANALYTICS_SERVER_COLLECTOR_INTERVAL         | false     | u64      |
+    /// | `collector.timeout`                  | ANALYTICS_SERVER_COLLECTOR_TIMEOUT          | false     | u64      |
+    /// | `collector.streaming`                | ANALYTICS_SERVER_COLLECTOR_STREAMING        | false     | bool     |
+    /// | `events.batch_size`                  | ANALYTICS_SERVER_EVENTS_BATCH_SIZE          | false     | usize    |
+    /// | `events.flush_interval`              | ANALYTICS_SERVER_EVENTS_FLUSH_INTERVAL      | false     | u64      |
+    /// | `events.max_body_size`               | ANALYTICS_SERVER_EVENTS_MAX_BODY_SIZE       | false     | u64      |
+    /// | `grpc.max_channels`                  | ANALYTICS_SERVER_GRPC_MAX_CHANNELS          | false     | usize    |
+    /// | `grpc.connect_timeout`               | ANALYTICS_SERVER_GRPC_CONNECT_TIMEOUT       | false     | u64      |
+    /// | `grpc.keepalive_interval`            | ANALYTICS_SERVER_GRPC_KEEPALIVE_INTERVAL    | false     | u64      |
+    /// | `grpc.keepalive_timeout`             | ANALYTICS_SERVER_GRPC_KEEPALIVE_TIMEOUT     | false     | u64      |
+    /// | `grpc_tls.mode`                      | ANALYTICS_SERVER_GRPC_TLS_MODE              | false     | TlsMode  |
+    /// | `grpc_tls.ca_cert`                   | ANALYTICS_SERVER_GRPC_TLS_CA_CERT           | false     | String   |
+    /// | `grpc_tls.client_cert`               | ANALYTICS_SERVER_GRPC_TLS_CLIENT_CERT       | false     | String   |
+    /// | `grpc_tls.client_key`                | ANALYTICS_SERVER_GRPC_TLS_CLIENT_KEY        | false     | String   |
+    /// | `health.enabled`                     | ANALYTICS_SERVER_HEALTH_ENABLED             | false     | bool     |
+    /// | `health.interval`                    | ANALYTICS_SERVER_HEALTH_INTERVAL            | false     | u64      |
+    /// | `health.timeout`                     | ANALYTICS_SERVER_HEALTH_TIMEOUT             | false     | u64      |
+    /// | `health.degraded_latency`            | ANALYTICS_SERVER_HEALTH_DEGRADED_LATENCY    | false     | u64      |
+    /// | `instances.reconcile_interval`       | ANALYTICS_SERVER_RECONCILE_INTERVAL         | false     | u64      |
     /// | `logging.logstash_url`               | ANALYTICS_SERVER_LOGSTASH_URL               | false     | URL      |
     /// | `logging.level`                      | ANALYTICS_SERVER_LOG_LEVEL                  | false     | LogLevel |
     /// | `logging.json`                       | ANALYTICS_SERVER_LOG_JSON                   | false     | bool     |
     /// | `server.log_requests`                | ANALYTICS_SERVER_HTTP_LOG_REQUESTS          | false     | bool     |
     /// | `server.port`                        | ANALYTICS_SERVER_HTTP_PORT (or `PORT`)      | false     | u16      |
     /// | `server.host`                        | ANALYTICS_SERVER_HTTP_HOST (or `HOST`)      | false     | String   |
+    /// | `server.grpc_port`                   | ANALYTICS_SERVER_GRPC_PORT                  | false     | u16      |
+    /// | `server.worker_id`                   | ANALYTICS_SERVER_WORKER_ID                  | false     | u16      |
+    /// | `sinks.outputs`                      | ANALYTICS_SERVER_SINKS (comma separated)    | false     | SinkKind |
+    /// | `sinks.elasticsearch.url`            | ANALYTICS_SERVER_SINKS_ES_URL               | false     | URL      |
+    /// | `sinks.elasticsearch.index_prefix`   | ANALYTICS_SERVER_SINKS_ES_INDEX_PREFIX      | false     | String   |
+    /// | `sinks.elasticsearch.username`       | ANALYTICS_SERVER_SINKS_ES_USERNAME          | false     | String   |
+    /// | `sinks.elasticsearch.password`       | ANALYTICS_SERVER_SINKS_ES_PASSWORD          | false     | String   |
+    /// | `sinks.elasticsearch.timeout`        | ANALYTICS_SERVER_SINKS_ES_TIMEOUT           | false     | u64      |
+    /// | `sinks.file.directory`               | ANALYTICS_SERVER_SINKS_FILE_DIRECTORY       | false     | String   |
+    /// | `sinks.file.max_size`                | ANALYTICS_SERVER_SINKS_FILE_MAX_SIZE        | false     | u64      |
+    /// | `sinks.file.max_files`               | ANALYTICS_SERVER_SINKS_FILE_MAX_FILES       | false     | usize    |
+    /// | `sinks.webhook.endpoints.url`        | ANALYTICS_SERVER_SINKS_WEBHOOK_URL          | false     | URL      |
+    /// | `sinks.webhook.endpoints.secret`     | ANALYTICS_SERVER_SINKS_WEBHOOK_SECRET       | false     | String   |
+    /// | `sinks.webhook.endpoints.events`     | ANALYTICS_SERVER_SINKS_WEBHOOK_EVENTS       | false     | String[] |
+    /// | `sinks.webhook.endpoints.instances`  | ANALYTICS_SERVER_SINKS_WEBHOOK_INSTANCES    | false     | String[] |
+    /// | `sinks.webhook.endpoints.stats`      | ANALYTICS_SERVER_SINKS_WEBHOOK_STATS        | false     | bool     |
+    /// | `sinks.webhook.max_retries`          | ANALYTICS_SERVER_SINKS_WEBHOOK_MAX_RETRIES  | false     | u32      |
+    /// | `sinks.webhook.retry_backoff`        | ANALYTICS_SERVER_SINKS_WEBHOOK_BACKOFF      | false     | u64      |
+    /// | `sinks.webhook.timeout`              | ANALYTICS_SERVER_SINKS_WEBHOOK_TIMEOUT      | false     | u64      |
     /// | `sentry_dsn`                         | ANALYTICS_SERVER_SENTRY_DSN                 | false     | String   |
     /// | `frontend`                           | ANALYTICS_SERVER_FRONTEND                   | false     | bool     |
     fn from_env() -> Config {
@@ -276,6 +793,156 @@ impl Config {
                     p.parse()
                         .expect("Unable to convert environment variable value to u16.")
                 }),
+
+                dry_run_migrations: var("ANALYTICS_SERVER_CLICKHOUSE_DRY_RUN").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to bool.")
+                }),
+            }),
+
+            alerts: Some(AlertsConfig {
+                enabled: var("ANALYTICS_SERVER_ALERTS_ENABLED").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to bool.")
+                }),
+
+                interval: var("ANALYTICS_SERVER_ALERTS_INTERVAL").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to u64.")
+                }),
+
+                max_snapshot_age: var("ANALYTICS_SERVER_ALERTS_MAX_SNAPSHOT_AGE").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to u64.")
+                }),
+
+                webhook: var("ANALYTICS_SERVER_ALERTS_WEBHOOK_URL")
+                    .ok()
+                    .map(|url| AlertWebhookConfig {
+                        url,
+                        secret: var("ANALYTICS_SERVER_ALERTS_WEBHOOK_SECRET").expect(
+                            "ANALYTICS_SERVER_ALERTS_WEBHOOK_SECRET is required with a webhook URL.",
+                        ),
+                    }),
+
+                email: var("ANALYTICS_SERVER_ALERTS_EMAIL_TO")
+                    .ok()
+                    .map(|to| AlertEmailConfig {
+                        host: var("ANALYTICS_SERVER_ALERTS_EMAIL_HOST").ok(),
+                        port: var("ANALYTICS_SERVER_ALERTS_EMAIL_PORT").ok().map(|p| {
+                            p.parse()
+                                .expect("Unable to convert environment variable value to u16.")
+                        }),
+                        from: var("ANALYTICS_SERVER_ALERTS_EMAIL_FROM").ok(),
+                        to: to.split(',').map(|t| t.trim().to_string()).collect(),
+                    }),
+            }),
+
+            collector: Some(CollectorConfig {
+                enabled: var("ANALYTICS_SERVER_COLLECTOR_ENABLED").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to bool.")
+                }),
+
+                interval: var("ANALYTICS_SERVER_COLLECTOR_INTERVAL").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to u64.")
+                }),
+
+                timeout: var("ANALYTICS_SERVER_COLLECTOR_TIMEOUT").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to u64.")
+                }),
+
+                streaming: var("ANALYTICS_SERVER_COLLECTOR_STREAMING").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to bool.")
+                }),
+            }),
+
+            events: Some(EventsConfig {
+                batch_size: var("ANALYTICS_SERVER_EVENTS_BATCH_SIZE").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to usize.")
+                }),
+
+                flush_interval: var("ANALYTICS_SERVER_EVENTS_FLUSH_INTERVAL").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to u64.")
+                }),
+
+                max_body_size: var("ANALYTICS_SERVER_EVENTS_MAX_BODY_SIZE").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to u64.")
+                }),
+            }),
+
+            grpc: Some(GrpcConfig {
+                max_channels: var("ANALYTICS_SERVER_GRPC_MAX_CHANNELS").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to usize.")
+                }),
+
+                connect_timeout: var("ANALYTICS_SERVER_GRPC_CONNECT_TIMEOUT").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to u64.")
+                }),
+
+                keepalive_interval: var("ANALYTICS_SERVER_GRPC_KEEPALIVE_INTERVAL")
+                    .ok()
+                    .map(|p| {
+                        p.parse()
+                            .expect("Unable to convert environment variable value to u64.")
+                    }),
+
+                keepalive_timeout: var("ANALYTICS_SERVER_GRPC_KEEPALIVE_TIMEOUT")
+                    .ok()
+                    .map(|p| {
+                        p.parse()
+                            .expect("Unable to convert environment variable value to u64.")
+                    }),
+            }),
+
+            grpc_tls: Some(GrpcTlsConfig {
+                mode: var("ANALYTICS_SERVER_GRPC_TLS_MODE").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to TlsMode.")
+                }),
+
+                ca_cert: var("ANALYTICS_SERVER_GRPC_TLS_CA_CERT").ok(),
+                client_cert: var("ANALYTICS_SERVER_GRPC_TLS_CLIENT_CERT").ok(),
+                client_key: var("ANALYTICS_SERVER_GRPC_TLS_CLIENT_KEY").ok(),
+            }),
+
+            health: Some(HealthConfig {
+                enabled: var("ANALYTICS_SERVER_HEALTH_ENABLED").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to bool.")
+                }),
+
+                interval: var("ANALYTICS_SERVER_HEALTH_INTERVAL").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to u64.")
+                }),
+
+                timeout: var("ANALYTICS_SERVER_HEALTH_TIMEOUT").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to u64.")
+                }),
+
+                degraded_latency: var("ANALYTICS_SERVER_HEALTH_DEGRADED_LATENCY")
+                    .ok()
+                    .map(|p| {
+                        p.parse()
+                            .expect("Unable to convert environment variable value to u64.")
+                    }),
+            }),
+
+            instances: Some(InstancesConfig {
+                reconcile_interval: var("ANALYTICS_SERVER_RECONCILE_INTERVAL").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to u64.")
+                }),
             }),
 
             logging: Some(LogConfig {
@@ -298,6 +965,90 @@ impl Config {
                     p.parse()
                         .expect("Unable to convert environment variable value to i16.")
                 }),
+
+                grpc_port: var("ANALYTICS_SERVER_GRPC_PORT").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to u16.")
+                }),
+
+                worker_id: var("ANALYTICS_SERVER_WORKER_ID").ok().map(|p| {
+                    p.parse()
+                        .expect("Unable to convert environment variable value to u16.")
+                }),
+            }),
+
+            sinks: Some(SinksConfig {
+                outputs: var("ANALYTICS_SERVER_SINKS").ok().map(|p| {
+                    p.split(',')
+                        .map(|kind| {
+                            kind.trim()
+                                .parse()
+                                .expect("Unable to convert environment variable value to SinkKind.")
+                        })
+                        .collect()
+                }),
+
+                elasticsearch: Some(ElasticsearchSinkConfig {
+                    url: var("ANALYTICS_SERVER_SINKS_ES_URL").ok(),
+                    index_prefix: var("ANALYTICS_SERVER_SINKS_ES_INDEX_PREFIX").ok(),
+                    username: var("ANALYTICS_SERVER_SINKS_ES_USERNAME").ok(),
+                    password: var("ANALYTICS_SERVER_SINKS_ES_PASSWORD").ok(),
+                    timeout: var("ANALYTICS_SERVER_SINKS_ES_TIMEOUT").ok().map(|p| {
+                        p.parse()
+                            .expect("Unable to convert environment variable value to u64.")
+                    }),
+                }),
+
+                file: Some(FileSinkConfig {
+                    directory: var("ANALYTICS_SERVER_SINKS_FILE_DIRECTORY").ok(),
+                    max_size: var("ANALYTICS_SERVER_SINKS_FILE_MAX_SIZE").ok().map(|p| {
+                        p.parse()
+                            .expect("Unable to convert environment variable value to u64.")
+                    }),
+
+                    max_files: var("ANALYTICS_SERVER_SINKS_FILE_MAX_FILES").ok().map(|p| {
+                        p.parse()
+                            .expect("Unable to convert environment variable value to usize.")
+                    }),
+                }),
+
+                webhook: Some(WebhookSinkConfig {
+                    endpoints: var("ANALYTICS_SERVER_SINKS_WEBHOOK_URL").ok().map(|url| {
+                        vec![WebhookEndpointConfig {
+                            url,
+                            secret: var("ANALYTICS_SERVER_SINKS_WEBHOOK_SECRET").expect(
+                                "ANALYTICS_SERVER_SINKS_WEBHOOK_SECRET is required with a webhook URL.",
+                            ),
+                            events: var("ANALYTICS_SERVER_SINKS_WEBHOOK_EVENTS")
+                                .ok()
+                                .map(|p| p.split(',').map(|e| e.trim().to_string()).collect()),
+                            instances: var("ANALYTICS_SERVER_SINKS_WEBHOOK_INSTANCES")
+                                .ok()
+                                .map(|p| p.split(',').map(|i| i.trim().to_string()).collect()),
+                            stats: var("ANALYTICS_SERVER_SINKS_WEBHOOK_STATS").ok().map(|p| {
+                                p.parse()
+                                    .expect("Unable to convert environment variable value to bool.")
+                            }),
+                        }]
+                    }),
+
+                    max_retries: var("ANALYTICS_SERVER_SINKS_WEBHOOK_MAX_RETRIES").ok().map(|p| {
+                        p.parse()
+                            .expect("Unable to convert environment variable value to u32.")
+                    }),
+
+                    retry_backoff: var("ANALYTICS_SERVER_SINKS_WEBHOOK_BACKOFF")
+                        .ok()
+                        .map(|p| {
+                            p.parse()
+                                .expect("Unable to convert environment variable value to u64.")
+                        }),
+
+                    timeout: var("ANALYTICS_SERVER_SINKS_WEBHOOK_TIMEOUT").ok().map(|p| {
+                        p.parse()
+                            .expect("Unable to convert environment variable value to u64.")
+                    }),
+                }),
             }),
         }
     }
@@ -369,6 +1120,7 @@ mod tests {
             password: None,
             host: Some("localhost".into()),
             port: Some(9000),
+            dry_run_migrations: None,
         };
 
         let url = config.to_string();
@@ -386,6 +1138,7 @@ mod tests {
             password: Some("noelisthebest".into()),
             host: Some("localhost".into()),
             port: Some(9000),
+            dry_run_migrations: None,
         };
 
         let url = config.to_string();
@@ -403,6 +1156,7 @@ mod tests {
             password: Some("noelisthebest".into()),
             host: Some("localhost".into()),
             port: Some(9000),
+            dry_run_migrations: None,
         };
 
         let url = config.to_string();
@@ -417,6 +1171,7 @@ mod tests {
             password: Some("noelisthebest".into()),
             host: Some("localhost".into()),
             port: Some(9000),
+            dry_run_migrations: None,
         };
 
         let url2 = config_2.to_string();
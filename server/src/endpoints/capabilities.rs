@@ -0,0 +1,170 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use analytics_protobufs::{ConnectionAckRequest, ConnectionAckResponse, Feature as ProtoFeature};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The newest version of the analytics protocol that this server speaks.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The oldest version of the analytics protocol that this server still speaks. Version 1
+/// is what instances spoke before `ConnectionAck` carried a version at all.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// The features that this server supports, which are sent in every `ConnectionAck`.
+pub const SERVER_FEATURES: &[Feature] = &[Feature::Streaming, Feature::MetricsSchema];
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    Streaming,
+    Compression,
+    MetricsSchema,
+}
+
+impl Feature {
+    fn from_proto(value: i32) -> Option<Feature> {
+        match ProtoFeature::from_i32(value)? {
+            ProtoFeature::Streaming => Some(Feature::Streaming),
+            ProtoFeature::Compression => Some(Feature::Compression),
+            ProtoFeature::MetricsSchema => Some(Feature::MetricsSchema),
+            ProtoFeature::UnknownFeature => None,
+        }
+    }
+
+    fn to_proto(self) -> ProtoFeature {
+        match self {
+            Feature::Streaming => ProtoFeature::Streaming,
+            Feature::Compression => ProtoFeature::Compression,
+            Feature::MetricsSchema => ProtoFeature::MetricsSchema,
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum NegotiationError {
+    #[error("Instance speaks protocol version {0}, but this server only supports versions {MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION}")]
+    IncompatibleVersion(u32),
+
+    #[error("Instance didn't acknowledge the connection")]
+    NotConnected,
+}
+
+/// What an instance said it supports in `ConnectionAck`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    pub protocol_version: u32,
+    pub features: Vec<Feature>,
+
+    #[serde(default)]
+    pub product: Option<String>,
+}
+
+impl Capabilities {
+    /// Returns the request that is sent to instances, which advertises what this server supports.
+    pub fn request() -> ConnectionAckRequest {
+        ConnectionAckRequest {
+            protocol_version: PROTOCOL_VERSION,
+            features: SERVER_FEATURES
+                .iter()
+                .map(|f| f.to_proto() as i32)
+                .collect(),
+        }
+    }
+
+    /// Checks what an instance responded with, and refuses it if it speaks a protocol version
+    /// that this server doesn't. Features that this server doesn't know of are ignored.
+    pub fn negotiate(res: &ConnectionAckResponse) -> Result<Capabilities, NegotiationError> {
+        if !res.connected {
+            return Err(NegotiationError::NotConnected);
+        }
+
+        let protocol_version = match res.protocol_version {
+            0 => MIN_PROTOCOL_VERSION,
+            version => version,
+        };
+
+        if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&protocol_version) {
+            return Err(NegotiationError::IncompatibleVersion(protocol_version));
+        }
+
+        let mut features = vec![];
+        for feature in res.features.iter().filter_map(|f| Feature::from_proto(*f)) {
+            if !features.contains(&feature) {
+                features.push(feature);
+            }
+        }
+
+        Ok(Capabilities {
+            protocol_version,
+            features,
+            product: Some(res.product.clone()).filter(|p| !p.is_empty()),
+        })
+    }
+
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ack(protocol_version: u32, features: Vec<ProtoFeature>) -> ConnectionAckResponse {
+        ConnectionAckResponse {
+            connected: true,
+            instance_uuid: "test".into(),
+            protocol_version,
+            features: features.into_iter().map(|f| f as i32).collect(),
+            product: "charted-server".into(),
+        }
+    }
+
+    #[test]
+    fn negotiate_capabilities() {
+        let caps = Capabilities::negotiate(&ack(
+            2,
+            vec![ProtoFeature::Streaming, ProtoFeature::UnknownFeature],
+        ))
+        .unwrap();
+
+        assert_eq!(caps.protocol_version, 2);
+        assert_eq!(caps.product.as_deref(), Some("charted-server"));
+        assert!(caps.supports(Feature::Streaming));
+        assert!(!caps.supports(Feature::Compression));
+
+        // instances from before negotiation don't send a version at all
+        let legacy = Capabilities::negotiate(&ack(0, vec![])).unwrap();
+        assert_eq!(legacy.protocol_version, 1);
+        assert!(legacy.features.is_empty());
+    }
+
+    #[test]
+    fn refuse_incompatible_versions() {
+        assert_eq!(
+            Capabilities::negotiate(&ack(PROTOCOL_VERSION + 1, vec![])),
+            Err(NegotiationError::IncompatibleVersion(PROTOCOL_VERSION + 1))
+        );
+
+        let mut res = ack(2, vec![]);
+        res.connected = false;
+        assert_eq!(
+            Capabilities::negotiate(&res),
+            Err(NegotiationError::NotConnected)
+        );
+    }
+}
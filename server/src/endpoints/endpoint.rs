@@ -13,20 +13,27 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::endpoints::capabilities::Capabilities;
+use crate::endpoints::health::{HealthCheck, HealthStatus};
+use crate::endpoints::pool::ChannelPool;
+use crate::endpoints::tls::EndpointTls;
+use crate::prisma::instance;
 use crate::to_redis_err;
 use analytics_protobufs::analytics_client::AnalyticsClient;
-use analytics_protobufs::{ConnectionAckRequest, ConnectionAckResponse};
+use analytics_protobufs::{ReceiveStatsRequest, ReceiveStatsResponse, StreamStatsRequest};
 use anyhow::{anyhow, Result};
+use chrono::Utc;
 use redis::Value::Nil;
 use redis::{FromRedisValue, RedisResult, RedisWrite, ToRedisArgs, Value};
 use rsa::{PaddingScheme, RsaPrivateKey, RsaPublicKey};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::net::SocketAddr;
+use std::time::{Duration, Instant};
 use tonic::codegen::InterceptedService;
 use tonic::service::Interceptor;
 use tonic::transport::Channel;
-use tonic::{Request, Status};
+use tonic::{Request, Status, Streaming};
 
 #[derive(Clone, Debug)]
 pub struct EndpointKeys {
@@ -39,6 +46,12 @@ pub struct Endpoint {
     pub instance_name: String,
     pub addr: SocketAddr,
     pub api_token: Option<String>,
+    #[serde(default)]
+    pub owner_id: Option<i64>,
+    #[serde(default)]
+    pub tls: Option<EndpointTls>,
+    #[serde(default)]
+    pub capabilities: Option<Capabilities>,
     #[serde(skip_serializing, skip_deserializing)]
     pub keys: Option<EndpointKeys>,
 }
@@ -63,6 +76,22 @@ impl FromRedisValue for Endpoint {
     }
 }
 
+impl TryFrom<instance::Data> for Endpoint {
+    type Error = anyhow::Error;
+
+    fn try_from(data: instance::Data) -> Result<Self> {
+        Ok(Endpoint {
+            instance_name: data.uuid,
+            addr: data.grpc_endpoint.parse()?,
+            api_token: Some(data.service_token),
+            owner_id: data.owner_id,
+            tls: data.tls.map(serde_json::from_value).transpose()?,
+            capabilities: data.capabilities.map(serde_json::from_value).transpose()?,
+            keys: None,
+        })
+    }
+}
+
 pub struct EndpointAuth {
     pub(crate) token: String,
 }
@@ -82,52 +111,102 @@ impl Endpoint {
             instance_name: instance_name.into(),
             addr: addr.into(),
             api_token: None,
+            owner_id: None,
+            tls: None,
+            capabilities: None,
             keys: None,
         }
     }
 
-    pub async fn get_grpc_client(
-        &self,
-    ) -> Result<AnalyticsClient<InterceptedService<Channel, EndpointAuth>>> {
+    /// Decrypts the service token with the endpoint's private key.
+    pub(crate) fn decrypt_token(&self) -> Result<String> {
         let mut token: Option<String> = None;
-        if let Some(keys) = &self.keys {
-            match keys.private.decrypt(
-                PaddingScheme::new_pkcs1v15_encrypt(),
-                self.api_token.clone().unwrap().as_bytes(),
-            ) {
-                Ok(dec) => token = Some(String::from_utf8(dec).unwrap()),
-                Err(_e) => {}
+        if let (Some(keys), Some(api_token)) = (&self.keys, &self.api_token) {
+            // the token is stored the same way it was sent in `instance_finalize`, which
+            // is base64 encoded ciphertext.
+            if let Ok(encrypted) = base64::decode(api_token) {
+                if let Ok(dec) = keys
+                    .private
+                    .decrypt(PaddingScheme::new_pkcs1v15_encrypt(), &encrypted[..])
+                {
+                    token = String::from_utf8(dec).ok();
+                }
             }
         }
 
-        if token.is_none() {
-            return Err(anyhow!("Unable to determine service token"));
-        }
-
-        let channel = Channel::from_shared(format!("grpc://{}", self.addr))
-            .unwrap()
-            .connect()
-            .await?;
+        token.ok_or_else(|| anyhow!("Unable to determine service token"))
+    }
 
+    /// Returns a client for the endpoint, reusing the channel from the [`ChannelPool`]
+    /// if the endpoint was connected to before.
+    pub async fn get_grpc_client(
+        &self,
+    ) -> Result<AnalyticsClient<InterceptedService<Channel, EndpointAuth>>> {
+        let (channel, token) = ChannelPool::global().get(self)?;
         Ok(AnalyticsClient::with_interceptor(
             channel,
-            EndpointAuth {
-                token: token.unwrap(),
-            },
+            EndpointAuth { token },
         ))
     }
 
-    pub async fn is_healthy(&self) -> Result<ConnectionAckResponse> {
+    /// Calls `ConnectionAck` and negotiates what the instance supports. Instances that speak
+    /// an incompatible protocol version fail with a [`NegotiationError`](crate::endpoints::capabilities::NegotiationError).
+    pub async fn is_healthy(&self) -> Result<Capabilities> {
         let client = self.get_grpc_client().await;
         return match client {
             Ok(mut c) => {
-                let ack_res = c.connection_ack(ConnectionAckRequest {}).await;
+                let ack_res = c.connection_ack(Capabilities::request()).await;
                 match ack_res {
-                    Ok(v) => Ok(v.into_inner()),
+                    Ok(v) => Ok(Capabilities::negotiate(&v.into_inner())?),
                     Err(e) => Err(anyhow::Error::from(e)),
                 }
             }
             Err(e) => Err(anyhow!(e.to_string())),
         };
     }
+
+    /// Runs [`is_healthy`](Self::is_healthy) with a timeout and classifies how it went, along
+    /// with the capabilities that were negotiated.
+    pub async fn check_health(
+        &self,
+        timeout: Duration,
+        degraded_latency: Duration,
+    ) -> (HealthCheck, Result<Capabilities>) {
+        let started = Instant::now();
+        let result = match tokio::time::timeout(timeout, self.is_healthy()).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow!("didn't respond within {timeout:?}")),
+        };
+
+        let latency = started.elapsed();
+        let check = HealthCheck {
+            status: HealthStatus::classify(result.is_ok(), latency, degraded_latency),
+            checked_at: Utc::now(),
+            latency_ms: latency.as_millis() as u64,
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+
+        (check, result)
+    }
+
+    pub async fn retrieve_stats(&self) -> Result<ReceiveStatsResponse> {
+        let mut client = self.get_grpc_client().await?;
+        match client.retrieve_stats(ReceiveStatsRequest {}).await {
+            Ok(v) => Ok(v.into_inner()),
+            Err(e) => Err(anyhow::Error::from(e)),
+        }
+    }
+
+    /// Subscribes to the stats of the endpoint. Fails with `UNIMPLEMENTED` if the instance
+    /// can only be polled.
+    pub async fn stream_stats(&self) -> Result<Streaming<ReceiveStatsResponse>> {
+        let mut client = self.get_grpc_client().await?;
+        match client
+            .stream_stats(StreamStatsRequest { min_interval: 0 })
+            .await
+        {
+            Ok(v) => Ok(v.into_inner()),
+            Err(e) => Err(anyhow::Error::from(e)),
+        }
+    }
 }
@@ -13,41 +13,87 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::config::Config;
+use crate::endpoints::capabilities::Capabilities;
 use crate::endpoints::endpoint::{Endpoint, EndpointKeys};
+use crate::endpoints::health::{HealthCheck, HEALTH_HASH};
+use crate::endpoints::keystore::KeyCipher;
+use crate::endpoints::pool::ChannelPool;
+use crate::prisma::{instance, user, PrismaClient};
+use crate::prometheus::registry::ServerMetrics;
 use crate::sentinel::SentinelManager;
-use crate::to_redis_err;
+use crate::{snowflake, to_redis_err};
 use anyhow::anyhow;
 use rand::thread_rng;
 use redis::{Commands, RedisResult};
 use rsa::{RsaPrivateKey, RsaPublicKey};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::Mutex;
 
+/// Keeps track of every registered endpoint. Postgres is the source of truth for finalized
+/// instances, while the Redis `endpoints` hash acts as a cache and also holds endpoints that
+/// haven't been finalized yet.
 #[derive(Clone, Debug)]
 pub struct EndpointManager {
     redis: Arc<Mutex<SentinelManager>>,
+    prisma: Arc<PrismaClient>,
+
+    /// Cache of the key pairs that were generated or loaded from the `endpoint_keys`
+    /// Redis hash.
     keys: HashMap<String, EndpointKeys>,
+
+    /// Cipher to encrypt the key pairs at rest, if a secret key was configured.
+    cipher: Option<KeyCipher>,
 }
 
 impl EndpointManager {
-    pub fn new(redis: Arc<Mutex<SentinelManager>>) -> Self {
+    pub fn new(redis: Arc<Mutex<SentinelManager>>, prisma: Arc<PrismaClient>) -> Self {
+        let cipher = Config::get()
+            .and_then(|config| config.secret_key.as_deref())
+            .map(KeyCipher::new);
+
+        if cipher.is_none() {
+            warn!("No secret key was configured, endpoint key pairs will only be kept in memory!");
+        }
+
         Self {
             redis,
+            prisma,
             keys: HashMap::new(),
+            cipher,
         }
     }
 
     pub async fn get_endpoint(&mut self, name: String) -> anyhow::Result<Endpoint> {
-        return match self.redis.lock().await.get_master().await {
-            Ok(mut client) => {
-                return match client.hget::<&str, String, Endpoint>("endpoints", name) {
-                    Ok(r) => Ok(r),
-                    Err(e) => Err(anyhow::Error::from(e)),
+        let mut client = self.redis.lock().await.get_master().await?;
+        match client.hget::<&str, String, Endpoint>("endpoints", name.clone()) {
+            Ok(r) => Ok(r),
+            Err(e) => {
+                // not in the cache, so it might've been finalized before redis was flushed.
+                let instance = self
+                    .prisma
+                    .instance()
+                    .find_unique(instance::uuid::equals(name.clone()))
+                    .exec()
+                    .await?;
+
+                match instance {
+                    Some(instance) => {
+                        let endpoint = Endpoint::try_from(instance)?;
+                        client.hset::<&str, String, Endpoint, i32>(
+                            "endpoints",
+                            name,
+                            endpoint.clone(),
+                        )?;
+
+                        Ok(endpoint)
+                    }
+                    None => Err(anyhow::Error::from(e)),
                 }
             }
-            Err(e) => Err(anyhow::Error::from(e)),
-        };
+        }
     }
 
     pub async fn get_endpoints(&mut self) -> anyhow::Result<Vec<Endpoint>> {
@@ -64,6 +110,8 @@ impl EndpointManager {
     }
 
     pub async fn add_endpoint(&mut self, endpoint: Endpoint) -> RedisResult<EndpointKeys> {
+        // the instance registered again, so whatever channel we had to it is stale
+        ChannelPool::global().invalidate(&endpoint.instance_name);
         return match self.redis.lock().await.get_master().await {
             Ok(mut client) => {
                 let r = client.hset::<&str, String, Endpoint, i32>(
@@ -74,11 +122,13 @@ impl EndpointManager {
                 return match r {
                     Ok(_) => {
                         let mut rng = thread_rng();
+                        let started = Instant::now();
                         let private = RsaPrivateKey::new(&mut rng, 2048);
-                        if r.is_err() {
+                        ServerMetrics::global().record_rsa_key_generation(started.elapsed());
+                        if let Err(e) = private {
                             return Err(to_redis_err!(format!(
                                 "Failed to create rsa private key: {}",
-                                r.unwrap_err()
+                                e
                             )));
                         }
                         let private_key = private.unwrap();
@@ -87,6 +137,14 @@ impl EndpointManager {
                             private: private_key,
                             public,
                         };
+                        if let Some(cipher) = &self.cipher {
+                            let sealed = cipher.seal(&new_keys).map_err(|e| to_redis_err!(e))?;
+                            client.hset::<&str, String, String, i32>(
+                                "endpoint_keys",
+                                endpoint.instance_name.clone(),
+                                sealed,
+                            )?;
+                        }
                         self.keys
                             .insert(endpoint.instance_name.clone(), new_keys.clone());
                         return Ok(new_keys);
@@ -98,31 +156,198 @@ impl EndpointManager {
         };
     }
 
-    pub async fn delete_endpoint(&mut self, name: String) -> RedisResult<i32> {
-        return match self.redis.lock().await.get_master().await {
-            Ok(mut client) => {
-                self.keys.remove(&name);
-                client.hdel::<&str, String, i32>("endpoints", name)
-            }
-            Err(e) => Err(e),
-        };
+    pub async fn delete_endpoint(&mut self, name: String) -> anyhow::Result<i32> {
+        self.prisma
+            .instance()
+            .delete_many(vec![instance::uuid::equals(name.clone())])
+            .exec()
+            .await?;
+
+        let mut client = self.redis.lock().await.get_master().await?;
+        self.keys.remove(&name);
+        ChannelPool::global().invalidate(&name);
+        client.hdel::<&str, String, i32>("endpoint_keys", name.clone())?;
+        client.hdel::<&str, String, i32>(HEALTH_HASH, name.clone())?;
+
+        Ok(client.hdel::<&str, String, i32>("endpoints", name)?)
+    }
+
+    /// Stores the capabilities that were negotiated with an instance, if they changed since
+    /// they were last negotiated.
+    pub async fn update_capabilities(
+        &mut self,
+        name: &str,
+        capabilities: Capabilities,
+    ) -> anyhow::Result<()> {
+        let mut endpoint = self.get_endpoint(name.to_string()).await?;
+        if endpoint.capabilities.as_ref() == Some(&capabilities) {
+            return Ok(());
+        }
+
+        info!(
+            "instance {name} speaks protocol version {} with features {:?}",
+            capabilities.protocol_version, capabilities.features
+        );
+
+        endpoint.capabilities = Some(capabilities);
+        if endpoint.api_token.is_some() {
+            self.persist_endpoint(&endpoint).await?;
+        }
+
+        let mut client = self.redis.lock().await.get_master().await?;
+        client.hset::<&str, &str, Endpoint, i32>("endpoints", name, endpoint)?;
+
+        Ok(())
+    }
+
+    /// Stores the result of the last health check for the given instance.
+    pub async fn record_health(&self, name: &str, health: &HealthCheck) -> anyhow::Result<()> {
+        let mut client = self.redis.lock().await.get_master().await?;
+        client.hset::<&str, &str, String, i32>(
+            HEALTH_HASH,
+            name,
+            serde_json::to_string(health)?,
+        )?;
+
+        Ok(())
     }
 
-    pub fn get_keys<S: Into<String>>(&mut self, instance: S) -> anyhow::Result<EndpointKeys> {
-        match self.keys.get(&instance.into()) {
+    /// Returns the result of the last health check for the given instance, if it was
+    /// ever checked.
+    pub async fn get_health(&self, name: &str) -> anyhow::Result<Option<HealthCheck>> {
+        let mut client = self.redis.lock().await.get_master().await?;
+        match client.hget::<&str, &str, Option<String>>(HEALTH_HASH, name)? {
+            Some(health) => Ok(Some(serde_json::from_str(&health)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the key pair for the given instance. Key pairs are loaded lazily from the
+    /// `endpoint_keys` Redis hash if they weren't generated by this server.
+    pub async fn get_keys<S: Into<String>>(&mut self, instance: S) -> anyhow::Result<EndpointKeys> {
+        let instance = instance.into();
+        if let Some(keys) = self.keys.get(&instance) {
+            return Ok(keys.clone());
+        }
+
+        let cipher = match &self.cipher {
+            Some(cipher) => cipher.clone(),
+            None => return Err(anyhow!("No key entry found!")),
+        };
+
+        let mut client = self.redis.lock().await.get_master().await?;
+        let sealed =
+            client.hget::<&str, String, Option<String>>("endpoint_keys", instance.clone())?;
+        match sealed {
+            Some(sealed) => {
+                let keys = cipher.open(&sealed)?;
+                self.keys.insert(instance, keys.clone());
+
+                Ok(keys)
+            }
             None => Err(anyhow!("No key entry found!")),
-            Some(v) => Ok(v.clone()),
         }
     }
 
-    pub fn drop_key<S: Into<String>>(&mut self, instance: S) -> Option<EndpointKeys> {
-        self.keys.remove(&instance.into())
+    pub async fn drop_key<S: Into<String>>(&mut self, instance: S) -> Option<EndpointKeys> {
+        let instance = instance.into();
+        if self.cipher.is_some() {
+            match self.redis.lock().await.get_master().await {
+                Ok(mut client) => {
+                    if let Err(e) =
+                        client.hdel::<&str, String, i32>("endpoint_keys", instance.clone())
+                    {
+                        warn!("Failed to drop key pair for {instance}: {e}");
+                    }
+                }
+                Err(e) => warn!("Failed to drop key pair for {instance}: {e}"),
+            }
+        }
+
+        self.keys.remove(&instance)
+    }
+
+    /// Upserts the endpoint as an `Instance` row in Postgres, keyed by its UUID.
+    pub async fn persist_endpoint(&self, e: &Endpoint) -> anyhow::Result<()> {
+        let token = match &e.api_token {
+            Some(token) => token.clone(),
+            None => return Err(anyhow!("Endpoint {} wasn't finalized", e.instance_name)),
+        };
+
+        let tls = e.tls.as_ref().map(serde_json::to_value).transpose()?;
+        let capabilities = e
+            .capabilities
+            .as_ref()
+            .map(serde_json::to_value)
+            .transpose()?;
+        let mut create_params = vec![
+            instance::tls::set(tls.clone()),
+            instance::capabilities::set(capabilities.clone()),
+        ];
+        let mut update_params = vec![
+            instance::grpc_endpoint::set(e.addr.to_string()),
+            instance::service_token::set(token.clone()),
+            instance::tls::set(tls),
+            instance::capabilities::set(capabilities),
+        ];
+
+        if let Some(owner_id) = e.owner_id {
+            create_params.push(instance::owner::connect(user::id::equals(owner_id)));
+            update_params.push(instance::owner::connect(user::id::equals(owner_id)));
+        }
+
+        self.prisma
+            .instance()
+            .upsert(
+                instance::uuid::equals(e.instance_name.clone()),
+                instance::create(
+                    e.addr.to_string(),
+                    token,
+                    e.instance_name.clone(),
+                    snowflake::generate(),
+                    e.instance_name.clone(),
+                    create_params,
+                ),
+                update_params,
+            )
+            .exec()
+            .await?;
+
+        Ok(())
+    }
+
+    /// Rebuilds the Redis `endpoints` hash from Postgres, which is needed if Redis
+    /// was flushed or lost its data. Returns how many endpoints were restored.
+    pub async fn reconcile(&mut self) -> anyhow::Result<usize> {
+        let instances = self.prisma.instance().find_many(vec![]).exec().await?;
+        let mut client = self.redis.lock().await.get_master().await?;
+        let cached = client.hkeys::<_, Vec<String>>("endpoints")?;
+
+        let mut restored = 0;
+        for instance in instances {
+            if cached.contains(&instance.uuid) {
+                continue;
+            }
+
+            let uuid = instance.uuid.clone();
+            match Endpoint::try_from(instance) {
+                Ok(endpoint) => {
+                    client.hset::<&str, String, Endpoint, i32>("endpoints", uuid, endpoint)?;
+                    restored += 1;
+                }
+                Err(e) => warn!("Unable to restore endpoint {uuid}: {e}"),
+            }
+        }
+
+        Ok(restored)
     }
 
     pub async fn store_api_key(&mut self, e: &mut Endpoint, key: String) -> anyhow::Result<bool> {
+        e.api_token = Some(key);
+        self.persist_endpoint(e).await?;
+
         return match self.redis.lock().await.get_master().await {
             Ok(mut client) => {
-                e.api_token = Some(key);
                 return Ok(
                     match client.hset::<&str, String, Endpoint, i32>(
                         "endpoints",
@@ -0,0 +1,324 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use clickhouse_rs::types::Block;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::clickhouse::client::ClickHouse;
+use crate::config::HealthConfig;
+use crate::endpoints::endpoint_manager::EndpointManager;
+
+/// The Redis hash that keeps the last health check of every endpoint.
+pub const HEALTH_HASH: &str = "endpoint_health";
+
+/// The ClickHouse table that keeps the history of every health check.
+pub const HEALTH_TABLE: &str = "instance_health";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// `ConnectionAck` succeeded in time.
+    Up,
+
+    /// `ConnectionAck` succeeded, but took longer than `health.degraded_latency`.
+    Degraded,
+
+    /// `ConnectionAck` failed or timed out.
+    Down,
+}
+
+impl HealthStatus {
+    /// Classifies the result of a single `ConnectionAck` call.
+    pub fn classify(succeeded: bool, latency: Duration, degraded_latency: Duration) -> Self {
+        match (succeeded, latency > degraded_latency) {
+            (false, _) => HealthStatus::Down,
+            (true, true) => HealthStatus::Degraded,
+            (true, false) => HealthStatus::Up,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HealthStatus::Up => "up",
+            HealthStatus::Degraded => "degraded",
+            HealthStatus::Down => "down",
+        }
+    }
+}
+
+impl FromStr for HealthStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "up" => Ok(HealthStatus::Up),
+            "degraded" => Ok(HealthStatus::Degraded),
+            "down" => Ok(HealthStatus::Down),
+            _ => Err(format!("unknown health status `{s}`")),
+        }
+    }
+}
+
+impl Display for HealthStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The result of calling `ConnectionAck` on an endpoint.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HealthCheck {
+    pub status: HealthStatus,
+    pub checked_at: DateTime<Utc>,
+    pub latency_ms: u64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A single row of the [`HEALTH_TABLE`], which is a health check along with the status
+/// the instance had before it.
+#[derive(Debug, Clone)]
+pub struct HealthRecord {
+    pub instance_uuid: String,
+    pub previous: Option<HealthStatus>,
+    pub check: HealthCheck,
+}
+
+/// Builds a ClickHouse [`Block`] out of the given records, with the columns in the
+/// same order as the `instance_health` migration. Instances that were never checked
+/// before have an empty `previous_status`.
+pub fn to_block(records: &[HealthRecord]) -> Block {
+    Block::new()
+        .column(
+            "instance_uuid",
+            records
+                .iter()
+                .map(|r| r.instance_uuid.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "status",
+            records
+                .iter()
+                .map(|r| r.check.status.as_str().to_string())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "previous_status",
+            records
+                .iter()
+                .map(|r| r.previous.map(|s| s.as_str()).unwrap_or("").to_string())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "latency_ms",
+            records
+                .iter()
+                .map(|r| r.check.latency_ms)
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "error",
+            records
+                .iter()
+                .map(|r| r.check.error.clone().unwrap_or_default())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "checked_at",
+            records
+                .iter()
+                .map(|r| r.check.checked_at.with_timezone(&Tz::UTC))
+                .collect::<Vec<_>>(),
+        )
+}
+
+/// Returns the query that counts the checks of an instance over the last `window`
+/// seconds, and how many of those weren't down. Degraded instances still count as up.
+pub fn uptime_sql(instance_uuid: &str, window: i64) -> String {
+    format!(
+        "SELECT count() AS checks, countIf(status != 'down') AS up FROM {HEALTH_TABLE} \
+         WHERE instance_uuid = '{instance_uuid}' AND checked_at >= now() - INTERVAL {window} SECOND"
+    )
+}
+
+/// Returns the query for the checks of an instance over the last `window` seconds
+/// where its status changed, newest first.
+pub fn transitions_sql(instance_uuid: &str, window: i64, limit: usize) -> String {
+    format!(
+        "SELECT status, previous_status, latency_ms, error, checked_at FROM {HEALTH_TABLE} \
+         WHERE instance_uuid = '{instance_uuid}' AND checked_at >= now() - INTERVAL {window} SECOND \
+         AND status != previous_status ORDER BY checked_at DESC LIMIT {limit}"
+    )
+}
+
+/// Background job that calls `ConnectionAck` on every finalized endpoint on an
+/// interval, caches the result on the endpoint and keeps the history in ClickHouse.
+#[derive(Debug, Clone)]
+pub struct HealthChecker {
+    clickhouse: Arc<ClickHouse>,
+    endpoints: Arc<Mutex<EndpointManager>>,
+    config: HealthConfig,
+}
+
+impl HealthChecker {
+    pub fn new(
+        clickhouse: Arc<ClickHouse>,
+        endpoints: Arc<Mutex<EndpointManager>>,
+        config: HealthConfig,
+    ) -> HealthChecker {
+        HealthChecker {
+            clickhouse,
+            endpoints,
+            config,
+        }
+    }
+
+    /// Spawns the health checker in the background. The first check happens right away.
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            info!(
+                "checking the health of all instances every {:?}",
+                self.config.interval()
+            );
+
+            let mut ticker = interval(self.config.interval());
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                ticker.tick().await;
+                match self.check_all().await {
+                    Ok(count) => debug!("checked the health of {count} instances"),
+                    Err(e) => error!("unable to check the health of instances: {e}"),
+                }
+            }
+        })
+    }
+
+    /// Checks every finalized endpoint once, returning how many were checked.
+    pub async fn check_all(&self) -> Result<usize> {
+        let endpoints = {
+            let mut manager = self.endpoints.lock().await;
+            let mut endpoints = manager.get_endpoints().await?;
+            endpoints.retain(|e| e.api_token.is_some());
+            for endpoint in endpoints.iter_mut() {
+                endpoint.keys = manager.get_keys(endpoint.instance_name.clone()).await.ok();
+            }
+
+            endpoints
+        };
+
+        let checks = join_all(
+            endpoints
+                .iter()
+                .map(|e| e.check_health(self.config.timeout(), self.config.degraded_latency())),
+        )
+        .await;
+
+        let mut records = Vec::with_capacity(checks.len());
+        {
+            let mut manager = self.endpoints.lock().await;
+            for (endpoint, (check, negotiated)) in endpoints.into_iter().zip(checks) {
+                if let Ok(capabilities) = negotiated {
+                    if let Err(e) = manager
+                        .update_capabilities(&endpoint.instance_name, capabilities)
+                        .await
+                    {
+                        warn!(
+                            "unable to store capabilities of instance {}: {e}",
+                            endpoint.instance_name
+                        );
+                    }
+                }
+
+                let previous = manager
+                    .get_health(&endpoint.instance_name)
+                    .await
+                    .ok()
+                    .flatten()
+                    .map(|h| h.status);
+
+                if previous.is_some() && previous != Some(check.status) {
+                    info!(
+                        "instance {} went from {} to {}",
+                        endpoint.instance_name,
+                        previous.unwrap(),
+                        check.status
+                    );
+                }
+
+                if let Err(e) = manager.record_health(&endpoint.instance_name, &check).await {
+                    warn!(
+                        "unable to cache health of instance {}: {e}",
+                        endpoint.instance_name
+                    );
+                }
+
+                records.push(HealthRecord {
+                    instance_uuid: endpoint.instance_name,
+                    previous,
+                    check,
+                });
+            }
+        }
+
+        if records.is_empty() {
+            return Ok(0);
+        }
+
+        self.clickhouse
+            .insert(HEALTH_TABLE, to_block(&records))
+            .await?;
+
+        Ok(records.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_status() {
+        let degraded = Duration::from_millis(1000);
+        assert_eq!(
+            HealthStatus::classify(true, Duration::from_millis(20), degraded),
+            HealthStatus::Up
+        );
+
+        assert_eq!(
+            HealthStatus::classify(true, Duration::from_millis(1500), degraded),
+            HealthStatus::Degraded
+        );
+
+        assert_eq!(
+            HealthStatus::classify(false, Duration::from_millis(20), degraded),
+            HealthStatus::Down
+        );
+    }
+}
@@ -0,0 +1,111 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{Debug, Formatter};
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{anyhow, Result};
+use rsa::pkcs8::{DecodePrivateKey, EncodePrivateKey};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use sha2::{Digest, Sha256};
+
+use crate::endpoints::endpoint::EndpointKeys;
+
+const NONCE_SIZE: usize = 12;
+
+/// Encrypts an endpoint's RSA key pair so it can be stored at rest in Redis. The AES-256-GCM
+/// key is derived from the configured `secret_key`, so every server that shares the same
+/// secret key can read the key pairs that another server generated.
+#[derive(Clone)]
+pub struct KeyCipher {
+    cipher: Aes256Gcm,
+}
+
+impl Debug for KeyCipher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyCipher").finish_non_exhaustive()
+    }
+}
+
+impl KeyCipher {
+    pub fn new(secret_key: &str) -> KeyCipher {
+        let mut hasher = Sha256::new();
+        hasher.update(b"noelware-analytics:endpoint-keys:");
+        hasher.update(secret_key.as_bytes());
+
+        KeyCipher {
+            cipher: Aes256Gcm::new(&hasher.finalize()),
+        }
+    }
+
+    /// Encrypts the private key (as PKCS#8 DER) and returns it base64 encoded, prefixed
+    /// with the random nonce that was used.
+    pub fn seal(&self, keys: &EndpointKeys) -> Result<String> {
+        let der = keys.private.to_pkcs8_der()?;
+        let nonce = rand::random::<[u8; NONCE_SIZE]>();
+        let encrypted = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), der.as_bytes())
+            .map_err(|_| anyhow!("Unable to encrypt key pair"))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(encrypted);
+
+        Ok(base64::encode(sealed))
+    }
+
+    /// Decrypts a key pair that was encrypted with [`KeyCipher::seal`].
+    pub fn open(&self, sealed: &str) -> Result<EndpointKeys> {
+        let sealed = base64::decode(sealed)?;
+        if sealed.len() <= NONCE_SIZE {
+            return Err(anyhow!("Sealed key pair is too short"));
+        }
+
+        let (nonce, encrypted) = sealed.split_at(NONCE_SIZE);
+        let der = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), encrypted)
+            .map_err(|_| anyhow!("Unable to decrypt key pair, was the secret key changed?"))?;
+
+        let private = RsaPrivateKey::from_pkcs8_der(&der)?;
+        let public = RsaPublicKey::from(&private);
+
+        Ok(EndpointKeys { public, private })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::thread_rng;
+
+    #[test]
+    fn key_pairs_survive_a_round_trip() {
+        let private = RsaPrivateKey::new(&mut thread_rng(), 512).unwrap();
+        let keys = EndpointKeys {
+            public: RsaPublicKey::from(&private),
+            private,
+        };
+
+        let cipher = KeyCipher::new("owo");
+        let sealed = cipher.seal(&keys).unwrap();
+        let opened = cipher.open(&sealed).unwrap();
+
+        assert_eq!(opened.private, keys.private);
+        assert_eq!(opened.public, keys.public);
+        assert!(KeyCipher::new("uwu").open(&sealed).is_err());
+    }
+}
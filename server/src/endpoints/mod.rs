@@ -13,5 +13,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod capabilities;
 pub mod endpoint;
 pub mod endpoint_manager;
+pub mod health;
+pub mod keystore;
+pub mod pool;
+pub mod reconciler;
+pub mod service_token;
+pub mod tls;
@@ -0,0 +1,238 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use lru::LruCache;
+use once_cell::sync::OnceCell;
+use tonic::transport::{Channel, Endpoint as GrpcEndpoint};
+
+use crate::config::{Config, GrpcConfig};
+use crate::endpoints::endpoint::Endpoint;
+use crate::endpoints::tls::{EndpointTls, TlsSettings};
+
+static POOL: OnceCell<ChannelPool> = OnceCell::new();
+
+/// What a pooled channel was built from. If any of it changes, i.e, because the instance
+/// registered again, the channel is rebuilt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ChannelKey {
+    addr: String,
+    tls: Option<EndpointTls>,
+    api_token: Option<String>,
+}
+
+impl From<&Endpoint> for ChannelKey {
+    fn from(endpoint: &Endpoint) -> Self {
+        ChannelKey {
+            addr: endpoint.addr.to_string(),
+            tls: endpoint.tls.clone(),
+            api_token: endpoint.api_token.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PooledChannel {
+    key: ChannelKey,
+    channel: Channel,
+    token: String,
+}
+
+/// Keeps one lazily connected [`Channel`] per instance, along with its decrypted service
+/// token, so polling an instance doesn't open a new connection or do RSA every time. The
+/// least recently used channels are closed once `grpc.max_channels` is reached.
+#[derive(Debug)]
+pub struct ChannelPool {
+    channels: Mutex<LruCache<String, PooledChannel>>,
+    connect_timeout: Duration,
+    keepalive_interval: Duration,
+    keepalive_timeout: Duration,
+}
+
+impl ChannelPool {
+    pub fn new(config: &GrpcConfig) -> ChannelPool {
+        let defaults = GrpcConfig::default();
+        let max_channels = config
+            .max_channels
+            .or(defaults.max_channels)
+            .and_then(NonZeroUsize::new)
+            .unwrap_or(NonZeroUsize::new(1).unwrap());
+
+        ChannelPool {
+            channels: Mutex::new(LruCache::new(max_channels)),
+            connect_timeout: Duration::from_secs(
+                config.connect_timeout.or(defaults.connect_timeout).unwrap(),
+            ),
+            keepalive_interval: Duration::from_secs(
+                config
+                    .keepalive_interval
+                    .or(defaults.keepalive_interval)
+                    .unwrap(),
+            ),
+            keepalive_timeout: Duration::from_secs(
+                config
+                    .keepalive_timeout
+                    .or(defaults.keepalive_timeout)
+                    .unwrap(),
+            ),
+        }
+    }
+
+    /// Returns the pool that is configured from the global configuration.
+    pub fn global() -> &'static ChannelPool {
+        POOL.get_or_init(|| {
+            ChannelPool::new(
+                &Config::get()
+                    .and_then(|c| c.grpc.clone())
+                    .unwrap_or_default(),
+            )
+        })
+    }
+
+    /// Returns the channel and decrypted service token for the given endpoint, creating
+    /// them if they weren't pooled or the endpoint changed since.
+    pub fn get(&self, endpoint: &Endpoint) -> Result<(Channel, String)> {
+        self.get_or_build(&endpoint.instance_name, ChannelKey::from(endpoint), || {
+            let token = endpoint.decrypt_token()?;
+            let grpc = TlsSettings::global()?.endpoint(endpoint.addr, endpoint.tls.as_ref())?;
+
+            Ok((self.configure(grpc).connect_lazy(), token))
+        })
+    }
+
+    /// Closes the pooled channel of an instance, if there was one.
+    pub fn invalidate(&self, instance: &str) {
+        if self.channels.lock().unwrap().pop(instance).is_some() {
+            debug!("closed pooled channel of instance {instance}");
+        }
+    }
+
+    /// Returns how many channels are currently pooled.
+    pub fn len(&self) -> usize {
+        self.channels.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn configure(&self, endpoint: GrpcEndpoint) -> GrpcEndpoint {
+        endpoint
+            .connect_timeout(self.connect_timeout)
+            .tcp_keepalive(Some(self.keepalive_interval))
+            .http2_keep_alive_interval(self.keepalive_interval)
+            .keep_alive_timeout(self.keepalive_timeout)
+            .keep_alive_while_idle(true)
+    }
+
+    fn get_or_build<F>(
+        &self,
+        instance: &str,
+        key: ChannelKey,
+        build: F,
+    ) -> Result<(Channel, String)>
+    where
+        F: FnOnce() -> Result<(Channel, String)>,
+    {
+        let mut channels = self.channels.lock().unwrap();
+        if let Some(pooled) = channels.get(instance) {
+            if pooled.key == key {
+                return Ok((pooled.channel.clone(), pooled.token.clone()));
+            }
+        }
+
+        let (channel, token) = build()?;
+        channels.put(
+            instance.to_string(),
+            PooledChannel {
+                key,
+                channel: channel.clone(),
+                token: token.clone(),
+            },
+        );
+
+        Ok((channel, token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn key(addr: &str, api_token: &str) -> ChannelKey {
+        ChannelKey {
+            addr: addr.into(),
+            tls: None,
+            api_token: Some(api_token.into()),
+        }
+    }
+
+    fn build(calls: &Cell<usize>) -> Result<(Channel, String)> {
+        calls.set(calls.get() + 1);
+        Ok((
+            GrpcEndpoint::from_static("http://127.0.0.1:1").connect_lazy(),
+            "token".into(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn reuses_channels_until_endpoint_changes() {
+        let pool = ChannelPool::new(&GrpcConfig::default());
+        let calls = Cell::new(0);
+
+        pool.get_or_build("a", key("127.0.0.1:1", "x"), || build(&calls))
+            .unwrap();
+        pool.get_or_build("a", key("127.0.0.1:1", "x"), || build(&calls))
+            .unwrap();
+        assert_eq!(calls.get(), 1);
+
+        // re-registering with a new token rebuilds the channel
+        pool.get_or_build("a", key("127.0.0.1:1", "y"), || build(&calls))
+            .unwrap();
+        assert_eq!(calls.get(), 2);
+
+        pool.invalidate("a");
+        assert!(pool.is_empty());
+    }
+
+    #[tokio::test]
+    async fn bounded_by_max_channels() {
+        let pool = ChannelPool::new(&GrpcConfig {
+            max_channels: Some(2),
+            ..GrpcConfig::default()
+        });
+
+        let calls = Cell::new(0);
+        for instance in ["a", "b", "a", "c"] {
+            pool.get_or_build(instance, key("127.0.0.1:1", "x"), || build(&calls))
+                .unwrap();
+        }
+
+        // `b` was the least recently used one, so it was closed to make room for `c`
+        assert_eq!(pool.len(), 2);
+        assert_eq!(calls.get(), 3);
+
+        pool.get_or_build("a", key("127.0.0.1:1", "x"), || build(&calls))
+            .unwrap();
+        pool.get_or_build("b", key("127.0.0.1:1", "x"), || build(&calls))
+            .unwrap();
+        assert_eq!(calls.get(), 4);
+    }
+}
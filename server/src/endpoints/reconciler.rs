@@ -0,0 +1,57 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::endpoints::endpoint_manager::EndpointManager;
+
+/// Background job that periodically rebuilds the Redis `endpoints` hash from Postgres,
+/// so registrations survive Redis being flushed.
+#[derive(Debug, Clone)]
+pub struct Reconciler {
+    endpoints: Arc<Mutex<EndpointManager>>,
+    interval: Duration,
+}
+
+impl Reconciler {
+    pub fn new(endpoints: Arc<Mutex<EndpointManager>>, interval: Duration) -> Reconciler {
+        Reconciler {
+            endpoints,
+            interval,
+        }
+    }
+
+    /// Spawns the reconciler in the background. The first reconciliation happens right away.
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = interval(self.interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                ticker.tick().await;
+                match self.endpoints.lock().await.reconcile().await {
+                    Ok(0) => {}
+                    Ok(restored) => info!("restored {restored} endpoints from postgres"),
+                    Err(e) => error!("unable to reconcile endpoints: {e}"),
+                }
+            }
+        })
+    }
+}
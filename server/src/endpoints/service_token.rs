@@ -0,0 +1,186 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+use crate::endpoints::endpoint::Endpoint;
+use crate::endpoints::endpoint_manager::EndpointManager;
+
+#[derive(Debug, Error)]
+pub enum ServiceTokenError {
+    #[error("unknown instance {0}")]
+    UnknownInstance(String),
+
+    #[error("instance {0} wasn't finalized")]
+    NotFinalized(String),
+
+    #[error("unable to verify the service token, call init again")]
+    Undecryptable,
+
+    #[error("invalid service token")]
+    Invalid,
+}
+
+/// Verifies the service tokens that instances authenticate with when they push data to the
+/// server, which are the same tokens that the server uses to call them.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceTokens {
+    /// The decrypted service tokens, keyed by instance UUID along with the encrypted token
+    /// they were decrypted from, so RSA only happens once per token.
+    tokens: Arc<Mutex<HashMap<String, (String, String)>>>,
+}
+
+impl ServiceTokens {
+    pub fn new() -> ServiceTokens {
+        ServiceTokens::default()
+    }
+
+    /// Checks that `given` is the service token of the instance, and returns its endpoint.
+    /// Every failure is returned as [`ServiceTokenError::Invalid`] and only the real cause is
+    /// logged, so callers can't probe which instances exist or were finalized.
+    pub async fn verify(
+        &self,
+        endpoints: &Mutex<EndpointManager>,
+        instance: &str,
+        given: &str,
+    ) -> Result<Endpoint, ServiceTokenError> {
+        let endpoint = {
+            let mut manager = endpoints.lock().await;
+            match manager.get_endpoint(instance.to_string()).await {
+                Ok(mut endpoint) => {
+                    if endpoint.api_token.is_some() {
+                        endpoint.keys = manager.get_keys(instance).await.ok();
+                    }
+
+                    Some(endpoint)
+                }
+
+                Err(_) => None,
+            }
+        };
+
+        self.authenticate(instance, endpoint, given).await
+    }
+
+    async fn authenticate(
+        &self,
+        instance: &str,
+        endpoint: Option<Endpoint>,
+        given: &str,
+    ) -> Result<Endpoint, ServiceTokenError> {
+        let result = match endpoint {
+            Some(endpoint) if endpoint.api_token.is_some() => {
+                self.check(&endpoint, given).await.map(|_| endpoint)
+            }
+
+            Some(_) => Err(ServiceTokenError::NotFinalized(instance.to_string())),
+            None => Err(ServiceTokenError::UnknownInstance(instance.to_string())),
+        };
+
+        result.map_err(|e| {
+            warn!("rejected the service token of instance {instance}: {e}");
+            ServiceTokenError::Invalid
+        })
+    }
+
+    /// Compares `given` with the service token of the endpoint in constant time.
+    async fn check(&self, endpoint: &Endpoint, given: &str) -> Result<(), ServiceTokenError> {
+        let token = self.token(endpoint).await?;
+        if bool::from(token.as_bytes().ct_eq(given.as_bytes())) {
+            Ok(())
+        } else {
+            Err(ServiceTokenError::Invalid)
+        }
+    }
+
+    async fn token(&self, endpoint: &Endpoint) -> Result<String, ServiceTokenError> {
+        let encrypted = endpoint.api_token.clone().unwrap_or_default();
+        let mut tokens = self.tokens.lock().await;
+        if let Some((cached, token)) = tokens.get(&endpoint.instance_name) {
+            if *cached == encrypted {
+                return Ok(token.clone());
+            }
+        }
+
+        let token = endpoint.decrypt_token().map_err(|e| {
+            warn!(
+                "unable to decrypt service token of instance {}: {e}",
+                endpoint.instance_name
+            );
+
+            ServiceTokenError::Undecryptable
+        })?;
+
+        tokens.insert(endpoint.instance_name.clone(), (encrypted, token.clone()));
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint() -> Endpoint {
+        let mut endpoint = Endpoint::new("waff", ([127, 0, 0, 1], 4000));
+        endpoint.api_token = Some("encrypted".into());
+        endpoint
+    }
+
+    async fn tokens() -> ServiceTokens {
+        let tokens = ServiceTokens::new();
+        tokens
+            .tokens
+            .lock()
+            .await
+            .insert("waff".into(), ("encrypted".into(), "owo".into()));
+
+        tokens
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_tokens() {
+        let tokens = tokens().await;
+        assert!(tokens.check(&endpoint(), "owo").await.is_ok());
+
+        for given in ["uwu", "ow", "owoo", ""] {
+            assert!(matches!(
+                tokens.check(&endpoint(), given).await,
+                Err(ServiceTokenError::Invalid)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_and_pending_instances_like_wrong_tokens() {
+        let tokens = tokens().await;
+        let pending = Endpoint::new("waff", ([127, 0, 0, 1], 4000));
+
+        assert!(tokens
+            .authenticate("waff", Some(endpoint()), "owo")
+            .await
+            .is_ok());
+        for endpoint in [Some(endpoint()), Some(pending), None] {
+            assert!(matches!(
+                tokens.authenticate("waff", endpoint, "uwu").await,
+                Err(ServiceTokenError::Invalid)
+            ));
+        }
+    }
+}
@@ -0,0 +1,338 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs;
+use std::net::SocketAddr;
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint as GrpcEndpoint, Identity};
+
+use crate::config::{Config, GrpcTlsConfig, TlsMode};
+
+static SETTINGS: OnceCell<TlsSettings> = OnceCell::new();
+static SYSTEM_ROOTS: OnceCell<String> = OnceCell::new();
+
+/// The TLS settings of a single endpoint, which override `grpc_tls` from the configuration.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct EndpointTls {
+    pub mode: TlsMode,
+
+    /// The PEM encoded CA that signed the instance's certificate.
+    #[serde(default)]
+    pub ca_cert: Option<String>,
+
+    /// The name to verify the instance's certificate against. Defaults to the IP
+    /// address the instance was registered with.
+    #[serde(default)]
+    pub domain: Option<String>,
+}
+
+/// The TLS settings that were loaded from the configuration, used to build the channel
+/// to every endpoint.
+#[derive(Clone, Debug, Default)]
+pub struct TlsSettings {
+    mode: TlsMode,
+    ca_cert: Option<String>,
+    identity: Option<(String, String)>,
+}
+
+impl TlsSettings {
+    /// Loads the certificates that `grpc_tls` points to.
+    pub fn from_config(config: &GrpcTlsConfig) -> Result<TlsSettings> {
+        let read = |path: &String| {
+            fs::read_to_string(path).map_err(|e| anyhow!("unable to read {path}: {e}"))
+        };
+
+        let identity = match (&config.client_cert, &config.client_key) {
+            (Some(cert), Some(key)) => Some((read(cert)?, read(key)?)),
+            (None, None) => None,
+            _ => {
+                return Err(anyhow!(
+                    "both `grpc_tls.client_cert` and `grpc_tls.client_key` need to be set"
+                ))
+            }
+        };
+
+        Ok(TlsSettings {
+            mode: config.mode.unwrap_or_default(),
+            ca_cert: config.ca_cert.as_ref().map(read).transpose()?,
+            identity,
+        })
+    }
+
+    /// Returns the settings from the global configuration, which are only loaded once.
+    pub fn global() -> Result<&'static TlsSettings> {
+        SETTINGS.get_or_try_init(|| match Config::get().and_then(|c| c.grpc_tls.as_ref()) {
+            Some(config) => TlsSettings::from_config(config),
+            None => Ok(TlsSettings::default()),
+        })
+    }
+
+    /// Checks that an endpoint with the given TLS settings can be connected to at all,
+    /// so misconfigured endpoints are rejected when they are registered.
+    pub fn check(&self, tls: Option<&EndpointTls>) -> std::result::Result<(), String> {
+        let mode = tls.map(|t| t.mode).unwrap_or(self.mode);
+        let ca_cert = tls
+            .and_then(|t| t.ca_cert.as_ref())
+            .or(self.ca_cert.as_ref());
+
+        if let Some(pem) = tls.and_then(|t| t.ca_cert.as_ref()) {
+            if !pem.contains("-----BEGIN CERTIFICATE-----") {
+                return Err("`tls.ca_cert` must be a PEM encoded certificate".into());
+            }
+        }
+
+        match mode {
+            TlsMode::Pinned if ca_cert.is_none() => {
+                Err("the `pinned` TLS mode requires a CA certificate".into())
+            }
+            TlsMode::Mutual if self.identity.is_none() => {
+                Err("the `mutual` TLS mode requires `grpc_tls.client_cert` to be configured".into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Builds the gRPC endpoint for the given address, using the endpoint's own TLS
+    /// settings if it has any.
+    pub fn endpoint(&self, addr: SocketAddr, tls: Option<&EndpointTls>) -> Result<GrpcEndpoint> {
+        self.check(tls).map_err(|e| anyhow!(e))?;
+
+        let mode = tls.map(|t| t.mode).unwrap_or(self.mode);
+        if mode == TlsMode::Plaintext {
+            return Ok(GrpcEndpoint::from_shared(format!("http://{addr}"))?);
+        }
+
+        let domain = tls
+            .and_then(|t| t.domain.clone())
+            .unwrap_or_else(|| addr.ip().to_string());
+
+        let ca_cert = tls
+            .and_then(|t| t.ca_cert.clone())
+            .or_else(|| self.ca_cert.clone());
+
+        let mut config = ClientTlsConfig::new().domain_name(domain);
+        config = match (mode, ca_cert) {
+            (TlsMode::Pinned | TlsMode::Mutual, Some(pem)) => {
+                config.ca_certificate(Certificate::from_pem(pem))
+            }
+            _ => config.ca_certificate(Certificate::from_pem(system_roots()?)),
+        };
+
+        if mode == TlsMode::Mutual {
+            let (cert, key) = self.identity.clone().unwrap();
+            config = config.identity(Identity::from_pem(cert, key));
+        }
+
+        Ok(GrpcEndpoint::from_shared(format!("https://{addr}"))?.tls_config(config)?)
+    }
+}
+
+/// Returns the system's root certificates as a single PEM bundle, since tonic only
+/// trusts the certificates that it was given.
+fn system_roots() -> Result<&'static String> {
+    SYSTEM_ROOTS.get_or_try_init(|| {
+        let mut bundle = String::new();
+        for cert in rustls_native_certs::load_native_certs()? {
+            let encoded = base64::encode(&cert.0);
+            bundle.push_str("-----BEGIN CERTIFICATE-----\n");
+            for line in encoded.as_bytes().chunks(64) {
+                bundle.push_str(std::str::from_utf8(line).unwrap());
+                bundle.push('\n');
+            }
+
+            bundle.push_str("-----END CERTIFICATE-----\n");
+        }
+
+        if bundle.is_empty() {
+            return Err(anyhow!("no system root certificates were found"));
+        }
+
+        Ok(bundle)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analytics_protobufs::analytics_client::AnalyticsClient;
+    use analytics_protobufs::analytics_server::{Analytics, AnalyticsServer};
+    use analytics_protobufs::{
+        ConnectionAckRequest, ConnectionAckResponse, ReceiveStatsRequest, ReceiveStatsResponse,
+        StreamStatsRequest,
+    };
+    use rcgen::{
+        BasicConstraints, Certificate as RcgenCertificate, CertificateParams, IsCa, SanType,
+    };
+    use std::net::{IpAddr, Ipv4Addr};
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+    use tonic::transport::{Server, ServerTlsConfig};
+    use tonic::{Request, Response, Status};
+
+    struct TestInstance;
+
+    #[tonic::async_trait]
+    impl Analytics for TestInstance {
+        type StreamStatsStream = ReceiverStream<std::result::Result<ReceiveStatsResponse, Status>>;
+
+        async fn connection_ack(
+            &self,
+            _: Request<ConnectionAckRequest>,
+        ) -> std::result::Result<Response<ConnectionAckResponse>, Status> {
+            Ok(Response::new(ConnectionAckResponse {
+                connected: true,
+                instance_uuid: "test".into(),
+                ..Default::default()
+            }))
+        }
+
+        async fn retrieve_stats(
+            &self,
+            _: Request<ReceiveStatsRequest>,
+        ) -> std::result::Result<Response<ReceiveStatsResponse>, Status> {
+            Err(Status::unimplemented("not needed"))
+        }
+
+        async fn stream_stats(
+            &self,
+            _: Request<StreamStatsRequest>,
+        ) -> std::result::Result<Response<Self::StreamStatsStream>, Status> {
+            Err(Status::unimplemented("not needed"))
+        }
+    }
+
+    struct Pki {
+        ca: RcgenCertificate,
+        server: (String, String),
+        client: (String, String),
+    }
+
+    fn generate_pki() -> Pki {
+        let mut params = CertificateParams::new(vec![]);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = RcgenCertificate::from_params(params).unwrap();
+
+        let mut params = CertificateParams::new(vec!["localhost".into()]);
+        params
+            .subject_alt_names
+            .push(SanType::IpAddress(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+
+        let server = RcgenCertificate::from_params(params).unwrap();
+        let client =
+            RcgenCertificate::from_params(CertificateParams::new(vec!["analytics".into()]))
+                .unwrap();
+
+        Pki {
+            server: (
+                server.serialize_pem_with_signer(&ca).unwrap(),
+                server.serialize_private_key_pem(),
+            ),
+            client: (
+                client.serialize_pem_with_signer(&ca).unwrap(),
+                client.serialize_private_key_pem(),
+            ),
+            ca,
+        }
+    }
+
+    async fn serve(pki: &Pki, require_client_cert: bool) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut tls = ServerTlsConfig::new().identity(Identity::from_pem(
+            pki.server.0.clone(),
+            pki.server.1.clone(),
+        ));
+
+        if require_client_cert {
+            tls = tls.client_ca_root(Certificate::from_pem(pki.ca.serialize_pem().unwrap()));
+        }
+
+        let server = Server::builder()
+            .tls_config(tls)
+            .unwrap()
+            .add_service(AnalyticsServer::new(TestInstance));
+
+        tokio::spawn(server.serve_with_incoming(TcpListenerStream::new(listener)));
+        addr
+    }
+
+    async fn ack(settings: &TlsSettings, addr: SocketAddr, tls: &EndpointTls) -> Result<bool> {
+        let channel = settings.endpoint(addr, Some(tls))?.connect().await?;
+        let res = AnalyticsClient::new(channel)
+            .connection_ack(ConnectionAckRequest::default())
+            .await?;
+
+        Ok(res.into_inner().connected)
+    }
+
+    #[tokio::test]
+    async fn pinned_ca() {
+        let pki = generate_pki();
+        let addr = serve(&pki, false).await;
+        let settings = TlsSettings::default();
+
+        let pinned = EndpointTls {
+            mode: TlsMode::Pinned,
+            ca_cert: Some(pki.ca.serialize_pem().unwrap()),
+            domain: None,
+        };
+
+        assert!(ack(&settings, addr, &pinned).await.unwrap());
+
+        // a CA that didn't sign the instance's certificate isn't trusted
+        let other = EndpointTls {
+            ca_cert: Some(generate_pki().ca.serialize_pem().unwrap()),
+            ..pinned.clone()
+        };
+
+        assert!(ack(&settings, addr, &other).await.is_err());
+
+        // neither is talking plaintext to a TLS server
+        assert!(ack(&settings, addr, &EndpointTls::default()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn mutual_tls() {
+        let pki = generate_pki();
+        let addr = serve(&pki, true).await;
+        let tls = EndpointTls {
+            mode: TlsMode::Mutual,
+            ca_cert: Some(pki.ca.serialize_pem().unwrap()),
+            domain: Some("localhost".into()),
+        };
+
+        let settings = TlsSettings {
+            identity: Some(pki.client.clone()),
+            ..TlsSettings::default()
+        };
+
+        assert!(ack(&settings, addr, &tls).await.unwrap());
+
+        // without a client certificate, the mode can't be used at all
+        assert!(TlsSettings::default().check(Some(&tls)).is_err());
+
+        // and the instance refuses connections that don't present one
+        let pinned = EndpointTls {
+            mode: TlsMode::Pinned,
+            ..tls
+        };
+
+        assert!(ack(&TlsSettings::default(), addr, &pinned).await.is_err());
+    }
+}
@@ -0,0 +1,262 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::Utc;
+use chrono_tz::Tz;
+use clickhouse_rs::types::Block;
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::config::EventsConfig;
+use crate::models::event::Event;
+use crate::prometheus::registry::ServerMetrics;
+use crate::sinks::sink::{Batch, Sinks};
+
+/// The ClickHouse table that events are inserted into.
+pub const EVENTS_TABLE: &str = "events";
+
+/// Returned when events can't be buffered because the primary sink has been failing
+/// for long enough that the buffer reached its capacity. The events weren't accepted.
+#[derive(Debug, thiserror::Error)]
+#[error("events buffer is full, dropped {0} events")]
+pub struct BufferFull(pub usize);
+
+/// Buffers validated events in memory and bulk-writes them to the sinks, either
+/// once the buffer reaches the configured batch size or when the flush interval elapses.
+#[derive(Debug)]
+pub struct EventBuffer {
+    sinks: Sinks,
+    events: Mutex<Vec<Event>>,
+    batch_size: usize,
+    flush_interval: Duration,
+}
+
+impl EventBuffer {
+    pub fn new(sinks: Sinks, config: EventsConfig) -> EventBuffer {
+        let defaults = EventsConfig::default();
+        let batch_size = config.batch_size.or(defaults.batch_size).unwrap().max(1);
+
+        EventBuffer {
+            sinks,
+            events: Mutex::new(Vec::with_capacity(batch_size)),
+            batch_size,
+            flush_interval: Duration::from_secs(
+                config
+                    .flush_interval
+                    .or(defaults.flush_interval)
+                    .unwrap()
+                    .max(1),
+            ),
+        }
+    }
+
+    /// How many events can be buffered while the primary sink is failing. Requests with
+    /// more events than this can never be buffered, so they should be rejected up front.
+    pub fn capacity(&self) -> usize {
+        self.batch_size * 10
+    }
+
+    /// Adds the events to the buffer, flushing it if the batch size was reached. Fails
+    /// with [`BufferFull`] if the events don't fit in the buffer.
+    pub async fn push(&self, events: Vec<Event>) -> Result<()> {
+        let batch = {
+            let mut buffer = self.events.lock().await;
+            if buffer.len() + events.len() > self.capacity() {
+                return Err(BufferFull(events.len()).into());
+            }
+
+            buffer.extend(events);
+            if buffer.len() < self.batch_size {
+                return Ok(());
+            }
+
+            std::mem::take(&mut *buffer)
+        };
+
+        self.insert(batch).await
+    }
+
+    /// Inserts everything that is currently buffered.
+    pub async fn flush(&self) -> Result<()> {
+        let batch = std::mem::take(&mut *self.events.lock().await);
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        self.insert(batch).await
+    }
+
+    /// Spawns a background task that flushes the buffer on every flush interval.
+    pub fn spawn_flusher(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = interval(self.flush_interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.flush().await {
+                    error!("unable to flush events buffer: {e}");
+                }
+            }
+        })
+    }
+
+    async fn insert(&self, batch: Vec<Event>) -> Result<()> {
+        debug!("writing {} events to the sinks", batch.len());
+        match self.sinks.write(Batch::Events(&batch)).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                // put the events back so they can be retried on the next flush. if the primary
+                // sink has been down for long enough that the buffer is full, the oldest events
+                // are dropped, which were already accepted by earlier requests.
+                let mut buffer = self.events.lock().await;
+                let overflow = (buffer.len() + batch.len()).saturating_sub(self.capacity());
+                if overflow > 0 {
+                    error!("events buffer is full, dropping the {overflow} oldest events: {e}");
+                    ServerMetrics::global().record_dropped_events(overflow);
+                }
+
+                let newer =
+                    std::mem::replace(&mut *buffer, batch.into_iter().skip(overflow).collect());
+
+                buffer.extend(newer);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Builds a ClickHouse [`Block`] out of the given events, with the columns
+/// laid out the same way as the [`EVENTS_TABLE`] table.
+pub fn to_block(events: &[Event]) -> Block {
+    let received_at = Utc::now().with_timezone(&Tz::UTC);
+
+    Block::new()
+        .column(
+            "instance_uuid",
+            events
+                .iter()
+                .map(|e| e.instance.to_string())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "name",
+            events.iter().map(|e| e.name.clone()).collect::<Vec<_>>(),
+        )
+        .column(
+            "timestamp",
+            events
+                .iter()
+                .map(|e| e.timestamp.with_timezone(&Tz::UTC))
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "properties",
+            events
+                .iter()
+                .map(|e| Value::Object(e.properties.clone()).to_string())
+                .collect::<Vec<_>>(),
+        )
+        .column("received_at", vec![received_at; events.len()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::sink::Sink;
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+
+    #[derive(Debug)]
+    struct DownSink;
+
+    #[async_trait]
+    impl Sink for DownSink {
+        fn name(&self) -> &'static str {
+            "down"
+        }
+
+        async fn write(&self, _batch: &Batch<'_>) -> Result<()> {
+            Err(anyhow!("sink is down"))
+        }
+    }
+
+    fn events(count: usize) -> Vec<Event> {
+        (0..count)
+            .map(|_| Event {
+                name: "page_view".into(),
+                timestamp: Utc::now(),
+                instance: uuid::Uuid::nil(),
+                properties: Default::default(),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn rejects_events_once_the_buffer_is_full() {
+        let buffer = EventBuffer::new(
+            Sinks::new(vec![Arc::new(DownSink)]),
+            EventsConfig {
+                batch_size: Some(1),
+                ..Default::default()
+            },
+        );
+
+        // failed writes are kept for the next flush, up to ten batches
+        let err = buffer.push(events(1)).await.unwrap_err();
+        assert!(err.downcast_ref::<BufferFull>().is_none());
+        let err = buffer.push(events(9)).await.unwrap_err();
+        assert!(err.downcast_ref::<BufferFull>().is_none());
+
+        let err = buffer.push(events(1)).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<BufferFull>().unwrap().0, 1);
+        assert_eq!(buffer.events.lock().await.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn drops_the_oldest_events_when_a_failed_batch_doesnt_fit() {
+        let buffer = EventBuffer::new(
+            Sinks::new(vec![Arc::new(DownSink)]),
+            EventsConfig {
+                batch_size: Some(2),
+                ..Default::default()
+            },
+        );
+
+        // events that were buffered while the batch was being written
+        let mut newer = events(15);
+        newer[14].name = "newest".into();
+        *buffer.events.lock().await = newer;
+
+        let mut batch = events(10);
+        batch[0].name = "oldest".into();
+
+        // the events that were dropped were accepted by earlier requests, so the caller
+        // doesn't get a [`BufferFull`] for them
+        let err = buffer.insert(batch).await.unwrap_err();
+        assert!(err.downcast_ref::<BufferFull>().is_none());
+
+        let buffered = buffer.events.lock().await;
+        assert_eq!(buffered.len(), buffer.capacity());
+        assert!(buffered.iter().all(|e| e.name != "oldest"));
+        assert_eq!(buffered.last().unwrap().name, "newest");
+    }
+}
@@ -0,0 +1,97 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use analytics_protobufs::ingest_server::Ingest;
+use analytics_protobufs::{IngestStatsRequest, IngestStatsResponse};
+use tokio::sync::Mutex;
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+use crate::collector::snapshot::StatsSnapshot;
+use crate::endpoints::endpoint_manager::EndpointManager;
+use crate::endpoints::service_token::{ServiceTokenError, ServiceTokens};
+use crate::sinks::sink::{Batch, Sinks};
+
+/// Lets instances push their stats to the server, authenticated with the same service
+/// token that the server uses to call them.
+#[derive(Debug, Clone)]
+pub struct IngestService {
+    sinks: Sinks,
+    endpoints: Arc<Mutex<EndpointManager>>,
+    tokens: ServiceTokens,
+}
+
+impl IngestService {
+    pub fn new(
+        sinks: Sinks,
+        endpoints: Arc<Mutex<EndpointManager>>,
+        tokens: ServiceTokens,
+    ) -> Self {
+        IngestService {
+            sinks,
+            endpoints,
+            tokens,
+        }
+    }
+
+    /// Resolves the instance that is pushing, and checks that it sent its service token.
+    async fn authenticate(&self, instance: &str, metadata: &MetadataMap) -> Result<(), Status> {
+        let given = metadata
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.strip_prefix("Bearer ").unwrap_or(v))
+            .ok_or_else(|| Status::unauthenticated("missing `authorization` metadata"))?;
+
+        // every failure looks the same to the caller, so it can't tell if an instance
+        // exists or was finalized without knowing its service token
+        match self.tokens.verify(&self.endpoints, instance, given).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Status::permission_denied(
+                ServiceTokenError::Invalid.to_string(),
+            )),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Ingest for IngestService {
+    async fn ingest_stats(
+        &self,
+        request: Request<IngestStatsRequest>,
+    ) -> Result<Response<IngestStatsResponse>, Status> {
+        let (metadata, _, body) = request.into_parts();
+        let instance = Uuid::parse_str(&body.instance_uuid)
+            .map_err(|_| Status::invalid_argument("`instanceUUID` is not a valid UUID"))?
+            .to_string();
+
+        self.authenticate(&instance, &metadata).await?;
+
+        let stats = body
+            .stats
+            .ok_or_else(|| Status::invalid_argument("`stats` is required"))?;
+
+        let snapshot = StatsSnapshot::from_response(instance.clone(), stats);
+        let batch = [snapshot];
+        self.sinks.write(Batch::Stats(&batch)).await.map_err(|e| {
+            error!("unable to store pushed stats of instance {instance}: {e}");
+            Status::internal("unable to store stats")
+        })?;
+
+        Ok(Response::new(IngestStatsResponse {}))
+    }
+}
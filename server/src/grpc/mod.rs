@@ -0,0 +1,45 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod ingest;
+pub mod otlp;
+
+use std::net::SocketAddr;
+
+use analytics_protobufs::ingest_server::IngestServer;
+use opentelemetry_proto::tonic::collector::logs::v1::logs_service_server::LogsServiceServer;
+use opentelemetry_proto::tonic::collector::metrics::v1::metrics_service_server::MetricsServiceServer;
+use tokio::task::JoinHandle;
+use tonic::transport::Server;
+
+use crate::grpc::ingest::IngestService;
+use crate::grpc::otlp::OtlpService;
+
+/// Runs the gRPC services that the analytics server hosts itself on the given address,
+/// next to the REST API. This is the ingest service, and the OTLP metrics and logs services.
+pub fn serve(addr: SocketAddr, ingest: IngestService, otlp: OtlpService) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        info!("gRPC services are listening on {addr}");
+        if let Err(e) = Server::builder()
+            .add_service(IngestServer::new(ingest))
+            .add_service(MetricsServiceServer::new(otlp.clone()))
+            .add_service(LogsServiceServer::new(otlp))
+            .serve(addr)
+            .await
+        {
+            error!("gRPC services stopped: {e}");
+        }
+    })
+}
@@ -0,0 +1,117 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use opentelemetry_proto::tonic::collector::logs::v1::logs_service_server::LogsService;
+use opentelemetry_proto::tonic::collector::logs::v1::{
+    ExportLogsServiceRequest, ExportLogsServiceResponse,
+};
+use opentelemetry_proto::tonic::collector::metrics::v1::metrics_service_server::MetricsService;
+use opentelemetry_proto::tonic::collector::metrics::v1::{
+    ExportMetricsServiceRequest, ExportMetricsServiceResponse,
+};
+use rocket::http::Status as HttpStatus;
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Response, Status};
+
+use crate::middleware::auth::{resolve_principal, Principal};
+use crate::models::token::Scope;
+use crate::otlp::receiver::OtlpReceiver;
+use crate::prisma::PrismaClient;
+
+/// Receives OTLP metrics and logs over gRPC, authenticated with an API token that has
+/// the `instances:write` scope.
+#[derive(Debug, Clone)]
+pub struct OtlpService {
+    receiver: OtlpReceiver,
+    prisma: Arc<PrismaClient>,
+}
+
+impl OtlpService {
+    pub fn new(receiver: OtlpReceiver, prisma: Arc<PrismaClient>) -> Self {
+        OtlpService { receiver, prisma }
+    }
+
+    async fn authenticate(&self, metadata: &MetadataMap) -> Result<Principal, Status> {
+        let credential = metadata
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| Status::unauthenticated("missing `authorization` metadata"))?;
+
+        let principal = match resolve_principal(&self.prisma, credential).await {
+            Ok(principal) => principal,
+            Err((HttpStatus::InternalServerError, message)) => {
+                return Err(Status::internal(message))
+            }
+            Err((_, message)) => return Err(Status::unauthenticated(message)),
+        };
+
+        if !principal.has_scope(Scope::InstancesWrite) {
+            return Err(Status::permission_denied(format!(
+                "Missing the `{}` scope",
+                Scope::InstancesWrite
+            )));
+        }
+
+        Ok(principal)
+    }
+}
+
+#[tonic::async_trait]
+impl MetricsService for OtlpService {
+    async fn export(
+        &self,
+        request: Request<ExportMetricsServiceRequest>,
+    ) -> Result<Response<ExportMetricsServiceResponse>, Status> {
+        let (metadata, _, body) = request.into_parts();
+        let principal = self.authenticate(&metadata).await?;
+
+        self.receiver
+            .export_metrics(&principal, body)
+            .await
+            .map_err(|e| {
+                error!("unable to store OTLP metrics: {e}");
+                Status::unavailable("unable to store metrics")
+            })?;
+
+        Ok(Response::new(ExportMetricsServiceResponse {
+            partial_success: None,
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl LogsService for OtlpService {
+    async fn export(
+        &self,
+        request: Request<ExportLogsServiceRequest>,
+    ) -> Result<Response<ExportLogsServiceResponse>, Status> {
+        let (metadata, _, body) = request.into_parts();
+        let principal = self.authenticate(&metadata).await?;
+
+        self.receiver
+            .export_logs(&principal, body)
+            .await
+            .map_err(|e| {
+                error!("unable to store OTLP logs: {e}");
+                Status::unavailable("unable to store logs")
+            })?;
+
+        Ok(Response::new(ExportLogsServiceResponse {
+            partial_success: None,
+        }))
+    }
+}
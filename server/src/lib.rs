@@ -28,18 +28,27 @@ pub const COMMIT_HASH: &str = env!("ANALYTICS_SERVER_COMMIT_HASH");
 extern crate log;
 extern crate core;
 
+pub mod alerts;
 pub mod catchers;
 pub mod clickhouse;
+pub mod collector;
 pub mod config;
 pub mod endpoints;
 pub mod errors;
+pub mod events;
+pub mod grpc;
+pub mod logs;
 pub mod macros;
 pub mod middleware;
 pub mod models;
 pub mod null_writer;
+pub mod otlp;
+pub mod prometheus;
 pub mod prisma;
 pub mod routes;
 pub mod sentinel;
 pub mod sentinel_test;
 pub mod server;
 pub mod setup_utils;
+pub mod sinks;
+pub mod snowflake;
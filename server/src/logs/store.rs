@@ -0,0 +1,114 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{anyhow, Result};
+use chrono::{TimeZone, Utc};
+use chrono_tz::Tz;
+use clickhouse_rs::types::Block;
+use uuid::Uuid;
+
+use crate::clickhouse::client::ClickHouse;
+use crate::models::log::{LogLevel, LogLine, LogSearch};
+
+/// The ClickHouse table that log lines are inserted into.
+pub const LOGS_TABLE: &str = "logs";
+
+/// Inserts the log lines into ClickHouse.
+pub async fn store(clickhouse: &ClickHouse, lines: &[LogLine]) -> Result<()> {
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    debug!("inserting {} log lines into clickhouse", lines.len());
+    clickhouse.insert(LOGS_TABLE, to_block(lines)).await
+}
+
+/// Runs the search, and returns the log lines that matched it.
+pub async fn find(clickhouse: &ClickHouse, search: &LogSearch) -> Result<Vec<LogLine>> {
+    let block = clickhouse.query(search.to_sql()).await?;
+    let mut lines = Vec::with_capacity(block.row_count());
+    for row in block.rows() {
+        let instance: String = row.get("instance_uuid")?;
+        let timestamp_ms: i64 = row.get("timestamp_ms")?;
+        let level: String = row.get("level")?;
+        let field_keys: Vec<String> = row.get("field_keys")?;
+        let field_values: Vec<String> = row.get("field_values")?;
+
+        lines.push(LogLine {
+            instance: Uuid::parse_str(&instance)?,
+            timestamp: Utc
+                .timestamp_millis_opt(timestamp_ms)
+                .single()
+                .ok_or_else(|| anyhow!("log line has an invalid timestamp: {timestamp_ms}"))?,
+            level: level.parse().unwrap_or(LogLevel::Info),
+            message: row.get("message")?,
+            fields: field_keys.into_iter().zip(field_values).collect(),
+        });
+    }
+
+    Ok(lines)
+}
+
+/// Builds a ClickHouse [`Block`] out of the given log lines, with the columns
+/// laid out the same way as the [`LOGS_TABLE`] table.
+pub fn to_block(lines: &[LogLine]) -> Block {
+    let received_at = Utc::now().with_timezone(&Tz::UTC);
+
+    Block::new()
+        .column(
+            "instance_uuid",
+            lines
+                .iter()
+                .map(|l| l.instance.to_string())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "timestamp_ms",
+            lines
+                .iter()
+                .map(|l| l.timestamp.timestamp_millis())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "level",
+            lines
+                .iter()
+                .map(|l| l.level.as_str().to_string())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "severity",
+            lines.iter().map(|l| l.level.severity()).collect::<Vec<_>>(),
+        )
+        .column(
+            "message",
+            lines.iter().map(|l| l.message.clone()).collect::<Vec<_>>(),
+        )
+        .column(
+            "field_keys",
+            lines
+                .iter()
+                .map(|l| l.fields.keys().cloned().collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "field_values",
+            lines
+                .iter()
+                .map(|l| l.fields.values().cloned().collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+        )
+        .column("received_at", vec![received_at; lines.len()])
+}
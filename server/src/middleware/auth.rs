@@ -13,40 +13,169 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::Arc;
+
 use crate::config::CONFIG;
 use crate::models::response::ApiError;
+use crate::models::token::{hash_token, parse_scopes, Scope, TOKEN_PREFIX};
+use crate::prisma::{api_token, PrismaClient};
+use chrono::Utc;
 use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome};
 use rocket::Request;
 
-pub struct AuthGuard;
+/// Who made a request, resolved from the `Authorization` header.
+#[derive(Debug, Clone)]
+pub enum Principal {
+    /// Authenticated with the server's `secret_key`. This is how the first users and
+    /// tokens are bootstrapped, and is allowed to do anything.
+    Root,
+
+    /// Authenticated with an API token that was issued to a user.
+    User {
+        id: i64,
+        token_id: i64,
+        scopes: Vec<Scope>,
+    },
+}
+
+impl Principal {
+    /// Checks if this principal was granted the given scope. `admin` implies every scope.
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        match self {
+            Principal::Root => true,
+            Principal::User { scopes, .. } => {
+                scopes.contains(&Scope::Admin) || scopes.contains(&scope)
+            }
+        }
+    }
+
+    /// Returns the ID of the user this principal acts as, if any.
+    pub fn user_id(&self) -> Option<i64> {
+        match self {
+            Principal::Root => None,
+            Principal::User { id, .. } => Some(*id),
+        }
+    }
+
+    /// Checks if this principal can act on behalf of the given user, which is only
+    /// allowed for the user themselves and administrators.
+    pub fn can_access_user(&self, id: i64) -> bool {
+        self.has_scope(Scope::Admin) || self.user_id() == Some(id)
+    }
+
+    /// Checks if this principal can see a resource with the given owner. Resources
+    /// without an owner are only visible to administrators.
+    pub fn can_access_owned(&self, owner: Option<i64>) -> bool {
+        self.has_scope(Scope::Admin) || (owner.is_some() && self.user_id() == owner)
+    }
+}
+
+pub struct AuthGuard {
+    pub principal: Principal,
+}
+
+impl AuthGuard {
+    /// Returns the principal if it was granted the given scope, or a `403` error.
+    pub fn require(self, scope: Scope) -> Result<Principal, ApiError> {
+        if self.principal.has_scope(scope) {
+            Ok(self.principal)
+        } else {
+            Err(ApiError {
+                code: Status::Forbidden.code.to_string(),
+                message: format!("Missing the `{scope}` scope"),
+            })
+        }
+    }
+}
+
+/// Resolves the principal of a request that needs the given scope. Routes take the
+/// guard as a `Result` so they can return the error in the usual response shape.
+pub fn authorize(auth: Result<AuthGuard, ApiError>, scope: Scope) -> Result<Principal, ApiError> {
+    auth.and_then(|guard| guard.require(scope))
+}
+
+fn failure(status: Status, message: &str) -> Outcome<AuthGuard, ApiError> {
+    Outcome::Failure((
+        status,
+        ApiError {
+            code: status.code.to_string(),
+            message: message.into(),
+        },
+    ))
+}
+
+/// Resolves the principal that a credential belongs to, which is either the `secret_key`
+/// or an API token, with an optional `Bearer ` prefix. The gRPC receivers use this directly,
+/// since they can't use the [`AuthGuard`] request guard.
+pub async fn resolve_principal(
+    prisma: &PrismaClient,
+    credential: &str,
+) -> Result<Principal, (Status, &'static str)> {
+    let config = CONFIG.get().cloned().unwrap();
+    let credential = credential
+        .strip_prefix("Bearer ")
+        .unwrap_or(credential)
+        .trim();
+
+    if config.secret_key.is_some() && config.secret_key.unwrap() == credential {
+        return Ok(Principal::Root);
+    }
+
+    if !credential.starts_with(TOKEN_PREFIX) {
+        return Err((Status::Forbidden, "Invalid secret key"));
+    }
+
+    let token = match prisma
+        .api_token()
+        .find_unique(api_token::hash::equals(hash_token(credential)))
+        .exec()
+        .await
+    {
+        Ok(Some(token)) => token,
+        Ok(None) => return Err((Status::Forbidden, "Invalid API token")),
+        Err(e) => {
+            error!("unable to look up API token: {e}");
+            return Err((Status::InternalServerError, "Unable to look up API token"));
+        }
+    };
+
+    if let Err(e) = prisma
+        .api_token()
+        .update(
+            api_token::id::equals(token.id),
+            vec![api_token::last_used_at::set(Some(Utc::now().into()))],
+        )
+        .exec()
+        .await
+    {
+        warn!("unable to update last usage of API token {}: {e}", token.id);
+    }
+
+    Ok(Principal::User {
+        id: token.user_id,
+        token_id: token.id,
+        scopes: parse_scopes(&token.scopes),
+    })
+}
 
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for AuthGuard {
     type Error = ApiError;
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let config = CONFIG.get().cloned().unwrap();
-        // TODO: Match Authorization header against production API token, for people wanting to register with our production instance.
-        return match request.headers().get_one("Authorization") {
-            None => Outcome::Failure((
-                Status::Unauthorized,
-                ApiError {
-                    code: Status::Unauthorized.code.to_string(),
-                    message: "No authorization header specified.".into(),
-                },
-            )),
-            Some(v)
-                if config.secret_key.is_some() && config.secret_key.unwrap() == *v =>
-            {
-                Outcome::Success(AuthGuard {})
-            }
-            Some(_) => Outcome::Failure((
-                Status::Unauthorized,
-                ApiError {
-                    code: Status::Forbidden.code.to_string(),
-                    message: "Invalid secret key".into(),
-                },
-            )),
+        let header = match request.headers().get_one("Authorization") {
+            Some(v) => v,
+            None => return failure(Status::Unauthorized, "No authorization header specified."),
+        };
+
+        let prisma = match request.rocket().state::<Arc<PrismaClient>>() {
+            Some(prisma) => prisma,
+            None => return failure(Status::Forbidden, "Invalid API token"),
         };
+
+        match resolve_principal(prisma, header).await {
+            Ok(principal) => Outcome::Success(AuthGuard { principal }),
+            Err((status, message)) => failure(status, message),
+        }
     }
 }
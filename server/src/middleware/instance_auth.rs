@@ -0,0 +1,127 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::endpoints::endpoint::Endpoint;
+use crate::endpoints::endpoint_manager::EndpointManager;
+use crate::endpoints::service_token::{ServiceTokenError, ServiceTokens};
+use crate::models::response::ApiError;
+
+/// Header that carries the instance UUID when the service token is sent as a bearer token.
+pub const INSTANCE_HEADER: &str = "X-Analytics-Instance";
+
+/// An instance that authenticated with its service token, for routes that instances push
+/// data to. The credentials can either be sent with basic authentication, where the username
+/// is the instance UUID and the password is the service token (which is how Prometheus'
+/// `basic_auth` sends them), or as a bearer token along with the [`INSTANCE_HEADER`] header.
+pub struct InstanceGuard {
+    pub endpoint: Endpoint,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for InstanceGuard {
+    type Error = ApiError;
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let header = match request.headers().get_one("Authorization") {
+            Some(v) => v.trim(),
+            None => return failure(Status::Unauthorized, "No authorization header specified."),
+        };
+
+        let (instance, token) =
+            match parse_credentials(header, request.headers().get_one(INSTANCE_HEADER)) {
+                Some(credentials) => credentials,
+                None => {
+                    return failure(
+                        Status::Unauthorized,
+                        "Expected basic authentication, or a bearer token with the X-Analytics-Instance header",
+                    )
+                }
+            };
+
+        let instance = match Uuid::parse_str(&instance) {
+            Ok(instance) => instance.to_string(),
+            Err(_) => return failure(Status::BadRequest, "Bad Uuid"),
+        };
+
+        let rocket = request.rocket();
+        let (endpoints, tokens) = match (
+            rocket.state::<Arc<Mutex<EndpointManager>>>(),
+            rocket.state::<ServiceTokens>(),
+        ) {
+            (Some(endpoints), Some(tokens)) => (endpoints, tokens),
+            _ => return failure(Status::Forbidden, "Invalid service token"),
+        };
+
+        match tokens.verify(endpoints, &instance, &token).await {
+            Ok(endpoint) => Outcome::Success(InstanceGuard { endpoint }),
+            Err(_) => failure(Status::Forbidden, &ServiceTokenError::Invalid.to_string()),
+        }
+    }
+}
+
+/// Returns the instance UUID and service token from the `Authorization` header, and the
+/// [`INSTANCE_HEADER`] header if a bearer token was sent.
+fn parse_credentials(authorization: &str, instance: Option<&str>) -> Option<(String, String)> {
+    if let Some(encoded) = authorization.strip_prefix("Basic ") {
+        let decoded = String::from_utf8(base64::decode(encoded.trim()).ok()?).ok()?;
+        let (instance, token) = decoded.split_once(':')?;
+
+        return Some((instance.to_string(), token.to_string()));
+    }
+
+    let token = authorization
+        .strip_prefix("Bearer ")
+        .unwrap_or(authorization);
+    Some((instance?.trim().to_string(), token.trim().to_string()))
+}
+
+fn failure(status: Status, message: &str) -> Outcome<InstanceGuard, ApiError> {
+    Outcome::Failure((
+        status,
+        ApiError {
+            code: status.code.to_string(),
+            message: message.into(),
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_basic_and_bearer_credentials() {
+        let basic = format!("Basic {}", base64::encode("waff:owo"));
+        assert_eq!(
+            parse_credentials(&basic, None),
+            Some(("waff".to_string(), "owo".to_string()))
+        );
+
+        assert_eq!(
+            parse_credentials("Bearer owo", Some("waff")),
+            Some(("waff".to_string(), "owo".to_string()))
+        );
+
+        assert_eq!(parse_credentials("Bearer owo", None), None);
+        assert_eq!(parse_credentials("Basic !!!", None), None);
+    }
+}
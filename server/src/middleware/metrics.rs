@@ -0,0 +1,64 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Instant;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Data, Request, Response};
+
+use crate::prometheus::registry::ServerMetrics;
+
+/// When a request was received, which is kept in the request's local cache.
+#[derive(Debug, Clone, Copy)]
+struct RequestStart(Option<Instant>);
+
+/// Fairing that records how many requests every route handled, and how long they took.
+/// Requests that didn't match any route are recorded under the `unmatched` route, so that
+/// scanning the server for random paths doesn't create a new time series for every path.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestMetrics;
+
+#[rocket::async_trait]
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request Metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Some(Instant::now())));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let started = match request.local_cache(|| RequestStart(None)).0 {
+            Some(started) => started,
+            None => return,
+        };
+
+        let route = request
+            .route()
+            .map(|route| route.uri.to_string())
+            .unwrap_or_else(|| "unmatched".into());
+
+        ServerMetrics::global().record_request(
+            request.method().as_str(),
+            &route,
+            response.status().code,
+            started.elapsed(),
+        );
+    }
+}
@@ -13,4 +13,6 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 pub mod auth;
+pub mod instance_auth;
+pub mod metrics;
 pub mod uuid;
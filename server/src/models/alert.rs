@@ -0,0 +1,507 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::clickhouse::client::quote;
+use crate::collector::snapshot::STATS_TABLE;
+use crate::models::stats::{parse_field, parse_step};
+
+/// Longest `for` duration (in seconds) of an alert expression, which is 30 days.
+pub const MAX_DURATION: i64 = 60 * 60 * 24 * 30;
+
+/// The state of an alert rule after it was evaluated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    /// The condition doesn't hold, and never fired.
+    Inactive,
+
+    /// The condition holds, but not for as long as the rule's `for` duration yet.
+    Pending,
+
+    /// The condition has held for at least the rule's `for` duration.
+    Firing,
+
+    /// The condition stopped holding after the rule fired.
+    Resolved,
+}
+
+impl AlertState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertState::Inactive => "inactive",
+            AlertState::Pending => "pending",
+            AlertState::Firing => "firing",
+            AlertState::Resolved => "resolved",
+        }
+    }
+
+    /// Returns the state that comes after this one, along with when the condition started
+    /// to hold if it still does. `since` is when it started to hold before this evaluation.
+    pub fn next(
+        &self,
+        holds: bool,
+        since: Option<DateTime<Utc>>,
+        duration: i64,
+        now: DateTime<Utc>,
+    ) -> (AlertState, Option<DateTime<Utc>>) {
+        if !holds {
+            return match self {
+                AlertState::Firing | AlertState::Resolved => (AlertState::Resolved, None),
+                AlertState::Inactive | AlertState::Pending => (AlertState::Inactive, None),
+            };
+        }
+
+        let since = match self {
+            AlertState::Pending | AlertState::Firing => since.unwrap_or(now),
+            AlertState::Inactive | AlertState::Resolved => now,
+        };
+
+        if (now - since).num_seconds() >= duration {
+            (AlertState::Firing, Some(since))
+        } else {
+            (AlertState::Pending, Some(since))
+        }
+    }
+}
+
+impl FromStr for AlertState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inactive" => Ok(AlertState::Inactive),
+            "pending" => Ok(AlertState::Pending),
+            "firing" => Ok(AlertState::Firing),
+            "resolved" => Ok(AlertState::Resolved),
+            _ => Err(format!("unknown alert state `{s}`")),
+        }
+    }
+}
+
+impl Display for AlertState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Represents how a rule's notifications are sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertChannel {
+    Webhook,
+    Email,
+}
+
+impl AlertChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertChannel::Webhook => "webhook",
+            AlertChannel::Email => "email",
+        }
+    }
+}
+
+impl FromStr for AlertChannel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "webhook" => Ok(AlertChannel::Webhook),
+            "email" => Ok(AlertChannel::Email),
+            _ => Err(format!("unknown alert channel `{s}`")),
+        }
+    }
+}
+
+impl Display for AlertChannel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Parses the channels that are stored on a rule, skipping the ones that aren't known.
+pub fn parse_channels(channels: &[String]) -> Vec<AlertChannel> {
+    channels.iter().filter_map(|c| c.parse().ok()).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Eq,
+    Ne,
+}
+
+impl Comparison {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+        }
+    }
+
+    pub fn holds(&self, left: f64, right: f64) -> bool {
+        match self {
+            Comparison::Gt => left > right,
+            Comparison::Ge => left >= right,
+            Comparison::Lt => left < right,
+            Comparison::Le => left <= right,
+            Comparison::Eq => left == right,
+            Comparison::Ne => left != right,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl Operator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+        }
+    }
+}
+
+/// One side of an alert condition, which is arithmetic over numbers and stats fields.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Number(f64),
+    Field(Vec<String>),
+    Binary(Box<Operand>, Operator, Box<Operand>),
+}
+
+impl Operand {
+    /// Builds the ClickHouse expression for this operand over the `data` column.
+    pub fn to_sql(&self) -> String {
+        match self {
+            Operand::Number(n) => n.to_string(),
+            Operand::Field(path) => format!(
+                "JSONExtractFloat(data, {})",
+                path.iter()
+                    .map(|p| format!("'{p}'"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Operand::Binary(left, op, right) => {
+                format!("({} {} {})", left.to_sql(), op.as_str(), right.to_sql())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(Operator),
+    Cmp(Comparison),
+    Open,
+    Close,
+}
+
+/// A validated alert expression, like `data.heap_used > 0.9 * data.heap_max for 5m`. Both
+/// sides support `+`, `-`, `*`, `/` and parentheses over numbers and stats fields, and the
+/// optional `for` is how long the condition has to hold before the rule fires.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertCondition {
+    pub left: Operand,
+    pub comparison: Comparison,
+    pub right: Operand,
+
+    /// In seconds, `0` fires as soon as the condition holds.
+    pub duration: i64,
+}
+
+impl AlertCondition {
+    pub fn parse(expression: &str) -> Result<AlertCondition, String> {
+        let (tokens, duration) = tokenize(expression)?;
+        let mut parser = Parser { tokens, pos: 0 };
+
+        let left = parser.expression()?;
+        let comparison = match parser.next() {
+            Some(Token::Cmp(comparison)) => comparison,
+            _ => return Err("expected a comparison (`>`, `>=`, `<`, `<=`, `==` or `!=`)".into()),
+        };
+
+        let right = parser.expression()?;
+        if parser.pos < parser.tokens.len() {
+            return Err("unexpected input after the condition".into());
+        }
+
+        let duration = match duration {
+            Some(duration) => parse_step(duration)
+                .map_err(|_| format!("`for` is not a valid duration: {duration}"))?,
+            None => 0,
+        };
+
+        if duration > MAX_DURATION {
+            return Err(format!(
+                "`for` can't be longer than {} days",
+                MAX_DURATION / (60 * 60 * 24)
+            ));
+        }
+
+        Ok(AlertCondition {
+            left,
+            comparison,
+            right,
+            duration,
+        })
+    }
+
+    /// Builds the query for both sides of the condition over the latest snapshot of an
+    /// instance, which has to be at most `max_age` seconds old.
+    pub fn to_sql(&self, instance_uuid: &str, max_age: u64) -> String {
+        format!(
+            "SELECT toFloat64({}) AS lhs, toFloat64({}) AS rhs FROM {STATS_TABLE} \
+             WHERE instance_uuid = {} AND snapshot_date >= now() - INTERVAL {max_age} SECOND \
+             ORDER BY snapshot_date DESC LIMIT 1",
+            self.left.to_sql(),
+            self.right.to_sql(),
+            quote(instance_uuid)
+        )
+    }
+}
+
+/// Splits the expression into tokens, and returns whatever comes after `for` as-is.
+fn tokenize(expression: &str) -> Result<(Vec<Token>, Option<&str>), String> {
+    let mut tokens = vec![];
+    let chars = expression.char_indices().collect::<Vec<_>>();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (start, c) = chars[i];
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push(Token::Open);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                i += 1;
+            }
+            '+' | '-' | '*' | '/' => {
+                tokens.push(Token::Op(match c {
+                    '+' => Operator::Add,
+                    '-' => Operator::Sub,
+                    '*' => Operator::Mul,
+                    _ => Operator::Div,
+                }));
+
+                i += 1;
+            }
+            '>' | '<' | '=' | '!' => {
+                let equals = chars.get(i + 1).map(|(_, c)| *c == '=').unwrap_or(false);
+                let comparison = match (c, equals) {
+                    ('>', true) => Comparison::Ge,
+                    ('>', false) => Comparison::Gt,
+                    ('<', true) => Comparison::Le,
+                    ('<', false) => Comparison::Lt,
+                    ('=', true) => Comparison::Eq,
+                    ('!', true) => Comparison::Ne,
+                    _ => return Err(format!("unexpected `{c}` at {start}")),
+                };
+
+                tokens.push(Token::Cmp(comparison));
+                i += if equals { 2 } else { 1 };
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut end = i;
+                while end < chars.len() && (chars[end].1.is_ascii_digit() || chars[end].1 == '.') {
+                    end += 1;
+                }
+
+                let text =
+                    &expression[start..chars.get(end).map(|(i, _)| *i).unwrap_or(expression.len())];
+                let number = text
+                    .parse::<f64>()
+                    .map_err(|_| format!("`{text}` is not a valid number"))?;
+
+                tokens.push(Token::Number(number));
+                i = end;
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut end = i;
+                while end < chars.len()
+                    && (chars[end].1.is_ascii_alphanumeric()
+                        || chars[end].1 == '_'
+                        || chars[end].1 == '.')
+                {
+                    end += 1;
+                }
+
+                let next = chars.get(end).map(|(i, _)| *i).unwrap_or(expression.len());
+                let text = &expression[start..next];
+                if text == "for" {
+                    return Ok((tokens, Some(expression[next..].trim())));
+                }
+
+                tokens.push(Token::Ident(text.to_string()));
+                i = end;
+            }
+            _ => return Err(format!("unexpected `{c}` at {start}")),
+        }
+    }
+
+    Ok((tokens, None))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// `term (('+' | '-') term)*`
+    fn expression(&mut self) -> Result<Operand, String> {
+        let mut left = self.term()?;
+        while let Some(Token::Op(op @ (Operator::Add | Operator::Sub))) = self.peek().cloned() {
+            self.pos += 1;
+            left = Operand::Binary(Box::new(left), op, Box::new(self.term()?));
+        }
+
+        Ok(left)
+    }
+
+    /// `factor (('*' | '/') factor)*`
+    fn term(&mut self) -> Result<Operand, String> {
+        let mut left = self.factor()?;
+        while let Some(Token::Op(op @ (Operator::Mul | Operator::Div))) = self.peek().cloned() {
+            self.pos += 1;
+            left = Operand::Binary(Box::new(left), op, Box::new(self.factor()?));
+        }
+
+        Ok(left)
+    }
+
+    /// A number, a field, `-factor` or a parenthesized expression.
+    fn factor(&mut self) -> Result<Operand, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Operand::Number(n)),
+            Some(Token::Ident(field)) => parse_field(&field)
+                .map(Operand::Field)
+                .map_err(|_| format!("`{field}` is not a valid field path")),
+            Some(Token::Op(Operator::Sub)) => Ok(Operand::Binary(
+                Box::new(Operand::Number(0.0)),
+                Operator::Sub,
+                Box::new(self.factor()?),
+            )),
+            Some(Token::Open) => {
+                let inner = self.expression()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(inner),
+                    _ => Err("expected a closing `)`".into()),
+                }
+            }
+            _ => Err("expected a number, a field or `(`".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    #[test]
+    fn parses_conditions() {
+        let condition =
+            AlertCondition::parse("data.heap_used > 0.9 * data.heap_max for 5m").unwrap();
+
+        assert_eq!(condition.comparison, Comparison::Gt);
+        assert_eq!(condition.duration, 300);
+        assert_eq!(
+            condition.to_sql("waff", 600),
+            "SELECT toFloat64(JSONExtractFloat(data, 'heap_used')) AS lhs, toFloat64((0.9 * JSONExtractFloat(data, 'heap_max'))) AS rhs FROM instance_stats WHERE instance_uuid = 'waff' AND snapshot_date >= now() - INTERVAL 600 SECOND ORDER BY snapshot_date DESC LIMIT 1"
+        );
+
+        let condition = AlertCondition::parse("(data.a + data.b) / 2 <= -1").unwrap();
+        assert_eq!(condition.comparison, Comparison::Le);
+        assert_eq!(condition.duration, 0);
+        assert_eq!(
+            condition.left.to_sql(),
+            "((JSONExtractFloat(data, 'a') + JSONExtractFloat(data, 'b')) / 2)"
+        );
+
+        assert!(AlertCondition::parse("data.heap_used").is_err());
+        assert!(AlertCondition::parse("data.heap_used > ").is_err());
+        assert!(AlertCondition::parse("data.heap_used > 1 for soon").is_err());
+        assert!(AlertCondition::parse("data.heap_used > 1 for 30d").is_ok());
+        assert!(AlertCondition::parse("data.heap_used > 1 for 31d").is_err());
+        assert!(AlertCondition::parse("data.x > 1 for 1000000000000000000s").is_err());
+        assert!(AlertCondition::parse("data.heap'); DROP TABLE instance_stats; -- > 1").is_err());
+        assert!(AlertCondition::parse("(data.heap_used > 1").is_err());
+    }
+
+    #[test]
+    fn moves_through_states() {
+        let start = Utc.timestamp_opt(1685577600, 0).unwrap();
+        let later = |secs| start + Duration::seconds(secs);
+
+        let (state, since) = AlertState::Inactive.next(true, None, 300, start);
+        assert_eq!((state, since), (AlertState::Pending, Some(start)));
+
+        let (state, since) = state.next(true, since, 300, later(120));
+        assert_eq!((state, since), (AlertState::Pending, Some(start)));
+
+        let (state, since) = state.next(true, since, 300, later(300));
+        assert_eq!((state, since), (AlertState::Firing, Some(start)));
+
+        let (state, since) = state.next(false, since, 300, later(360));
+        assert_eq!((state, since), (AlertState::Resolved, None));
+
+        let (state, _) = AlertState::Pending.next(false, Some(start), 300, later(60));
+        assert_eq!(state, AlertState::Inactive);
+
+        let (state, _) = AlertState::Resolved.next(true, None, 0, later(600));
+        assert_eq!(state, AlertState::Firing);
+
+        // rules that were stored before `for` had an upper bound
+        let (state, _) = AlertState::Pending.next(true, Some(start), i64::MAX, later(600));
+        assert_eq!(state, AlertState::Pending);
+    }
+}
@@ -13,6 +13,237 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-/// Represents a dashboard's content.
-#[derive(Debug, Clone)]
-pub struct Dashboard;
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::models::stats::{parse_field, parse_step, Aggregation};
+
+/// The only layout version that is currently supported.
+pub const DASHBOARD_VERSION: u8 = 1;
+
+/// How many columns the dashboard grid has.
+pub const GRID_COLUMNS: u16 = 24;
+
+/// Maximum amount of panels a single dashboard can have.
+pub const MAX_PANELS: usize = 100;
+
+/// Represents a dashboard's content, which is stored as the `data` JSON column of
+/// the `Dashboard` model and served as `dashboard.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dashboard {
+    /// The layout version, which must be [`DASHBOARD_VERSION`].
+    pub version: u8,
+
+    /// How often the web client should refresh the panels, like `30s` or `5m`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh: Option<String>,
+
+    #[serde(default)]
+    pub panels: Vec<Panel>,
+}
+
+/// Represents a single chart, stat or table on a dashboard.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Panel {
+    pub id: String,
+    pub title: String,
+    #[serde(rename = "type")]
+    pub kind: PanelKind,
+    pub query: PanelQuery,
+    pub grid: GridPos,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PanelKind {
+    Line,
+    Bar,
+    Stat,
+    Table,
+}
+
+/// Represents the `GET /instances/<id>/stats` query that a panel renders.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PanelQuery {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    pub aggregate: Aggregation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<String>,
+}
+
+/// Represents where a panel sits on the grid, in grid units.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GridPos {
+    pub x: u16,
+    pub y: u16,
+    pub w: u16,
+    pub h: u16,
+}
+
+impl Dashboard {
+    /// Deserializes and validates a dashboard's `data`, returning every problem
+    /// that was found instead of only the first one.
+    pub fn from_json(value: Value) -> Result<Dashboard, Vec<String>> {
+        let dashboard =
+            serde_json::from_value::<Dashboard>(value).map_err(|e| vec![e.to_string()])?;
+
+        dashboard.validate()?;
+        Ok(dashboard)
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        if self.version != DASHBOARD_VERSION {
+            errors.push(format!(
+                "unsupported dashboard version {}, expected {DASHBOARD_VERSION}",
+                self.version
+            ));
+        }
+
+        if let Some(refresh) = &self.refresh {
+            if let Err(e) = parse_step(refresh) {
+                errors.push(e.replace("`step`", "`refresh`"));
+            }
+        }
+
+        if self.panels.len() > MAX_PANELS {
+            errors.push(format!(
+                "a dashboard can't have more than {MAX_PANELS} panels"
+            ));
+        }
+
+        let mut ids = HashSet::new();
+        for (i, panel) in self.panels.iter().enumerate() {
+            let prefix = format!("panels[{i}]");
+            if panel.id.trim().is_empty() {
+                errors.push(format!("{prefix}: `id` can't be empty"));
+            } else if !ids.insert(panel.id.as_str()) {
+                errors.push(format!("{prefix}: duplicate panel id `{}`", panel.id));
+            }
+
+            if panel.title.len() > 256 {
+                errors.push(format!(
+                    "{prefix}: `title` can't be longer than 256 characters"
+                ));
+            }
+
+            let grid = panel.grid;
+            if grid.w == 0 || grid.h == 0 {
+                errors.push(format!(
+                    "{prefix}: `grid.w` and `grid.h` must be at least 1"
+                ));
+            }
+
+            // widened, so a huge `x` can't overflow past the grid
+            if u32::from(grid.x) + u32::from(grid.w) > u32::from(GRID_COLUMNS) {
+                errors.push(format!(
+                    "{prefix}: panel doesn't fit in the {GRID_COLUMNS} column grid"
+                ));
+            }
+
+            match &panel.query.field {
+                Some(field) => {
+                    if let Err(e) = parse_field(field) {
+                        errors.push(format!("{prefix}: {e}"));
+                    }
+                }
+                None if panel.query.aggregate != Aggregation::Count => errors.push(format!(
+                    "{prefix}: `query.field` is required for the `{}` aggregation",
+                    panel.query.aggregate
+                )),
+                None => {}
+            }
+
+            if let Some(step) = &panel.query.step {
+                if let Err(e) = parse_step(step) {
+                    errors.push(format!("{prefix}: {e}"));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn accepts_valid_dashboards() {
+        let dashboard = Dashboard::from_json(json!({
+            "version": 1,
+            "refresh": "30s",
+            "panels": [
+                {
+                    "id": "heap",
+                    "title": "Heap usage",
+                    "type": "line",
+                    "query": { "field": "data.heap.used", "aggregate": "avg", "step": "5m" },
+                    "grid": { "x": 0, "y": 0, "w": 12, "h": 8 }
+                },
+                {
+                    "id": "snapshots",
+                    "title": "Snapshots",
+                    "type": "stat",
+                    "query": { "aggregate": "count" },
+                    "grid": { "x": 12, "y": 0, "w": 12, "h": 8 }
+                }
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(dashboard.panels.len(), 2);
+        assert_eq!(dashboard.panels[0].kind, PanelKind::Line);
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let errors = Dashboard::from_json(json!({
+            "version": 2,
+            "panels": [
+                {
+                    "id": "a",
+                    "title": "A",
+                    "type": "bar",
+                    "query": { "aggregate": "max" },
+                    "grid": { "x": 20, "y": 0, "w": 8, "h": 4 }
+                },
+                {
+                    "id": "a",
+                    "title": "B",
+                    "type": "table",
+                    "query": { "field": "x'; --", "aggregate": "last", "step": "soon" },
+                    "grid": { "x": 0, "y": 0, "w": 0, "h": 4 }
+                },
+                {
+                    "id": "c",
+                    "title": "C",
+                    "type": "stat",
+                    "query": { "aggregate": "count" },
+                    "grid": { "x": 65535, "y": 0, "w": 1, "h": 4 }
+                }
+            ]
+        }))
+        .unwrap_err();
+
+        assert_eq!(errors.len(), 8, "{errors:?}");
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(Dashboard::from_json(json!({ "version": 1, "widgets": [] })).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+/// Maximum size (in bytes) of an event's properties when serialized as JSON.
+pub const MAX_PROPERTIES_SIZE: usize = 64 * 1024;
+
+static EVENT_NAME_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[A-Za-z0-9_.:/\-]{1,128}$").unwrap());
+
+/// Represents an event as it was sent to the Events API, before it was validated.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RawEvent {
+    pub name: Option<String>,
+
+    /// Either an RFC 3339 timestamp or a UNIX timestamp in milliseconds. If
+    /// this is not present, the time the event was received is used.
+    pub timestamp: Option<Value>,
+
+    /// The UUID of the instance that this event belongs to.
+    pub instance: Option<String>,
+
+    #[serde(default)]
+    pub properties: Option<Value>,
+}
+
+/// Represents a validated event that is ready to be inserted into ClickHouse.
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub name: String,
+    pub timestamp: DateTime<Utc>,
+    pub instance: Uuid,
+    pub properties: Map<String, Value>,
+}
+
+impl TryFrom<RawEvent> for Event {
+    type Error = String;
+
+    fn try_from(raw: RawEvent) -> Result<Self, Self::Error> {
+        let name =
+            match raw.name {
+                Some(name) if EVENT_NAME_REGEX.is_match(&name) => name,
+                Some(_) => return Err(
+                    "`name` must be 1-128 characters of letters, digits, `_`, `.`, `:`, `/` or `-`"
+                        .into(),
+                ),
+                None => return Err("missing `name`".into()),
+            };
+
+        let instance = match raw.instance {
+            Some(instance) => Uuid::parse_str(&instance)
+                .map_err(|_| format!("`instance` is not a valid UUID: {instance}"))?,
+            None => return Err("missing `instance`".into()),
+        };
+
+        let now = Utc::now();
+        let timestamp = parse_timestamp("timestamp", raw.timestamp, now)?;
+
+        let properties = match raw.properties {
+            None | Some(Value::Null) => Map::new(),
+            Some(Value::Object(map)) => map,
+            Some(_) => return Err("`properties` must be an object".into()),
+        };
+
+        if Value::Object(properties.clone()).to_string().len() > MAX_PROPERTIES_SIZE {
+            return Err(format!(
+                "`properties` can't be larger than {MAX_PROPERTIES_SIZE} bytes"
+            ));
+        }
+
+        Ok(Event {
+            name,
+            timestamp,
+            instance,
+            properties,
+        })
+    }
+}
+
+/// Parses the `timestamp` of an entry, which is either an RFC 3339 date or a UNIX timestamp
+/// in milliseconds. Entries without one use `now`, and entries can't be more than a day
+/// in the future. `name` is the key the timestamp was sent as, for the error messages.
+pub fn parse_timestamp(
+    name: &str,
+    value: Option<Value>,
+    now: DateTime<Utc>,
+) -> Result<DateTime<Utc>, String> {
+    let timestamp = match value {
+        None | Some(Value::Null) => now,
+        Some(Value::String(s)) => DateTime::parse_from_rfc3339(&s)
+            .map(|dt| dt.with_timezone(&Utc))
+            .map_err(|_| format!("`{name}` is not a valid RFC 3339 date: {s}"))?,
+        Some(Value::Number(n)) => n
+            .as_i64()
+            .and_then(|ms| Utc.timestamp_millis_opt(ms).single())
+            .ok_or_else(|| format!("`{name}` is not a valid UNIX timestamp: {n}"))?,
+        Some(_) => {
+            return Err(format!(
+                "`{name}` must be an RFC 3339 date or a UNIX timestamp in milliseconds"
+            ))
+        }
+    };
+
+    if timestamp > now + Duration::days(1) {
+        return Err(format!("`{name}` can't be more than a day in the future"));
+    }
+
+    Ok(timestamp)
+}
+
+/// Splits a request body into its JSON entries. The body can either be a single JSON
+/// object, a JSON array of objects or newline-delimited JSON if `ndjson` is true. Lines
+/// of newline-delimited JSON that can't be parsed are returned as errors, so one bad
+/// line doesn't reject the whole batch.
+pub fn parse_batch(body: &str, ndjson: bool) -> Result<Vec<Result<Value, String>>, String> {
+    if ndjson {
+        return Ok(body
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str::<Value>(line).map_err(|e| e.to_string()))
+            .collect());
+    }
+
+    match serde_json::from_str::<Value>(body) {
+        Ok(Value::Array(values)) => Ok(values.into_iter().map(Ok).collect()),
+        Ok(value @ Value::Object(_)) => Ok(vec![Ok(value)]),
+        Ok(_) => Err("body must be a JSON object or an array of objects".into()),
+        Err(e) => Err(format!("body is not valid JSON: {e}")),
+    }
+}
+
+/// Parses a request body into events, see [`parse_batch`] for the accepted formats.
+/// Every entry is validated on its own, so one bad event doesn't reject the whole batch.
+pub fn parse_events(body: &str, ndjson: bool) -> Result<Vec<Result<Event, String>>, String> {
+    Ok(parse_batch(body, ndjson)?
+        .into_iter()
+        .map(|value| {
+            let raw = serde_json::from_value::<RawEvent>(value?).map_err(|e| e.to_string())?;
+            Event::try_from(raw)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INSTANCE: &str = "a8e7c6e2-5b4b-4b5a-9a3a-6f0b0b3c2d1e";
+
+    #[test]
+    fn parses_single_events_and_arrays() {
+        let single = parse_events(
+            &format!(
+                r#"{{"name":"user.login","instance":"{INSTANCE}","timestamp":1685577600000}}"#
+            ),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(single.len(), 1);
+        let event = single[0].as_ref().unwrap();
+        assert_eq!(event.name, "user.login");
+        assert_eq!(event.timestamp.timestamp(), 1685577600);
+
+        let batch = parse_events(
+            &format!(
+                r#"[{{"name":"a","instance":"{INSTANCE}"}},{{"name":"","instance":"{INSTANCE}"}}]"#
+            ),
+            false,
+        )
+        .unwrap();
+
+        assert!(batch[0].is_ok());
+        assert!(batch[1].is_err());
+    }
+
+    #[test]
+    fn parses_ndjson_line_by_line() {
+        let body = format!(
+            "{{\"name\":\"a\",\"instance\":\"{INSTANCE}\",\"properties\":{{\"x\":1}}}}\n\n{{not json}}\n{{\"name\":\"b\",\"instance\":\"nope\"}}\n"
+        );
+
+        let events = parse_events(&body, true).unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].as_ref().unwrap().properties["x"], 1);
+        assert!(events[1].is_err());
+        assert_eq!(
+            events[2].as_ref().unwrap_err(),
+            "`instance` is not a valid UUID: nope"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_timestamps_and_properties() {
+        let event = |extra: &str| {
+            parse_events(
+                &format!(r#"{{"name":"a","instance":"{INSTANCE}",{extra}}}"#),
+                false,
+            )
+            .unwrap()
+            .remove(0)
+        };
+
+        assert!(event(r#""timestamp":"yesterday""#).is_err());
+        assert!(event(r#""timestamp":"2023-06-01T00:00:00Z""#).is_ok());
+        assert!(event(r#""timestamp":"2999-06-01T00:00:00Z""#).is_err());
+        assert!(event(r#""properties":[1,2,3]"#).is_err());
+    }
+}
@@ -0,0 +1,500 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Utc};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use crate::clickhouse::client::quote;
+use crate::logs::store::LOGS_TABLE;
+use crate::models::event::{parse_batch, parse_timestamp};
+use crate::models::stats::parse_time;
+
+/// Maximum size (in bytes) of a log line's message.
+pub const MAX_MESSAGE_SIZE: usize = 32 * 1024;
+
+/// Maximum size (in bytes) of a log line's extra fields, keys and values included.
+pub const MAX_FIELDS_SIZE: usize = 64 * 1024;
+
+/// Maximum amount of log lines a single search can return.
+pub const MAX_SEARCH_LIMIT: usize = 1000;
+
+static FIELD_NAME_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[A-Za-z0-9_.@\-]{1,128}$").unwrap());
+
+/// Represents the level of a log line, from least to most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl LogLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Trace => "trace",
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+            LogLevel::Fatal => "fatal",
+        }
+    }
+
+    /// Returns how severe this level is, which is what level filters compare against.
+    pub fn severity(&self) -> u8 {
+        match self {
+            LogLevel::Trace => 1,
+            LogLevel::Debug => 2,
+            LogLevel::Info => 3,
+            LogLevel::Warn => 4,
+            LogLevel::Error => 5,
+            LogLevel::Fatal => 6,
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "trace" => Ok(LogLevel::Trace),
+            "debug" => Ok(LogLevel::Debug),
+            "info" | "information" => Ok(LogLevel::Info),
+            "warn" | "warning" => Ok(LogLevel::Warn),
+            "error" | "err" => Ok(LogLevel::Error),
+            "fatal" | "critical" | "panic" => Ok(LogLevel::Fatal),
+            _ => Err(format!(
+                "unknown level `{s}`, expected one of: trace, debug, info, warn, error, fatal"
+            )),
+        }
+    }
+}
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Represents a validated log line that is ready to be inserted into ClickHouse.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LogLine {
+    pub instance: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub level: LogLevel,
+    pub message: String,
+
+    /// Every other key of the line, like `thread.name`. Values that aren't strings are
+    /// kept as their JSON representation.
+    pub fields: BTreeMap<String, String>,
+}
+
+impl LogLine {
+    /// Validates a single log line. Besides `instance`, `timestamp`, `level` and `message`,
+    /// the keys that the Logstash formatter of `setup_utils::setup_logging` writes are
+    /// understood, so `@timestamp` and `log.level` can be used instead. `instance` is used
+    /// for lines that don't have one.
+    pub fn from_value(
+        value: Value,
+        instance: Option<Uuid>,
+        now: DateTime<Utc>,
+    ) -> Result<LogLine, String> {
+        let mut object = match value {
+            Value::Object(object) => object,
+            _ => return Err("log line must be an object".into()),
+        };
+
+        let instance = match take(&mut object, &["instance"]) {
+            Some((_, Value::String(s))) => {
+                Uuid::parse_str(&s).map_err(|_| format!("`instance` is not a valid UUID: {s}"))?
+            }
+            Some(_) => return Err("`instance` must be a string".into()),
+            None => instance.ok_or("missing `instance`")?,
+        };
+
+        let timestamp = match take(&mut object, &["@timestamp", "timestamp"]) {
+            Some((key, value)) => parse_timestamp(key, Some(value), now)?,
+            None => now,
+        };
+
+        let level = match take(&mut object, &["log.level", "level"]) {
+            Some((key, Value::String(s))) => {
+                s.parse().map_err(|e| format!("`{key}` has an {e}"))?
+            }
+            Some((key, _)) => return Err(format!("`{key}` must be a string")),
+            None => LogLevel::Info,
+        };
+
+        let message = match take(&mut object, &["message", "msg"]) {
+            Some((_, Value::String(s))) => s,
+            Some((key, _)) => return Err(format!("`{key}` must be a string")),
+            None => return Err("missing `message`".into()),
+        };
+
+        if message.len() > MAX_MESSAGE_SIZE {
+            return Err(format!(
+                "`message` can't be larger than {MAX_MESSAGE_SIZE} bytes"
+            ));
+        }
+
+        object.remove("@version");
+        let fields = object
+            .into_iter()
+            .map(|(key, value)| match value {
+                Value::String(s) => (key, s),
+                value => (key, value.to_string()),
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        if fields.iter().map(|(k, v)| k.len() + v.len()).sum::<usize>() > MAX_FIELDS_SIZE {
+            return Err(format!(
+                "the fields of a log line can't be larger than {MAX_FIELDS_SIZE} bytes"
+            ));
+        }
+
+        Ok(LogLine {
+            instance,
+            timestamp,
+            level,
+            message,
+            fields,
+        })
+    }
+}
+
+/// Removes the first of the given keys that has a non-null value, along with the others.
+fn take(object: &mut Map<String, Value>, keys: &[&'static str]) -> Option<(&'static str, Value)> {
+    let mut found = None;
+    for key in keys {
+        match object.remove(*key) {
+            Some(Value::Null) | None => {}
+            Some(value) if found.is_none() => found = Some((*key, value)),
+            Some(_) => {}
+        }
+    }
+
+    found
+}
+
+/// Parses a request body into log lines, see [`parse_batch`] for the accepted formats.
+/// Every line is validated on its own, so one bad line doesn't reject the whole batch.
+pub fn parse_logs(
+    body: &str,
+    ndjson: bool,
+    instance: Option<Uuid>,
+) -> Result<Vec<Result<LogLine, String>>, String> {
+    let now = Utc::now();
+    Ok(parse_batch(body, ndjson)?
+        .into_iter()
+        .map(|value| LogLine::from_value(value?, instance, now))
+        .collect())
+}
+
+/// Represents how the `q` parameter of a search is matched against messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    /// Messages that contain `q`, ignoring case.
+    Substring,
+
+    /// Messages that contain every word of `q` as a whole word, ignoring case. This can
+    /// use the token index of the logs table, so it is a lot faster than substrings.
+    Token,
+}
+
+impl FromStr for MatchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "substring" => Ok(MatchMode::Substring),
+            "token" => Ok(MatchMode::Token),
+            _ => Err(format!(
+                "unknown mode `{s}`, expected one of: substring, token"
+            )),
+        }
+    }
+}
+
+/// Represents a validated `GET /api/logs/search` query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogSearch {
+    /// The instances to search, or `None` to search every instance.
+    pub instances: Option<Vec<String>>,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+
+    /// Only lines at this level or a more severe one are returned.
+    pub level: Option<LogLevel>,
+    pub query: Option<String>,
+    pub mode: MatchMode,
+
+    /// Fields that lines must have with exactly the given value.
+    pub fields: Vec<(String, String)>,
+    pub limit: usize,
+}
+
+/// Represents the log lines that are returned by `GET /api/logs/search`, newest first.
+#[derive(Debug, Clone, Serialize)]
+pub struct LogSearchResults {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub logs: Vec<LogLine>,
+}
+
+impl LogSearch {
+    /// Validates the raw query parameters. `to` defaults to now, `from` defaults to an hour
+    /// before `to`, `mode` defaults to `substring` and `limit` defaults to `100`. Field
+    /// filters are written as `key:value`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn parse(
+        instances: Option<Vec<String>>,
+        from: Option<&str>,
+        to: Option<&str>,
+        level: Option<&str>,
+        query: Option<&str>,
+        mode: Option<&str>,
+        fields: &[String],
+        limit: Option<usize>,
+    ) -> Result<LogSearch, String> {
+        let to = match to {
+            Some(to) => parse_time(to).map_err(|e| format!("`to` {e}"))?,
+            None => Utc::now(),
+        };
+
+        let from = match from {
+            Some(from) => parse_time(from).map_err(|e| format!("`from` {e}"))?,
+            None => to - Duration::hours(1),
+        };
+
+        if from >= to {
+            return Err("`from` must be before `to`".into());
+        }
+
+        let level = match level {
+            Some(level) => Some(level.parse::<LogLevel>()?),
+            None => None,
+        };
+
+        let mode = match mode {
+            Some(mode) => mode.parse::<MatchMode>()?,
+            None => MatchMode::Substring,
+        };
+
+        let query = query
+            .map(str::trim)
+            .filter(|q| !q.is_empty())
+            .map(String::from);
+
+        if mode == MatchMode::Token && query.as_deref().map(tokens).unwrap_or_default().is_empty() {
+            return Err("`q` must contain at least one word to search by token".into());
+        }
+
+        let fields = fields
+            .iter()
+            .map(|field| match field.split_once(':') {
+                Some((key, value)) if FIELD_NAME_REGEX.is_match(key) => {
+                    Ok((key.to_string(), value.to_string()))
+                }
+                _ => Err(format!(
+                    "`field` must be a `key:value` pair with a valid key: {field}"
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let limit = match limit {
+            Some(limit) if limit == 0 || limit > MAX_SEARCH_LIMIT => {
+                return Err(format!("`limit` must be between 1 and {MAX_SEARCH_LIMIT}"))
+            }
+            Some(limit) => limit,
+            None => 100,
+        };
+
+        Ok(LogSearch {
+            instances,
+            from,
+            to,
+            level,
+            query,
+            mode,
+            fields,
+            limit,
+        })
+    }
+
+    /// Builds the ClickHouse query for this search. Values that can't be validated, like
+    /// `q` and field values, are quoted.
+    pub fn to_sql(&self) -> String {
+        let mut conditions = vec![
+            format!(
+                "timestamp >= toDateTime({}, 'UTC') AND timestamp <= toDateTime({}, 'UTC')",
+                self.from.timestamp(),
+                self.to.timestamp()
+            ),
+            format!(
+                "timestamp_ms >= {} AND timestamp_ms < {}",
+                self.from.timestamp_millis(),
+                self.to.timestamp_millis()
+            ),
+        ];
+
+        match &self.instances {
+            Some(instances) if instances.is_empty() => conditions.push("0".into()),
+            Some(instances) => conditions.push(format!(
+                "instance_uuid IN ({})",
+                instances
+                    .iter()
+                    .map(|i| quote(i))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+            None => {}
+        }
+
+        if let Some(level) = self.level {
+            conditions.push(format!("severity >= {}", level.severity()));
+        }
+
+        match (&self.query, self.mode) {
+            (Some(query), MatchMode::Substring) => conditions.push(format!(
+                "positionCaseInsensitiveUTF8(message, {}) > 0",
+                quote(query)
+            )),
+            (Some(query), MatchMode::Token) => {
+                for token in tokens(query) {
+                    conditions.push(format!("hasToken(lowerUTF8(message), {})", quote(&token)));
+                }
+            }
+            (None, _) => {}
+        }
+
+        for (key, value) in &self.fields {
+            conditions.push(format!(
+                "has(field_keys, '{key}') AND field_values[indexOf(field_keys, '{key}')] = {}",
+                quote(value)
+            ));
+        }
+
+        format!(
+            "SELECT instance_uuid, timestamp_ms, level, message, field_keys, field_values FROM {LOGS_TABLE} WHERE {} ORDER BY timestamp_ms DESC LIMIT {}",
+            conditions.join(" AND "),
+            self.limit
+        )
+    }
+}
+
+/// Splits a search into the lowercased words that ClickHouse's `hasToken` matches, which
+/// are runs of anything but ASCII punctuation and whitespace.
+fn tokens(query: &str) -> Vec<String> {
+    query
+        .split(|c: char| c.is_ascii() && !c.is_ascii_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INSTANCE: &str = "a8e7c6e2-5b4b-4b5a-9a3a-6f0b0b3c2d1e";
+
+    #[test]
+    fn parses_logstash_lines() {
+        let body = format!(
+            "{}\n{}\n",
+            r#"{"@timestamp":"2023-07-10T12:00:00.250+02:00","@version":"1","message":"launching server","log.level":"INFO","thread.name":"main","process.id":42,"metadata.file.path":"src/server.rs","metadata.file.line":130}"#,
+            r#"{"message":"no instance","level":"loud"}"#
+        );
+
+        let lines = parse_logs(&body, true, Some(Uuid::parse_str(INSTANCE).unwrap())).unwrap();
+        let line = lines[0].as_ref().unwrap();
+        assert_eq!(line.instance.to_string(), INSTANCE);
+        assert_eq!(line.timestamp.timestamp_millis(), 1688983200250);
+        assert_eq!(line.level, LogLevel::Info);
+        assert_eq!(line.message, "launching server");
+        assert_eq!(
+            line.fields.keys().collect::<Vec<_>>(),
+            vec![
+                "metadata.file.line",
+                "metadata.file.path",
+                "process.id",
+                "thread.name"
+            ]
+        );
+        assert_eq!(line.fields["process.id"], "42");
+
+        assert!(lines[1]
+            .as_ref()
+            .unwrap_err()
+            .starts_with("`level` has an unknown level"));
+        assert_eq!(
+            parse_logs(r#"{"message":"hi"}"#, false, None).unwrap()[0],
+            Err("missing `instance`".to_string())
+        );
+    }
+
+    #[test]
+    fn builds_search_queries() {
+        let search = LogSearch::parse(
+            Some(vec![INSTANCE.into()]),
+            Some("1688983200"),
+            Some("1688986800"),
+            Some("warn"),
+            Some("Unable to ping"),
+            Some("token"),
+            &["thread.name:tokio-runtime-worker".into()],
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            search.to_sql(),
+            format!(
+                "SELECT instance_uuid, timestamp_ms, level, message, field_keys, field_values FROM logs WHERE timestamp >= toDateTime(1688983200, 'UTC') AND timestamp <= toDateTime(1688986800, 'UTC') AND timestamp_ms >= 1688983200000 AND timestamp_ms < 1688986800000 AND instance_uuid IN ('{INSTANCE}') AND severity >= 4 AND hasToken(lowerUTF8(message), 'unable') AND hasToken(lowerUTF8(message), 'to') AND hasToken(lowerUTF8(message), 'ping') AND has(field_keys, 'thread.name') AND field_values[indexOf(field_keys, 'thread.name')] = 'tokio-runtime-worker' ORDER BY timestamp_ms DESC LIMIT 100"
+            )
+        );
+
+        let search =
+            LogSearch::parse(None, None, None, None, Some("it's"), None, &[], Some(5)).unwrap();
+        assert!(search
+            .to_sql()
+            .contains("positionCaseInsensitiveUTF8(message, 'it\\'s') > 0"));
+
+        let parse = |mode, query, field: &str, limit| {
+            LogSearch::parse(None, None, None, None, query, mode, &[field.into()], limit)
+        };
+
+        assert!(parse(Some("token"), Some("..."), "a:b", None).is_err());
+        assert!(parse(None, None, "a' OR 1 = 1:b", None).is_err());
+        assert!(parse(None, None, "no-value", None).is_err());
+        assert!(parse(None, None, "a:b", Some(0)).is_err());
+        assert!(parse(None, None, "a:", Some(MAX_SEARCH_LIMIT)).is_ok());
+    }
+}
@@ -13,5 +13,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod alert;
 pub mod dashboard;
+pub mod event;
+pub mod log;
 pub mod response;
+pub mod stats;
+pub mod token;
@@ -95,6 +95,24 @@ where
     }
 }
 
+/// Returns a new [`ApiResponse`] struct for a REST request that partially succeeded,
+/// where `errors` describes what was rejected.
+pub fn new_response_with_errors<T>(status: u16, data: T, errors: Vec<ApiError>) -> ApiResponse<T>
+where
+    T: Serialize + Debug,
+{
+    ApiResponse {
+        status: Some(status),
+        success: true,
+        errors: if errors.is_empty() {
+            None
+        } else {
+            Some(errors)
+        },
+        data: Some(data),
+    }
+}
+
 /// Returns a new [`ApiResponse`] struct for a failed REST request.
 pub fn new_err_resp<R, S>(code: i32, message: S) -> ApiResponse<R>
 where
@@ -124,6 +142,20 @@ where
     }
 }
 
+/// Returns a new [`ApiResponse`] struct for a failed REST request that failed for
+/// more than one reason. The status code is taken from the first error.
+pub fn new_err_resp_from_errs<R>(errors: Vec<ApiError>) -> ApiResponse<R>
+where
+    R: Serialize + Debug,
+{
+    ApiResponse {
+        status: None,
+        success: false,
+        data: None,
+        errors: Some(errors),
+    }
+}
+
 impl<'r, S> Responder<'r, 'static> for ApiResponse<S>
 where
     S: Serialize + Debug,
@@ -140,7 +172,7 @@ where
         }
         let serialised = json!(self);
         let str = serialised.to_string();
-        if let Some(errs) = self.errors {
+        if let (false, Some(errs)) = (self.success, self.errors) {
             return Response::build()
                 .sized_body(str.len(), Cursor::new(str))
                 .status(
@@ -0,0 +1,338 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::collector::snapshot::STATS_TABLE;
+
+/// Maximum amount of buckets a single stats query can return.
+pub const MAX_POINTS: i64 = 11_000;
+
+/// Longest duration (in seconds) that [`parse_step`] accepts, which is a year.
+pub const MAX_STEP: i64 = 60 * 60 * 24 * 365;
+
+/// Represents how the snapshots inside a single bucket are reduced into one value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregation {
+    Last,
+    Avg,
+    Min,
+    Max,
+    Count,
+}
+
+impl FromStr for Aggregation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "last" => Ok(Aggregation::Last),
+            "avg" => Ok(Aggregation::Avg),
+            "min" => Ok(Aggregation::Min),
+            "max" => Ok(Aggregation::Max),
+            "count" => Ok(Aggregation::Count),
+            _ => Err(format!(
+                "unknown aggregation `{s}`, expected one of: last, avg, min, max, count"
+            )),
+        }
+    }
+}
+
+impl Display for Aggregation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Aggregation::Last => "last",
+            Aggregation::Avg => "avg",
+            Aggregation::Min => "min",
+            Aggregation::Max => "max",
+            Aggregation::Count => "count",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// Represents a validated `GET /instances/<id>/stats` query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatsQuery {
+    pub instance: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub step: i64,
+    pub aggregate: Aggregation,
+    pub field: Option<Vec<String>>,
+}
+
+/// Represents a single bucket of the returned time-series.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsPoint {
+    pub timestamp: DateTime<Utc>,
+    pub value: Option<f64>,
+}
+
+/// Represents the time-series that is returned by `GET /instances/<id>/stats`.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsSeries {
+    pub instance: String,
+    pub field: Option<String>,
+    pub aggregate: Aggregation,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub step: i64,
+    pub points: Vec<StatsPoint>,
+}
+
+impl StatsQuery {
+    /// Validates the raw query parameters. `to` defaults to now, `from` defaults to an
+    /// hour before `to`, `step` defaults to one minute and `aggregate` defaults to `last`.
+    pub fn parse(
+        instance: String,
+        from: Option<&str>,
+        to: Option<&str>,
+        step: Option<&str>,
+        aggregate: Option<&str>,
+        field: Option<&str>,
+    ) -> Result<StatsQuery, String> {
+        let to = match to {
+            Some(to) => parse_time(to).map_err(|e| format!("`to` {e}"))?,
+            None => Utc::now(),
+        };
+
+        let from = match from {
+            Some(from) => parse_time(from).map_err(|e| format!("`from` {e}"))?,
+            None => to
+                .checked_sub_signed(Duration::hours(1))
+                .ok_or("`to` is too far in the past")?,
+        };
+
+        if from >= to {
+            return Err("`from` must be before `to`".into());
+        }
+
+        let step = match step {
+            Some(step) => parse_step(step)?,
+            None => 60,
+        };
+
+        if (to - from).num_seconds() / step > MAX_POINTS {
+            return Err(format!(
+                "the time range would return more than {MAX_POINTS} points, use a bigger `step`"
+            ));
+        }
+
+        let aggregate = match aggregate {
+            Some(aggregate) => aggregate.parse::<Aggregation>()?,
+            None => Aggregation::Last,
+        };
+
+        let field = match field {
+            Some(field) => Some(parse_field(field)?),
+            None if aggregate == Aggregation::Count => None,
+            None => {
+                return Err(format!(
+                    "`field` is required for the `{aggregate}` aggregation"
+                ))
+            }
+        };
+
+        Ok(StatsQuery {
+            instance,
+            from,
+            to,
+            step,
+            aggregate,
+            field,
+        })
+    }
+
+    /// Builds the ClickHouse query for this stats query. Every user-provided value has
+    /// already been validated in [`StatsQuery::parse`], so it is safe to inline them.
+    pub fn to_sql(&self) -> String {
+        let value = match &self.field {
+            Some(path) => format!(
+                "JSONExtractFloat(data, {})",
+                path.iter()
+                    .map(|p| format!("'{p}'"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            None => String::new(),
+        };
+
+        let aggregate = match self.aggregate {
+            Aggregation::Last => format!("argMax({value}, snapshot_date)"),
+            Aggregation::Avg => format!("avg({value})"),
+            Aggregation::Min => format!("min({value})"),
+            Aggregation::Max => format!("max({value})"),
+            Aggregation::Count => "count()".into(),
+        };
+
+        format!(
+            "SELECT toStartOfInterval(snapshot_date, INTERVAL {} SECOND) AS bucket, toFloat64({aggregate}) AS value FROM {STATS_TABLE} WHERE instance_uuid = '{}' AND snapshot_date >= toDateTime({}, 'UTC') AND snapshot_date < toDateTime({}, 'UTC') GROUP BY bucket ORDER BY bucket",
+            self.step,
+            self.instance,
+            self.from.timestamp(),
+            self.to.timestamp()
+        )
+    }
+}
+
+/// Parses either an RFC 3339 date or a UNIX timestamp in seconds.
+pub fn parse_time(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(secs) = value.parse::<i64>() {
+        return Utc
+            .timestamp_opt(secs, 0)
+            .single()
+            .ok_or_else(|| format!("is not a valid UNIX timestamp: {value}"));
+    }
+
+    DateTime::parse_from_rfc3339(value)
+        .map(|dt| dt.with_timezone(&Utc))
+        .map_err(|_| format!("is not a valid RFC 3339 date or UNIX timestamp: {value}"))
+}
+
+/// Parses a step like `30s`, `5m`, `1h`, `1d` or a plain amount of seconds into seconds,
+/// up to [`MAX_STEP`].
+pub fn parse_step(value: &str) -> Result<i64, String> {
+    let invalid = || format!("`step` is not a valid duration: {value}");
+    let (amount, multiplier) = match value.char_indices().last() {
+        Some((i, 's')) => (&value[..i], 1),
+        Some((i, 'm')) => (&value[..i], 60),
+        Some((i, 'h')) => (&value[..i], 60 * 60),
+        Some((i, 'd')) => (&value[..i], 60 * 60 * 24),
+        Some(_) => (value, 1),
+        None => return Err(invalid()),
+    };
+
+    match amount.parse::<i64>() {
+        Ok(amount) if amount > 0 => match amount.checked_mul(multiplier) {
+            Some(seconds) if seconds <= MAX_STEP => Ok(seconds),
+            _ => Err(format!(
+                "`step` can't be longer than {} days",
+                MAX_STEP / (60 * 60 * 24)
+            )),
+        },
+        _ => Err(invalid()),
+    }
+}
+
+/// Parses a field path like `data.heap.used` (the `data.` prefix is optional) into
+/// its segments.
+pub fn parse_field(value: &str) -> Result<Vec<String>, String> {
+    let path = value.strip_prefix("data.").unwrap_or(value);
+    let segments = path.split('.').map(String::from).collect::<Vec<_>>();
+    let valid = segments.iter().all(|s| {
+        !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    });
+
+    if !valid {
+        return Err(format!("`field` is not a valid field path: {value}"));
+    }
+
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_steps() {
+        assert_eq!(parse_step("30s"), Ok(30));
+        assert_eq!(parse_step("5m"), Ok(300));
+        assert_eq!(parse_step("1h"), Ok(3600));
+        assert_eq!(parse_step("1d"), Ok(86400));
+        assert_eq!(parse_step("90"), Ok(90));
+        assert!(parse_step("0s").is_err());
+        assert!(parse_step("-5m").is_err());
+        assert!(parse_step("m").is_err());
+        assert!(parse_step("").is_err());
+        assert_eq!(parse_step("365d"), Ok(MAX_STEP));
+        assert!(parse_step("366d").is_err());
+        assert!(parse_step("144115188075855872d").is_err());
+        assert!(parse_step("9223372036854775807m").is_err());
+    }
+
+    #[test]
+    fn rejects_unsafe_field_paths() {
+        assert_eq!(
+            parse_field("data.heap.used"),
+            Ok(vec!["heap".to_string(), "used".to_string()])
+        );
+
+        assert!(parse_field("heap'); DROP TABLE instance_stats; --").is_err());
+        assert!(parse_field("heap..used").is_err());
+    }
+
+    #[test]
+    fn builds_bucketed_queries() {
+        let query = StatsQuery::parse(
+            "waff".into(),
+            Some("1685577600"),
+            Some("2023-06-01T01:00:00Z"),
+            Some("5m"),
+            Some("avg"),
+            Some("data.heap.used"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            query.to_sql(),
+            "SELECT toStartOfInterval(snapshot_date, INTERVAL 300 SECOND) AS bucket, toFloat64(avg(JSONExtractFloat(data, 'heap', 'used'))) AS value FROM instance_stats WHERE instance_uuid = 'waff' AND snapshot_date >= toDateTime(1685577600, 'UTC') AND snapshot_date < toDateTime(1685581200, 'UTC') GROUP BY bucket ORDER BY bucket"
+        );
+    }
+
+    #[test]
+    fn validates_ranges_and_aggregations() {
+        let parse = |from, step, aggregate, field| {
+            StatsQuery::parse(
+                "waff".into(),
+                Some(from),
+                Some("1685581200"),
+                step,
+                aggregate,
+                field,
+            )
+        };
+
+        assert!(parse("1685581200", None, Some("count"), None).is_err());
+        assert!(parse("0", Some("1s"), Some("count"), None).is_err());
+        assert!(parse("1685577600", None, Some("median"), Some("x")).is_err());
+        assert!(parse("1685577600", None, Some("max"), None).is_err());
+        assert!(parse("1685577600", None, Some("count"), None).is_ok());
+    }
+
+    #[test]
+    fn rejects_ranges_before_the_earliest_date() {
+        let earliest = DateTime::<Utc>::MIN_UTC.timestamp().to_string();
+        let query = StatsQuery::parse(
+            "waff".into(),
+            None,
+            Some(&earliest),
+            None,
+            Some("count"),
+            None,
+        );
+
+        assert!(query.is_err());
+    }
+}
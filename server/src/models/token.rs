@@ -0,0 +1,143 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The prefix that every issued API token starts with, so they are easy to spot
+/// in logs and secret scanners.
+pub const TOKEN_PREFIX: &str = "nat_";
+
+/// The amount of random characters that come after [`TOKEN_PREFIX`].
+const TOKEN_LENGTH: usize = 40;
+
+/// Represents what an API token is allowed to do. `admin` implies every other scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "instances:write")]
+    InstancesWrite,
+
+    #[serde(rename = "stats:read")]
+    StatsRead,
+
+    #[serde(rename = "dashboards:write")]
+    DashboardsWrite,
+
+    #[serde(rename = "alerts:write")]
+    AlertsWrite,
+
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::InstancesWrite => "instances:write",
+            Scope::StatsRead => "stats:read",
+            Scope::DashboardsWrite => "dashboards:write",
+            Scope::AlertsWrite => "alerts:write",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "instances:write" => Ok(Scope::InstancesWrite),
+            "stats:read" => Ok(Scope::StatsRead),
+            "dashboards:write" => Ok(Scope::DashboardsWrite),
+            "alerts:write" => Ok(Scope::AlertsWrite),
+            "admin" => Ok(Scope::Admin),
+            _ => Err(format!("unknown scope `{s}`")),
+        }
+    }
+}
+
+/// Parses the scopes stored alongside a token, dropping any that this version of the
+/// server doesn't know about.
+pub fn parse_scopes(scopes: &[String]) -> Vec<Scope> {
+    scopes.iter().filter_map(|s| s.parse().ok()).collect()
+}
+
+/// Generates a new random API token. Only its [hash](hash_token) is ever stored.
+pub fn generate_token() -> String {
+    let random: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect();
+
+    format!("{TOKEN_PREFIX}{random}")
+}
+
+/// Hashes an API token with SHA-256 and returns it hex encoded. Tokens have enough
+/// entropy that a salt isn't needed, and it lets us look them up by their hash.
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_roundtrip() {
+        for scope in [
+            Scope::InstancesWrite,
+            Scope::StatsRead,
+            Scope::DashboardsWrite,
+            Scope::AlertsWrite,
+            Scope::Admin,
+        ] {
+            assert_eq!(scope.as_str().parse::<Scope>(), Ok(scope));
+        }
+
+        assert!("stats:write".parse::<Scope>().is_err());
+        assert_eq!(
+            parse_scopes(&["stats:read".into(), "nope".into()]),
+            vec![Scope::StatsRead]
+        );
+    }
+
+    #[test]
+    fn tokens_are_hashed() {
+        let token = generate_token();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_eq!(token.len(), TOKEN_PREFIX.len() + TOKEN_LENGTH);
+
+        let hash = hash_token(&token);
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_token(&token));
+        assert_ne!(hash, hash_token(&generate_token()));
+    }
+}
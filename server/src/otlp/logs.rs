@@ -0,0 +1,205 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashSet;
+
+use clickhouse_rs::types::Block;
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use serde::Serialize;
+
+use crate::otlp::resource::{any_value_to_string, hex, or_now, Attributes, ResourceInfo};
+
+/// The ClickHouse table that log records received over OTLP are inserted into.
+pub const OTLP_LOGS_TABLE: &str = "otlp_logs";
+
+/// A single OTLP log record, flattened so it can be stored as a row in ClickHouse.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OtlpLogRow {
+    pub resource: ResourceInfo,
+    pub scope_name: String,
+    pub severity_number: i32,
+    pub severity_text: String,
+
+    /// the body as a string, or its JSON representation if it isn't one.
+    pub body: String,
+    pub attributes: Attributes,
+
+    /// hex encoded, or empty if the record isn't part of a trace.
+    pub trace_id: String,
+    pub span_id: String,
+    pub timestamp_ns: u64,
+}
+
+/// Flattens every log record of the request into rows, where `instances` are the instance
+/// UUIDs that resources can be mapped to. Records that don't have a timestamp use when they
+/// were observed, or `now_ns` if that is missing too.
+pub fn from_request(
+    request: ExportLogsServiceRequest,
+    instances: &HashSet<String>,
+    now_ns: u64,
+) -> Vec<OtlpLogRow> {
+    let mut rows = vec![];
+    for resource_logs in request.resource_logs {
+        let resource = ResourceInfo::new(resource_logs.resource.as_ref(), instances);
+        for scope_logs in resource_logs.scope_logs {
+            let scope_name = scope_logs.scope.map(|scope| scope.name).unwrap_or_default();
+
+            for record in scope_logs.log_records {
+                rows.push(OtlpLogRow {
+                    resource: resource.clone(),
+                    scope_name: scope_name.clone(),
+                    severity_number: record.severity_number,
+                    severity_text: record.severity_text,
+                    body: record
+                        .body
+                        .as_ref()
+                        .map(any_value_to_string)
+                        .unwrap_or_default(),
+                    attributes: Attributes::from_key_values(&record.attributes),
+                    trace_id: hex(&record.trace_id),
+                    span_id: hex(&record.span_id),
+                    timestamp_ns: or_now(
+                        record.time_unix_nano,
+                        or_now(record.observed_time_unix_nano, now_ns),
+                    ),
+                });
+            }
+        }
+    }
+
+    rows
+}
+
+/// Builds a ClickHouse [`Block`] out of the given rows, with the columns
+/// laid out the same way as the [`OTLP_LOGS_TABLE`] table.
+pub fn to_block(rows: &[OtlpLogRow]) -> Block {
+    Block::new()
+        .column(
+            "instance_uuid",
+            rows.iter()
+                .map(|r| r.resource.instance_uuid.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "service_name",
+            rows.iter()
+                .map(|r| r.resource.service_name.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "resource_keys",
+            rows.iter()
+                .map(|r| r.resource.attributes.keys.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "resource_values",
+            rows.iter()
+                .map(|r| r.resource.attributes.values.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "scope_name",
+            rows.iter()
+                .map(|r| r.scope_name.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "severity_number",
+            rows.iter().map(|r| r.severity_number).collect::<Vec<_>>(),
+        )
+        .column(
+            "severity_text",
+            rows.iter()
+                .map(|r| r.severity_text.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "body",
+            rows.iter().map(|r| r.body.clone()).collect::<Vec<_>>(),
+        )
+        .column(
+            "attribute_keys",
+            rows.iter()
+                .map(|r| r.attributes.keys.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "attribute_values",
+            rows.iter()
+                .map(|r| r.attributes.values.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "trace_id",
+            rows.iter().map(|r| r.trace_id.clone()).collect::<Vec<_>>(),
+        )
+        .column(
+            "span_id",
+            rows.iter().map(|r| r.span_id.clone()).collect::<Vec<_>>(),
+        )
+        .column(
+            "timestamp_ns",
+            rows.iter().map(|r| r.timestamp_ns).collect::<Vec<_>>(),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, KeyValue};
+    use opentelemetry_proto::tonic::logs::v1::{LogRecord, ResourceLogs, ScopeLogs};
+    use opentelemetry_proto::tonic::resource::v1::Resource;
+
+    #[test]
+    fn flattens_log_records() {
+        let request = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: Some(Resource {
+                    attributes: vec![KeyValue {
+                        key: "service.instance.id".into(),
+                        value: Some(AnyValue {
+                            value: Some(any_value::Value::StringValue("waff".into())),
+                        }),
+                    }],
+                    dropped_attributes_count: 0,
+                }),
+                scope_logs: vec![ScopeLogs {
+                    log_records: vec![LogRecord {
+                        observed_time_unix_nano: 2_000,
+                        severity_number: 17,
+                        severity_text: "ERROR".into(),
+                        body: Some(AnyValue {
+                            value: Some(any_value::Value::StringValue("unable to ping".into())),
+                        }),
+                        trace_id: vec![0xab; 16],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        let rows = from_request(request, &HashSet::from(["waff".to_string()]), 5_000);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].resource.instance_uuid, "waff");
+        assert_eq!(rows[0].severity_text, "ERROR");
+        assert_eq!(rows[0].body, "unable to ping");
+        assert_eq!(rows[0].trace_id, "ab".repeat(16));
+        assert_eq!(rows[0].span_id, "");
+        assert_eq!(rows[0].timestamp_ns, 2_000);
+    }
+}
@@ -0,0 +1,372 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashSet;
+
+use clickhouse_rs::types::Block;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::metrics::v1::{
+    metric::Data, number_data_point::Value as NumberValue, AggregationTemporality, NumberDataPoint,
+};
+use serde::Serialize;
+
+use crate::otlp::resource::{or_now, Attributes, ResourceInfo};
+
+/// The ClickHouse table that data points received over OTLP are inserted into.
+pub const OTLP_METRICS_TABLE: &str = "otlp_metrics";
+
+/// A single data point of an OTLP metric, flattened so it can be stored as a row in ClickHouse.
+///
+/// Gauges and sums only have a `value`, while histograms and summaries have their `count`,
+/// `sum` and buckets or quantiles filled in. Histogram buckets are stored the way OTLP sends
+/// them, so `bucket_counts` has one more entry than `bucket_bounds` and isn't cumulative.
+/// Exponential histograms only have their `count` and `sum` stored.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OtlpMetricRow {
+    pub resource: ResourceInfo,
+    pub scope_name: String,
+    pub name: String,
+    pub unit: String,
+    pub kind: &'static str,
+    pub temporality: &'static str,
+    pub is_monotonic: bool,
+    pub attributes: Attributes,
+    pub value: f64,
+    pub count: u64,
+    pub sum: f64,
+    pub bucket_bounds: Vec<f64>,
+    pub bucket_counts: Vec<u64>,
+    pub quantiles: Vec<f64>,
+    pub quantile_values: Vec<f64>,
+    pub timestamp_ns: u64,
+}
+
+/// Flattens every data point of the request into rows, where `instances` are the instance
+/// UUIDs that resources can be mapped to. Data points without a timestamp get `now_ns`.
+pub fn from_request(
+    request: ExportMetricsServiceRequest,
+    instances: &HashSet<String>,
+    now_ns: u64,
+) -> Vec<OtlpMetricRow> {
+    let mut rows = vec![];
+    for resource_metrics in request.resource_metrics {
+        let resource = ResourceInfo::new(resource_metrics.resource.as_ref(), instances);
+        for scope_metrics in resource_metrics.scope_metrics {
+            let scope_name = scope_metrics
+                .scope
+                .map(|scope| scope.name)
+                .unwrap_or_default();
+
+            for metric in scope_metrics.metrics {
+                let template = OtlpMetricRow {
+                    resource: resource.clone(),
+                    scope_name: scope_name.clone(),
+                    name: metric.name,
+                    unit: metric.unit,
+                    kind: "gauge",
+                    temporality: "",
+                    is_monotonic: false,
+                    attributes: Attributes::default(),
+                    value: 0.0,
+                    count: 0,
+                    sum: 0.0,
+                    bucket_bounds: vec![],
+                    bucket_counts: vec![],
+                    quantiles: vec![],
+                    quantile_values: vec![],
+                    timestamp_ns: now_ns,
+                };
+
+                match metric.data {
+                    Some(Data::Gauge(gauge)) => {
+                        for point in gauge.data_points {
+                            rows.push(number_row(&template, point, now_ns));
+                        }
+                    }
+                    Some(Data::Sum(sum)) => {
+                        let template = OtlpMetricRow {
+                            kind: "sum",
+                            temporality: temporality(sum.aggregation_temporality),
+                            is_monotonic: sum.is_monotonic,
+                            ..template
+                        };
+
+                        for point in sum.data_points {
+                            rows.push(number_row(&template, point, now_ns));
+                        }
+                    }
+                    Some(Data::Histogram(histogram)) => {
+                        for point in histogram.data_points {
+                            rows.push(OtlpMetricRow {
+                                kind: "histogram",
+                                temporality: temporality(histogram.aggregation_temporality),
+                                attributes: Attributes::from_key_values(&point.attributes),
+                                count: point.count,
+                                sum: point.sum.unwrap_or_default(),
+                                bucket_bounds: point.explicit_bounds,
+                                bucket_counts: point.bucket_counts,
+                                timestamp_ns: or_now(point.time_unix_nano, now_ns),
+                                ..template.clone()
+                            });
+                        }
+                    }
+                    Some(Data::ExponentialHistogram(histogram)) => {
+                        for point in histogram.data_points {
+                            rows.push(OtlpMetricRow {
+                                kind: "exponential_histogram",
+                                temporality: temporality(histogram.aggregation_temporality),
+                                attributes: Attributes::from_key_values(&point.attributes),
+                                count: point.count,
+                                sum: point.sum.unwrap_or_default(),
+                                timestamp_ns: or_now(point.time_unix_nano, now_ns),
+                                ..template.clone()
+                            });
+                        }
+                    }
+                    Some(Data::Summary(summary)) => {
+                        for point in summary.data_points {
+                            let (quantiles, quantile_values) = point
+                                .quantile_values
+                                .iter()
+                                .map(|q| (q.quantile, q.value))
+                                .unzip();
+
+                            rows.push(OtlpMetricRow {
+                                kind: "summary",
+                                attributes: Attributes::from_key_values(&point.attributes),
+                                count: point.count,
+                                sum: point.sum,
+                                quantiles,
+                                quantile_values,
+                                timestamp_ns: or_now(point.time_unix_nano, now_ns),
+                                ..template.clone()
+                            });
+                        }
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+
+    rows
+}
+
+fn number_row(template: &OtlpMetricRow, point: NumberDataPoint, now_ns: u64) -> OtlpMetricRow {
+    let value = match point.value {
+        Some(NumberValue::AsDouble(n)) => n,
+        Some(NumberValue::AsInt(n)) => n as f64,
+        None => 0.0,
+    };
+
+    OtlpMetricRow {
+        attributes: Attributes::from_key_values(&point.attributes),
+        value,
+        timestamp_ns: or_now(point.time_unix_nano, now_ns),
+        ..template.clone()
+    }
+}
+
+fn temporality(value: i32) -> &'static str {
+    match AggregationTemporality::from_i32(value) {
+        Some(AggregationTemporality::Delta) => "delta",
+        Some(AggregationTemporality::Cumulative) => "cumulative",
+        _ => "",
+    }
+}
+
+/// Builds a ClickHouse [`Block`] out of the given rows, with the columns
+/// laid out the same way as the [`OTLP_METRICS_TABLE`] table.
+pub fn to_block(rows: &[OtlpMetricRow]) -> Block {
+    Block::new()
+        .column(
+            "instance_uuid",
+            rows.iter()
+                .map(|r| r.resource.instance_uuid.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "service_name",
+            rows.iter()
+                .map(|r| r.resource.service_name.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "resource_keys",
+            rows.iter()
+                .map(|r| r.resource.attributes.keys.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "resource_values",
+            rows.iter()
+                .map(|r| r.resource.attributes.values.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "scope_name",
+            rows.iter()
+                .map(|r| r.scope_name.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "name",
+            rows.iter().map(|r| r.name.clone()).collect::<Vec<_>>(),
+        )
+        .column(
+            "unit",
+            rows.iter().map(|r| r.unit.clone()).collect::<Vec<_>>(),
+        )
+        .column(
+            "type",
+            rows.iter().map(|r| r.kind.to_string()).collect::<Vec<_>>(),
+        )
+        .column(
+            "temporality",
+            rows.iter()
+                .map(|r| r.temporality.to_string())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "is_monotonic",
+            rows.iter()
+                .map(|r| r.is_monotonic as u8)
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "attribute_keys",
+            rows.iter()
+                .map(|r| r.attributes.keys.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "attribute_values",
+            rows.iter()
+                .map(|r| r.attributes.values.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column("value", rows.iter().map(|r| r.value).collect::<Vec<_>>())
+        .column("count", rows.iter().map(|r| r.count).collect::<Vec<_>>())
+        .column("sum", rows.iter().map(|r| r.sum).collect::<Vec<_>>())
+        .column(
+            "bucket_bounds",
+            rows.iter()
+                .map(|r| r.bucket_bounds.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "bucket_counts",
+            rows.iter()
+                .map(|r| r.bucket_counts.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "quantiles",
+            rows.iter().map(|r| r.quantiles.clone()).collect::<Vec<_>>(),
+        )
+        .column(
+            "quantile_values",
+            rows.iter()
+                .map(|r| r.quantile_values.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "timestamp_ns",
+            rows.iter().map(|r| r.timestamp_ns).collect::<Vec<_>>(),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, KeyValue};
+    use opentelemetry_proto::tonic::metrics::v1::{
+        Histogram, HistogramDataPoint, Metric, ResourceMetrics, ScopeMetrics, Sum,
+    };
+    use opentelemetry_proto::tonic::resource::v1::Resource;
+
+    fn kv(key: &str, value: &str) -> KeyValue {
+        KeyValue {
+            key: key.into(),
+            value: Some(AnyValue {
+                value: Some(any_value::Value::StringValue(value.into())),
+            }),
+        }
+    }
+
+    #[test]
+    fn flattens_data_points() {
+        let request = ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(Resource {
+                    attributes: vec![kv("service.name", "charted-server")],
+                    dropped_attributes_count: 0,
+                }),
+                scope_metrics: vec![ScopeMetrics {
+                    metrics: vec![
+                        Metric {
+                            name: "http.server.requests".into(),
+                            data: Some(Data::Sum(Sum {
+                                data_points: vec![NumberDataPoint {
+                                    attributes: vec![kv("http.method", "GET")],
+                                    time_unix_nano: 1_000,
+                                    value: Some(NumberValue::AsInt(42)),
+                                    ..Default::default()
+                                }],
+                                aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                                is_monotonic: true,
+                            })),
+                            ..Default::default()
+                        },
+                        Metric {
+                            name: "http.server.duration".into(),
+                            unit: "ms".into(),
+                            data: Some(Data::Histogram(Histogram {
+                                data_points: vec![HistogramDataPoint {
+                                    count: 3,
+                                    sum: Some(12.5),
+                                    bucket_counts: vec![1, 2, 0],
+                                    explicit_bounds: vec![5.0, 10.0],
+                                    ..Default::default()
+                                }],
+                                aggregation_temporality: AggregationTemporality::Delta as i32,
+                            })),
+                            ..Default::default()
+                        },
+                    ],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        let rows = from_request(request, &HashSet::new(), 5_000);
+        assert_eq!(rows.len(), 2);
+
+        assert_eq!(rows[0].kind, "sum");
+        assert_eq!(rows[0].temporality, "cumulative");
+        assert!(rows[0].is_monotonic);
+        assert_eq!(rows[0].value, 42.0);
+        assert_eq!(rows[0].timestamp_ns, 1_000);
+        assert_eq!(rows[0].attributes.get("http.method"), Some("GET"));
+        assert_eq!(rows[0].resource.service_name, "charted-server");
+
+        assert_eq!(rows[1].kind, "histogram");
+        assert_eq!(rows[1].temporality, "delta");
+        assert_eq!(rows[1].bucket_counts, vec![1, 2, 0]);
+        assert_eq!(rows[1].sum, 12.5);
+        assert_eq!(rows[1].timestamp_ns, 5_000);
+    }
+}
@@ -0,0 +1,108 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use tokio::sync::Mutex;
+
+use crate::endpoints::endpoint_manager::EndpointManager;
+use crate::middleware::auth::Principal;
+use crate::otlp::{logs, metrics};
+use crate::sinks::sink::{Batch, Sinks};
+
+/// How long the registered instances are cached for, so every export doesn't have to
+/// go through Redis.
+const INSTANCES_TTL: Duration = Duration::from_secs(30);
+
+/// Writes OTLP exports to the sinks. This is shared by the gRPC and HTTP receivers, which
+/// only differ in how the request is decoded and how the sender is authenticated.
+#[derive(Debug, Clone)]
+pub struct OtlpReceiver {
+    sinks: Sinks,
+    endpoints: Arc<Mutex<EndpointManager>>,
+
+    /// UUIDs of the registered instances along with their owners, and when they were loaded.
+    instances: Arc<Mutex<Option<(Instant, Vec<(String, Option<i64>)>)>>>,
+}
+
+impl OtlpReceiver {
+    pub fn new(sinks: Sinks, endpoints: Arc<Mutex<EndpointManager>>) -> OtlpReceiver {
+        OtlpReceiver {
+            sinks,
+            endpoints,
+            instances: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Returns the UUIDs of the instances that the principal can map resources to.
+    async fn instances_of(&self, principal: &Principal) -> Result<HashSet<String>> {
+        let mut cache = self.instances.lock().await;
+        let fresh = matches!(&*cache, Some((loaded, _)) if loaded.elapsed() < INSTANCES_TTL);
+        if !fresh {
+            let endpoints = self.endpoints.lock().await.get_endpoints().await?;
+            let instances = endpoints
+                .into_iter()
+                .map(|e| (e.instance_name, e.owner_id))
+                .collect();
+
+            *cache = Some((Instant::now(), instances));
+        }
+
+        Ok(cache
+            .iter()
+            .flat_map(|(_, instances)| instances.iter())
+            .filter(|(_, owner)| principal.can_access_owned(*owner))
+            .map(|(uuid, _)| uuid.clone())
+            .collect())
+    }
+
+    /// Stores the data points of the request, and returns how many were stored.
+    pub async fn export_metrics(
+        &self,
+        principal: &Principal,
+        request: ExportMetricsServiceRequest,
+    ) -> Result<usize> {
+        let instances = self.instances_of(principal).await?;
+        let rows = metrics::from_request(request, &instances, now_ns());
+        self.sinks.write(Batch::OtlpMetrics(&rows)).await?;
+
+        Ok(rows.len())
+    }
+
+    /// Stores the log records of the request, and returns how many were stored.
+    pub async fn export_logs(
+        &self,
+        principal: &Principal,
+        request: ExportLogsServiceRequest,
+    ) -> Result<usize> {
+        let instances = self.instances_of(principal).await?;
+        let rows = logs::from_request(request, &instances, now_ns());
+        self.sinks.write(Batch::OtlpLogs(&rows)).await?;
+
+        Ok(rows.len())
+    }
+}
+
+fn now_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before the UNIX epoch")
+        .as_nanos() as u64
+}
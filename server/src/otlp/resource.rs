@@ -0,0 +1,193 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashSet;
+
+use opentelemetry_proto::tonic::common::v1::{
+    any_value::Value as AnyValueKind, AnyValue, KeyValue,
+};
+use opentelemetry_proto::tonic::resource::v1::Resource;
+use serde::Serialize;
+use serde_json::{Map, Number, Value};
+
+/// Resource attribute that names the service that sent the data.
+pub const SERVICE_NAME: &str = "service.name";
+
+/// Resource attribute that is matched against the UUIDs of registered instances.
+pub const SERVICE_INSTANCE_ID: &str = "service.instance.id";
+
+/// OpenTelemetry attributes, flattened into two arrays so they can be stored in ClickHouse
+/// like the labels of the other tables. Values that aren't strings are stored as their JSON
+/// representation.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Attributes {
+    pub keys: Vec<String>,
+    pub values: Vec<String>,
+}
+
+impl Attributes {
+    pub fn from_key_values(attributes: &[KeyValue]) -> Attributes {
+        let mut pairs = attributes
+            .iter()
+            .map(|kv| {
+                (
+                    kv.key.clone(),
+                    kv.value
+                        .as_ref()
+                        .map(any_value_to_string)
+                        .unwrap_or_default(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        pairs.sort();
+        let (keys, values) = pairs.into_iter().unzip();
+        Attributes { keys, values }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.keys
+            .iter()
+            .position(|k| k == key)
+            .map(|i| self.values[i].as_str())
+    }
+}
+
+/// The resource that sent some data, along with the registered instance it was mapped to.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ResourceInfo {
+    /// UUID of the instance, or empty if the resource isn't a registered instance that the
+    /// sender can access.
+    pub instance_uuid: String,
+    pub service_name: String,
+    pub attributes: Attributes,
+}
+
+impl ResourceInfo {
+    /// Resolves the resource, where `instances` are the instance UUIDs that the sender can
+    /// map its data to.
+    pub fn new(resource: Option<&Resource>, instances: &HashSet<String>) -> ResourceInfo {
+        let attributes = resource
+            .map(|r| Attributes::from_key_values(&r.attributes))
+            .unwrap_or_default();
+
+        let instance_uuid = attributes
+            .get(SERVICE_INSTANCE_ID)
+            .filter(|id| instances.contains(*id))
+            .unwrap_or_default()
+            .to_string();
+
+        ResourceInfo {
+            instance_uuid,
+            service_name: attributes.get(SERVICE_NAME).unwrap_or_default().to_string(),
+            attributes,
+        }
+    }
+}
+
+/// Renders an attribute value as a string, which is the string itself for string values.
+pub fn any_value_to_string(value: &AnyValue) -> String {
+    match &value.value {
+        Some(AnyValueKind::StringValue(s)) => s.clone(),
+        _ => any_value_to_json(value).to_string(),
+    }
+}
+
+fn any_value_to_json(value: &AnyValue) -> Value {
+    match &value.value {
+        None => Value::Null,
+        Some(AnyValueKind::StringValue(s)) => Value::String(s.clone()),
+        Some(AnyValueKind::BoolValue(b)) => Value::Bool(*b),
+        Some(AnyValueKind::IntValue(n)) => Value::Number((*n).into()),
+        Some(AnyValueKind::DoubleValue(n)) => Number::from_f64(*n)
+            .map(Value::Number)
+            .unwrap_or(Value::Null),
+        Some(AnyValueKind::ArrayValue(array)) => {
+            Value::Array(array.values.iter().map(any_value_to_json).collect())
+        }
+        Some(AnyValueKind::KvlistValue(list)) => {
+            let mut map = Map::new();
+            for kv in &list.values {
+                map.insert(
+                    kv.key.clone(),
+                    kv.value
+                        .as_ref()
+                        .map(any_value_to_json)
+                        .unwrap_or(Value::Null),
+                );
+            }
+
+            Value::Object(map)
+        }
+        Some(AnyValueKind::BytesValue(bytes)) => Value::String(hex(bytes)),
+    }
+}
+
+/// Encodes bytes as lowercase hex, which is how trace and span IDs are usually shown.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Returns the given timestamp, or `now_ns` if it is zero, which is how OTLP marks
+/// timestamps that are unknown.
+pub fn or_now(timestamp_ns: u64, now_ns: u64) -> u64 {
+    if timestamp_ns == 0 {
+        now_ns
+    } else {
+        timestamp_ns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kv(key: &str, value: AnyValueKind) -> KeyValue {
+        KeyValue {
+            key: key.into(),
+            value: Some(AnyValue { value: Some(value) }),
+        }
+    }
+
+    #[test]
+    fn maps_resources_to_accessible_instances() {
+        let resource = Resource {
+            attributes: vec![
+                kv(
+                    SERVICE_NAME,
+                    AnyValueKind::StringValue("charted-server".into()),
+                ),
+                kv(
+                    SERVICE_INSTANCE_ID,
+                    AnyValueKind::StringValue("waff".into()),
+                ),
+                kv("host.cpus", AnyValueKind::IntValue(8)),
+            ],
+            dropped_attributes_count: 0,
+        };
+
+        let info = ResourceInfo::new(Some(&resource), &HashSet::from(["waff".to_string()]));
+        assert_eq!(info.instance_uuid, "waff");
+        assert_eq!(info.service_name, "charted-server");
+        assert_eq!(info.attributes.get("host.cpus"), Some("8"));
+        assert_eq!(
+            info.attributes.keys,
+            vec!["host.cpus", SERVICE_INSTANCE_ID, SERVICE_NAME]
+        );
+
+        let info = ResourceInfo::new(Some(&resource), &HashSet::new());
+        assert_eq!(info.instance_uuid, "");
+    }
+}
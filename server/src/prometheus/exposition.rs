@@ -0,0 +1,299 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::collector::metrics::MetricKind;
+
+/// A single line of a [`MetricFamily`], i.e, one bucket of a histogram.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    /// appended to the name of the family, like `_bucket` or `_sum`.
+    pub suffix: &'static str,
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+}
+
+/// Represents every sample of a metric, which are rendered below a single `# TYPE` line.
+#[derive(Debug, Clone)]
+pub struct MetricFamily {
+    pub name: String,
+    pub help: Option<String>,
+    pub kind: MetricKind,
+    pub samples: Vec<Sample>,
+}
+
+impl MetricFamily {
+    /// Adds a counter or gauge sample.
+    pub fn push(&mut self, labels: Vec<(String, String)>, value: f64) {
+        self.samples.push(Sample {
+            suffix: "",
+            labels,
+            value,
+        });
+    }
+
+    /// Adds the samples of a histogram, where `counts` are the cumulative counts of
+    /// each bucket in `bounds`. A `+Inf` bucket is added if it is missing.
+    pub fn push_histogram(
+        &mut self,
+        labels: Vec<(String, String)>,
+        bounds: &[f64],
+        counts: &[u64],
+        count: u64,
+        sum: f64,
+    ) {
+        for (bound, bucket) in bounds.iter().zip(counts) {
+            self.samples.push(Sample {
+                suffix: "_bucket",
+                labels: with_label(&labels, "le", format_value(*bound)),
+                value: *bucket as f64,
+            });
+        }
+
+        if !bounds.last().map(|b| *b == f64::INFINITY).unwrap_or(false) {
+            self.samples.push(Sample {
+                suffix: "_bucket",
+                labels: with_label(&labels, "le", "+Inf".into()),
+                value: count as f64,
+            });
+        }
+
+        self.push_sum_and_count(labels, count, sum);
+    }
+
+    /// Adds the samples of a summary.
+    pub fn push_summary(
+        &mut self,
+        labels: Vec<(String, String)>,
+        quantiles: &[f64],
+        values: &[f64],
+        count: u64,
+        sum: f64,
+    ) {
+        for (quantile, value) in quantiles.iter().zip(values) {
+            self.samples.push(Sample {
+                suffix: "",
+                labels: with_label(&labels, "quantile", format_value(*quantile)),
+                value: *value,
+            });
+        }
+
+        self.push_sum_and_count(labels, count, sum);
+    }
+
+    fn push_sum_and_count(&mut self, labels: Vec<(String, String)>, count: u64, sum: f64) {
+        self.samples.push(Sample {
+            suffix: "_sum",
+            labels: labels.clone(),
+            value: sum,
+        });
+
+        self.samples.push(Sample {
+            suffix: "_count",
+            labels,
+            value: count as f64,
+        });
+    }
+}
+
+/// Collects metric families and renders them in the Prometheus text exposition
+/// format, sorted by their name.
+#[derive(Debug, Clone, Default)]
+pub struct Exposition {
+    families: BTreeMap<String, MetricFamily>,
+}
+
+impl Exposition {
+    pub fn new() -> Exposition {
+        Exposition::default()
+    }
+
+    /// Returns the family with the given name, creating it if it doesn't exist. The name is
+    /// sanitized first, and `None` is returned if the family already exists with another
+    /// type, since Prometheus refuses the whole scrape otherwise.
+    pub fn family(
+        &mut self,
+        name: &str,
+        kind: MetricKind,
+        help: Option<&str>,
+    ) -> Option<&mut MetricFamily> {
+        let name = sanitize_name(name);
+        let family = self
+            .families
+            .entry(name.clone())
+            .or_insert_with(|| MetricFamily {
+                name,
+                help: help.map(String::from),
+                kind,
+                samples: vec![],
+            });
+
+        if family.kind != kind {
+            return None;
+        }
+
+        Some(family)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.families.is_empty()
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for family in self.families.values() {
+            if let Some(help) = &family.help {
+                let _ = writeln!(out, "# HELP {} {}", family.name, escape(help, false));
+            }
+
+            let _ = writeln!(out, "# TYPE {} {}", family.name, family.kind);
+            for sample in &family.samples {
+                out.push_str(&family.name);
+                out.push_str(sample.suffix);
+                if !sample.labels.is_empty() {
+                    let labels = sample
+                        .labels
+                        .iter()
+                        .map(|(k, v)| format!("{}=\"{}\"", sanitize_label_name(k), escape(v, true)))
+                        .collect::<Vec<_>>()
+                        .join(",");
+
+                    let _ = write!(out, "{{{labels}}}");
+                }
+
+                let _ = writeln!(out, " {}", format_value(sample.value));
+            }
+        }
+
+        out
+    }
+}
+
+/// Replaces every character that isn't allowed in a metric name with an underscore.
+pub fn sanitize_name(name: &str) -> String {
+    sanitize(name, true)
+}
+
+/// Replaces every character that isn't allowed in a label name with an underscore.
+pub fn sanitize_label_name(name: &str) -> String {
+    sanitize(name, false)
+}
+
+fn sanitize(name: &str, allow_colons: bool) -> String {
+    let mut sanitized = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => c,
+            ':' if allow_colons => c,
+            _ => '_',
+        })
+        .collect::<String>();
+
+    if sanitized.is_empty() || sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+
+    sanitized
+}
+
+fn escape(value: &str, quotes: bool) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '"' if quotes => escaped.push_str("\\\""),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".into()
+    } else if value == f64::INFINITY {
+        "+Inf".into()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".into()
+    } else {
+        value.to_string()
+    }
+}
+
+fn with_label(labels: &[(String, String)], name: &str, value: String) -> Vec<(String, String)> {
+    let mut labels = labels.to_vec();
+    labels.push((name.to_string(), value));
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn renders_text_format() {
+        let mut exposition = Exposition::new();
+        exposition
+            .family(
+                "http_requests_total",
+                MetricKind::Counter,
+                Some("requests\nserved"),
+            )
+            .unwrap()
+            .push(labels(&[("path", "/say \"hi\"")]), 3.0);
+
+        exposition
+            .family("latency", MetricKind::Histogram, None)
+            .unwrap()
+            .push_histogram(labels(&[]), &[0.5], &[2], 3, 1.5);
+
+        assert!(exposition
+            .family("latency", MetricKind::Gauge, None)
+            .is_none());
+
+        let expected = [
+            "# HELP http_requests_total requests\\nserved",
+            "# TYPE http_requests_total counter",
+            "http_requests_total{path=\"/say \\\"hi\\\"\"} 3",
+            "# TYPE latency histogram",
+            "latency_bucket{le=\"0.5\"} 2",
+            "latency_bucket{le=\"+Inf\"} 3",
+            "latency_sum 1.5",
+            "latency_count 3",
+            "",
+        ];
+
+        assert_eq!(exposition.render(), expected.join("\n"));
+    }
+
+    #[test]
+    fn sanitizes_names() {
+        assert_eq!(sanitize_name("heap.used-bytes"), "heap_used_bytes");
+        assert_eq!(sanitize_name("node:cpu"), "node:cpu");
+        assert_eq!(sanitize_label_name("node:cpu"), "node_cpu");
+        assert_eq!(sanitize_name("0day"), "_0day");
+    }
+}
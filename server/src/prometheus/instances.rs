@@ -0,0 +1,349 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde_json::Value;
+
+use crate::clickhouse::client::{quote, ClickHouse};
+use crate::collector::metrics::{MetricKind, MetricSample, METRICS_TABLE};
+use crate::collector::snapshot::STATS_TABLE;
+use crate::prometheus::exposition::Exposition;
+
+/// Prefix of the metrics that are flattened out of the free-form `data` of a snapshot.
+const DATA_PREFIX: &str = "analytics_data";
+
+/// Labels that every metric of an instance has. Typed metrics that have a label with the
+/// same name get it renamed to `exported_<name>`, like Prometheus does.
+const INSTANCE_LABELS: [&str; 4] = ["instance_uuid", "product", "version", "build_flavour"];
+
+/// The newest snapshot that is stored for an instance.
+#[derive(Debug, Clone)]
+pub struct LatestSnapshot {
+    pub instance_uuid: String,
+    pub product: String,
+    pub version: String,
+    pub build_flavour: String,
+    pub data: Value,
+    pub snapshot_date: DateTime<Utc>,
+}
+
+impl LatestSnapshot {
+    fn labels(&self) -> Vec<(String, String)> {
+        INSTANCE_LABELS
+            .iter()
+            .map(|l| l.to_string())
+            .zip([
+                self.instance_uuid.clone(),
+                self.product.clone(),
+                self.version.clone(),
+                self.build_flavour.clone(),
+            ])
+            .collect()
+    }
+}
+
+/// Returns the query for the newest snapshot of each of the given instances.
+pub fn latest_snapshots_sql(instance_uuids: &[String]) -> String {
+    format!(
+        "SELECT instance_uuid, product, version, build_flavour, data, snapshot_date FROM {STATS_TABLE} \
+         WHERE instance_uuid IN ({}) ORDER BY snapshot_date DESC LIMIT 1 BY instance_uuid",
+        in_list(instance_uuids)
+    )
+}
+
+/// Returns the query for the typed metrics of the given snapshots, which only reads the
+/// metrics that were stored with the exact snapshot of each instance.
+pub fn latest_metrics_sql(snapshots: &[LatestSnapshot]) -> String {
+    let keys = snapshots
+        .iter()
+        .map(|s| {
+            format!(
+                "({}, toDateTime({}, 'UTC'))",
+                quote(&s.instance_uuid),
+                s.snapshot_date.timestamp()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!(
+        "SELECT instance_uuid, name, type, label_names, label_values, value, count, sum, bucket_bounds, \
+         bucket_counts, quantiles, quantile_values, snapshot_date FROM {METRICS_TABLE} \
+         WHERE (instance_uuid, snapshot_date) IN ({keys})"
+    )
+}
+
+/// Renders the newest snapshot of each of the given instances in the Prometheus text
+/// exposition format. Instances that don't have any snapshots yet are skipped.
+pub async fn render(clickhouse: &ClickHouse, instance_uuids: &[String]) -> Result<String> {
+    if instance_uuids.is_empty() {
+        return Ok(String::new());
+    }
+
+    let block = clickhouse
+        .query(latest_snapshots_sql(instance_uuids))
+        .await?;
+
+    let mut snapshots = Vec::with_capacity(block.row_count());
+    for row in block.rows() {
+        let data: String = row.get("data").unwrap_or_default();
+        let snapshot_date: DateTime<Tz> = row.get("snapshot_date")?;
+
+        snapshots.push(LatestSnapshot {
+            instance_uuid: row.get("instance_uuid")?,
+            product: row.get("product").unwrap_or_default(),
+            version: row.get("version").unwrap_or_default(),
+            build_flavour: row.get("build_flavour").unwrap_or_default(),
+            data: serde_json::from_str(&data).unwrap_or(Value::Null),
+            snapshot_date: snapshot_date.with_timezone(&Utc),
+        });
+    }
+
+    if snapshots.is_empty() {
+        return Ok(String::new());
+    }
+
+    let block = clickhouse.query(latest_metrics_sql(&snapshots)).await?;
+    let mut samples = Vec::with_capacity(block.row_count());
+    for row in block.rows() {
+        let kind: String = row.get("type")?;
+        let kind = match kind.parse::<MetricKind>() {
+            Ok(kind) => kind,
+            Err(e) => {
+                warn!("skipping stored metric: {e}");
+                continue;
+            }
+        };
+
+        let label_names: Vec<String> = row.get("label_names")?;
+        let label_values: Vec<String> = row.get("label_values")?;
+        let snapshot_date: DateTime<Tz> = row.get("snapshot_date")?;
+
+        samples.push(MetricSample {
+            instance_uuid: row.get("instance_uuid")?,
+            name: row.get("name")?,
+            kind,
+            labels: label_names.into_iter().zip(label_values).collect(),
+            value: row.get("value")?,
+            count: row.get("count")?,
+            sum: row.get("sum")?,
+            bucket_bounds: row.get("bucket_bounds")?,
+            bucket_counts: row.get("bucket_counts")?,
+            quantiles: row.get("quantiles")?,
+            quantile_values: row.get("quantile_values")?,
+            snapshot_date: snapshot_date.with_timezone(&Utc),
+        });
+    }
+
+    Ok(build(&snapshots, &samples).render())
+}
+
+/// Builds the metric families of the given snapshots, where `samples` are their typed metrics.
+/// Samples that don't belong to one of the snapshots are ignored.
+pub fn build(snapshots: &[LatestSnapshot], samples: &[MetricSample]) -> Exposition {
+    let mut exposition = Exposition::new();
+    let mut labels_of = HashMap::new();
+
+    for snapshot in snapshots {
+        let labels = snapshot.labels();
+        if let Some(family) = exposition.family(
+            "analytics_instance_info",
+            MetricKind::Gauge,
+            Some("Information about the product that an instance runs, always 1."),
+        ) {
+            family.push(labels.clone(), 1.0);
+        }
+
+        if let Some(family) = exposition.family(
+            "analytics_instance_last_snapshot_timestamp_seconds",
+            MetricKind::Gauge,
+            Some("When the newest snapshot of an instance was taken."),
+        ) {
+            family.push(labels.clone(), snapshot.snapshot_date.timestamp() as f64);
+        }
+
+        let mut values = vec![];
+        flatten_json(DATA_PREFIX, &snapshot.data, &mut values);
+        for (name, value) in values {
+            if let Some(family) = exposition.family(&name, MetricKind::Gauge, None) {
+                family.push(labels.clone(), value);
+            }
+        }
+
+        labels_of.insert(
+            snapshot.instance_uuid.as_str(),
+            (snapshot.snapshot_date, labels),
+        );
+    }
+
+    for sample in samples {
+        let instance_labels = match labels_of.get(sample.instance_uuid.as_str()) {
+            Some((snapshot_date, labels)) if *snapshot_date == sample.snapshot_date => labels,
+            _ => continue,
+        };
+
+        let mut labels = instance_labels.clone();
+        for (name, value) in &sample.labels {
+            let name = if INSTANCE_LABELS.contains(&name.as_str()) {
+                format!("exported_{name}")
+            } else {
+                name.clone()
+            };
+
+            labels.push((name, value.clone()));
+        }
+
+        let family = match exposition.family(&sample.name, sample.kind, None) {
+            Some(family) => family,
+            None => {
+                warn!(
+                    "skipping metric {} of instance {}, it was already reported with another type",
+                    sample.name, sample.instance_uuid
+                );
+
+                continue;
+            }
+        };
+
+        match sample.kind {
+            MetricKind::Counter | MetricKind::Gauge => family.push(labels, sample.value),
+            MetricKind::Histogram => family.push_histogram(
+                labels,
+                &sample.bucket_bounds,
+                &sample.bucket_counts,
+                sample.count,
+                sample.sum,
+            ),
+            MetricKind::Summary => family.push_summary(
+                labels,
+                &sample.quantiles,
+                &sample.quantile_values,
+                sample.count,
+                sample.sum,
+            ),
+        }
+    }
+
+    exposition
+}
+
+/// Flattens every number and boolean of a JSON value into `(name, value)` pairs, where the
+/// name is the path to it joined by underscores. Strings and nulls can't be represented, so
+/// they are skipped.
+pub fn flatten_json(prefix: &str, value: &Value, out: &mut Vec<(String, f64)>) {
+    match value {
+        Value::Number(n) => {
+            if let Some(n) = n.as_f64() {
+                out.push((prefix.to_string(), n));
+            }
+        }
+        Value::Bool(b) => out.push((prefix.to_string(), if *b { 1.0 } else { 0.0 })),
+        Value::Object(map) => {
+            for (key, value) in map {
+                flatten_json(&format!("{prefix}_{key}"), value, out);
+            }
+        }
+        Value::Array(values) => {
+            for (i, value) in values.iter().enumerate() {
+                flatten_json(&format!("{prefix}_{i}"), value, out);
+            }
+        }
+        Value::Null | Value::String(_) => {}
+    }
+}
+
+fn in_list(values: &[String]) -> String {
+    values
+        .iter()
+        .map(|v| quote(v))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    #[test]
+    fn flattens_snapshots_into_families() {
+        let snapshot_date = Utc::now();
+        let snapshot = LatestSnapshot {
+            instance_uuid: "waff".into(),
+            product: "charted-server".into(),
+            version: "0.1.0".into(),
+            build_flavour: "GIT".into(),
+            data: json!({ "heap": { "used": 512 }, "healthy": true, "name": "noel" }),
+            snapshot_date,
+        };
+
+        let sample = MetricSample {
+            instance_uuid: "waff".into(),
+            name: "http_requests_total".into(),
+            kind: MetricKind::Counter,
+            labels: vec![("version".into(), "v2".into())],
+            value: 42.0,
+            count: 0,
+            sum: 0.0,
+            bucket_bounds: vec![],
+            bucket_counts: vec![],
+            quantiles: vec![],
+            quantile_values: vec![],
+            snapshot_date,
+        };
+
+        let stale = MetricSample {
+            snapshot_date: snapshot_date - chrono::Duration::seconds(30),
+            ..sample.clone()
+        };
+
+        let rendered = build(&[snapshot], &[sample, stale]).render();
+        let labels = "instance_uuid=\"waff\",product=\"charted-server\",version=\"0.1.0\",build_flavour=\"GIT\"";
+
+        assert!(rendered.contains(&format!("analytics_data_heap_used{{{labels}}} 512\n")));
+        assert!(rendered.contains(&format!("analytics_data_healthy{{{labels}}} 1\n")));
+        assert!(!rendered.contains("analytics_data_name"));
+        assert_eq!(
+            rendered
+                .lines()
+                .filter(|l| l.starts_with("http_requests_total{"))
+                .collect::<Vec<_>>(),
+            vec![format!(
+                "http_requests_total{{{labels},exported_version=\"v2\"}} 42"
+            )]
+        );
+    }
+    #[test]
+    fn queries_the_metrics_of_the_latest_snapshots() {
+        let snapshot = |uuid: &str, timestamp: i64| LatestSnapshot {
+            instance_uuid: uuid.into(),
+            product: "charted-server".into(),
+            version: "0.1.0".into(),
+            build_flavour: "GIT".into(),
+            data: Value::Null,
+            snapshot_date: Utc.timestamp_opt(timestamp, 0).unwrap(),
+        };
+
+        let sql = latest_metrics_sql(&[snapshot("waff", 1685577600), snapshot("owo", 1685581200)]);
+        assert!(sql.ends_with(
+            "WHERE (instance_uuid, snapshot_date) IN (('waff', toDateTime(1685577600, 'UTC')), ('owo', toDateTime(1685581200, 'UTC')))"
+        ));
+    }
+}
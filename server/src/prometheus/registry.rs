@@ -0,0 +1,309 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use once_cell::sync::OnceCell;
+
+use crate::collector::metrics::MetricKind;
+use crate::prometheus::exposition::Exposition;
+
+static METRICS: OnceCell<ServerMetrics> = OnceCell::new();
+
+/// Buckets (in seconds) of the HTTP request latency histograms.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Buckets (in seconds) of the RSA key generation histogram, which is a lot slower
+/// than any request should be.
+const RSA_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// A histogram with fixed buckets, where every bucket counts the observations that are
+/// less than or equal to its bound.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len()],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RouteStats {
+    statuses: BTreeMap<u16, u64>,
+    latency: Histogram,
+}
+
+/// Metrics about the analytics server itself, which are recorded from wherever they happen
+/// and rendered by `GET /metrics`. Metrics that are cheap to read when scraped, like the
+/// amount of ClickHouse calls, aren't kept in here.
+#[derive(Debug)]
+pub struct ServerMetrics {
+    /// keyed by the method and the route that handled the request.
+    requests: Mutex<HashMap<(String, String), RouteStats>>,
+    polls: AtomicU64,
+    poll_failures: AtomicU64,
+    failovers: AtomicU64,
+    failover_failures: AtomicU64,
+    dropped_events: AtomicU64,
+    rsa_key_generation: Mutex<Histogram>,
+
+    /// keyed by the name of the sink and if the write succeeded.
+    sink_writes: Mutex<BTreeMap<(&'static str, bool), u64>>,
+}
+
+impl ServerMetrics {
+    fn new() -> ServerMetrics {
+        ServerMetrics {
+            requests: Mutex::new(HashMap::new()),
+            polls: AtomicU64::new(0),
+            poll_failures: AtomicU64::new(0),
+            failovers: AtomicU64::new(0),
+            failover_failures: AtomicU64::new(0),
+            dropped_events: AtomicU64::new(0),
+            rsa_key_generation: Mutex::new(Histogram::new(RSA_BUCKETS)),
+            sink_writes: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn global() -> &'static ServerMetrics {
+        METRICS.get_or_init(ServerMetrics::new)
+    }
+
+    pub fn record_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        let mut requests = self.requests.lock().unwrap();
+        let stats = requests
+            .entry((method.to_string(), route.to_string()))
+            .or_insert_with(|| RouteStats {
+                statuses: BTreeMap::new(),
+                latency: Histogram::new(LATENCY_BUCKETS),
+            });
+
+        *stats.statuses.entry(status).or_insert(0) += 1;
+        stats.latency.observe(elapsed.as_secs_f64());
+    }
+
+    /// Records the outcome of polling an instance with `RetrieveStats`.
+    pub fn record_poll(&self, success: bool) {
+        let counter = if success {
+            &self.polls
+        } else {
+            &self.poll_failures
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the outcome of switching to a new Redis master after the current one
+    /// went away.
+    pub fn record_failover(&self, success: bool) {
+        let counter = if success {
+            &self.failovers
+        } else {
+            &self.failover_failures
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records events that were dropped after they were accepted, because the events
+    /// buffer was full while the primary sink was failing.
+    pub fn record_dropped_events(&self, count: usize) {
+        self.dropped_events
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_rsa_key_generation(&self, elapsed: Duration) {
+        self.rsa_key_generation
+            .lock()
+            .unwrap()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Records the outcome of writing a batch to a sink.
+    pub fn record_sink_write(&self, sink: &'static str, success: bool) {
+        *self
+            .sink_writes
+            .lock()
+            .unwrap()
+            .entry((sink, success))
+            .or_insert(0) += 1;
+    }
+
+    /// Adds every metric in here to the given exposition.
+    pub fn render(&self, exposition: &mut Exposition) {
+        let mut requests = self
+            .requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, stats)| (key.clone(), stats.clone()))
+            .collect::<Vec<_>>();
+
+        requests.sort_by(|a, b| a.0.cmp(&b.0));
+        for ((method, route), stats) in requests {
+            let labels = vec![("method".to_string(), method), ("route".to_string(), route)];
+            if let Some(family) = exposition.family(
+                "analytics_http_requests_total",
+                MetricKind::Counter,
+                Some("HTTP requests that were handled, by route and status."),
+            ) {
+                for (status, count) in &stats.statuses {
+                    let mut labels = labels.clone();
+                    labels.push(("status".to_string(), status.to_string()));
+                    family.push(labels, *count as f64);
+                }
+            }
+
+            if let Some(family) = exposition.family(
+                "analytics_http_request_duration_seconds",
+                MetricKind::Histogram,
+                Some("How long HTTP requests took to handle, by route."),
+            ) {
+                let latency = &stats.latency;
+                family.push_histogram(
+                    labels,
+                    latency.bounds,
+                    &latency.counts,
+                    latency.count,
+                    latency.sum,
+                );
+            }
+        }
+
+        let counters = [
+            (
+                "analytics_grpc_polls_total",
+                "Instances that were polled for their stats successfully.",
+                &self.polls,
+            ),
+            (
+                "analytics_grpc_poll_failures_total",
+                "Instances that couldn't be polled for their stats.",
+                &self.poll_failures,
+            ),
+            (
+                "analytics_redis_failovers_total",
+                "Times that the server switched to a new Redis master.",
+                &self.failovers,
+            ),
+            (
+                "analytics_redis_failover_failures_total",
+                "Times that the Redis master went away and no new one could be found.",
+                &self.failover_failures,
+            ),
+            (
+                "analytics_dropped_events_total",
+                "Accepted events that were dropped because the events buffer was full.",
+                &self.dropped_events,
+            ),
+        ];
+
+        for (name, help, counter) in counters {
+            if let Some(family) = exposition.family(name, MetricKind::Counter, Some(help)) {
+                family.push(vec![], counter.load(Ordering::Relaxed) as f64);
+            }
+        }
+
+        if let Some(family) = exposition.family(
+            "analytics_rsa_key_generation_seconds",
+            MetricKind::Histogram,
+            Some("How long it took to generate the RSA key pair of an instance."),
+        ) {
+            let histogram = self.rsa_key_generation.lock().unwrap();
+            family.push_histogram(
+                vec![],
+                histogram.bounds,
+                &histogram.counts,
+                histogram.count,
+                histogram.sum,
+            );
+        }
+
+        if let Some(family) = exposition.family(
+            "analytics_sink_writes_total",
+            MetricKind::Counter,
+            Some("Batches that were written to a sink, by sink and result."),
+        ) {
+            for ((sink, success), count) in self.sink_writes.lock().unwrap().iter() {
+                let result = if *success { "success" } else { "failure" };
+                family.push(
+                    vec![
+                        ("sink".to_string(), sink.to_string()),
+                        ("result".to_string(), result.to_string()),
+                    ],
+                    *count as f64,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_request_metrics() {
+        let metrics = ServerMetrics::new();
+        metrics.record_request("GET", "/instances", 200, Duration::from_millis(20));
+        metrics.record_request("GET", "/instances", 200, Duration::from_millis(300));
+        metrics.record_request("GET", "/instances", 403, Duration::from_millis(1));
+        metrics.record_poll(false);
+
+        let mut exposition = Exposition::new();
+        metrics.render(&mut exposition);
+
+        let rendered = exposition.render();
+        let labels = "method=\"GET\",route=\"/instances\"";
+        for line in [
+            format!("analytics_http_requests_total{{{labels},status=\"200\"}} 2"),
+            format!("analytics_http_requests_total{{{labels},status=\"403\"}} 1"),
+            format!("analytics_http_request_duration_seconds_bucket{{{labels},le=\"0.025\"}} 2"),
+            format!("analytics_http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} 3"),
+            format!("analytics_http_request_duration_seconds_count{{{labels}}} 3"),
+            "analytics_grpc_poll_failures_total 1".to_string(),
+            "analytics_rsa_key_generation_seconds_count 0".to_string(),
+        ] {
+            assert!(rendered.lines().any(|l| l == line), "missing {line}");
+        }
+    }
+}
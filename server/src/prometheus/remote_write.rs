@@ -0,0 +1,223 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clickhouse_rs::types::Block;
+use prost::Message;
+use serde::Serialize;
+use thiserror::Error;
+
+/// The ClickHouse table that samples received from Prometheus' `remote_write` are inserted into.
+pub const REMOTE_WRITE_TABLE: &str = "remote_write_samples";
+
+/// Largest size that a decompressed `WriteRequest` can have, Prometheus sends a lot smaller
+/// batches than this by default.
+pub const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+/// Prometheus marks series that went stale with this specific NaN, which isn't a real sample.
+const STALE_NAN: u64 = 0x7ff0000000000002;
+
+/// `prometheus.WriteRequest` from Prometheus' `remote.proto`. Metadata and native histograms
+/// aren't decoded, since they aren't stored.
+#[derive(Clone, PartialEq, Message)]
+pub struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    pub labels: Vec<Label>,
+
+    #[prost(message, repeated, tag = "2")]
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Label {
+    #[prost(string, tag = "1")]
+    pub name: String,
+
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Sample {
+    #[prost(double, tag = "1")]
+    pub value: f64,
+
+    /// milliseconds since the Unix epoch.
+    #[prost(int64, tag = "2")]
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Error)]
+pub enum RemoteWriteError {
+    #[error("body is not valid snappy: {0}")]
+    Snappy(#[from] snap::Error),
+
+    #[error("decompressed body can't be larger than {MAX_DECOMPRESSED_SIZE} bytes")]
+    TooLarge,
+
+    #[error("body is not a valid WriteRequest: {0}")]
+    Protobuf(#[from] prost::DecodeError),
+}
+
+/// A single sample of a series, tagged with the instance that sent it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RemoteSample {
+    pub instance_uuid: String,
+    pub name: String,
+
+    /// labels of the series without `__name__`, sorted by their name.
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+    pub timestamp_ms: i64,
+}
+
+/// Decodes a snappy compressed `WriteRequest`, which is the body of every `remote_write` request.
+pub fn decode(body: &[u8]) -> Result<WriteRequest, RemoteWriteError> {
+    if snap::raw::decompress_len(body)? > MAX_DECOMPRESSED_SIZE {
+        return Err(RemoteWriteError::TooLarge);
+    }
+
+    let decompressed = snap::raw::Decoder::new().decompress_vec(body)?;
+    Ok(WriteRequest::decode(decompressed.as_slice())?)
+}
+
+/// Flattens every series of the request into samples. Series without a `__name__` label and
+/// stale markers are skipped.
+pub fn to_samples(instance_uuid: &str, request: WriteRequest) -> Vec<RemoteSample> {
+    let mut samples = vec![];
+    for series in request.timeseries {
+        let mut name = None;
+        let mut labels = Vec::with_capacity(series.labels.len());
+        for label in series.labels {
+            if label.name == "__name__" {
+                name = Some(label.value);
+            } else {
+                labels.push((label.name, label.value));
+            }
+        }
+
+        let name = match name {
+            Some(name) if !name.is_empty() => name,
+            _ => continue,
+        };
+
+        labels.sort();
+        for sample in series.samples {
+            if sample.value.to_bits() == STALE_NAN {
+                continue;
+            }
+
+            samples.push(RemoteSample {
+                instance_uuid: instance_uuid.to_string(),
+                name: name.clone(),
+                labels: labels.clone(),
+                value: sample.value,
+                timestamp_ms: sample.timestamp,
+            });
+        }
+    }
+
+    samples
+}
+
+/// Builds a ClickHouse [`Block`] out of the given samples, with the columns
+/// laid out the same way as the [`REMOTE_WRITE_TABLE`] table.
+pub fn to_block(samples: &[RemoteSample]) -> Block {
+    Block::new()
+        .column(
+            "instance_uuid",
+            samples
+                .iter()
+                .map(|s| s.instance_uuid.clone())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "name",
+            samples.iter().map(|s| s.name.clone()).collect::<Vec<_>>(),
+        )
+        .column(
+            "label_names",
+            samples
+                .iter()
+                .map(|s| s.labels.iter().map(|(k, _)| k.clone()).collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+        )
+        .column(
+            "label_values",
+            samples
+                .iter()
+                .map(|s| s.labels.iter().map(|(_, v)| v.clone()).collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+        )
+        .column("value", samples.iter().map(|s| s.value).collect::<Vec<_>>())
+        .column(
+            "timestamp_ms",
+            samples.iter().map(|s| s.timestamp_ms).collect::<Vec<_>>(),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `WriteRequest` as sent by Prometheus' `remote_write`, with two samples of
+    /// `http_requests_total`, one of `up` and a stale marker of `up`.
+    const FIXTURE: &[u8] = include_bytes!("../../fixtures/prometheus/write_request.snappy");
+
+    #[test]
+    fn decodes_recorded_write_request() {
+        let request = decode(FIXTURE).unwrap();
+        let samples = to_samples("waff", request);
+
+        assert_eq!(samples.len(), 3);
+        assert_eq!(
+            samples[0],
+            RemoteSample {
+                instance_uuid: "waff".into(),
+                name: "http_requests_total".into(),
+                labels: vec![
+                    ("instance".into(), "localhost:3651".into()),
+                    ("job".into(), "charted-server".into()),
+                    ("method".into(), "GET".into()),
+                ],
+                value: 1027.0,
+                timestamp_ms: 1687392000000,
+            }
+        );
+
+        assert_eq!(samples[1].timestamp_ms, 1687392015000);
+        assert_eq!(samples[2].name, "up");
+        assert_eq!(samples[2].value, 1.0);
+    }
+
+    #[test]
+    fn refuses_invalid_bodies() {
+        assert!(matches!(
+            decode(b"not snappy"),
+            Err(RemoteWriteError::Snappy(_))
+        ));
+
+        let garbage = snap::raw::Encoder::new().compress_vec(&[0xff; 16]).unwrap();
+        assert!(matches!(
+            decode(&garbage),
+            Err(RemoteWriteError::Protobuf(_))
+        ));
+    }
+}
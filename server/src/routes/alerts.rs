@@ -0,0 +1,425 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use chrono::{DateTime, FixedOffset};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{delete, get, patch, post, State};
+use serde::{Deserialize, Serialize};
+
+use crate::alerts::notifier::Notifiers;
+use crate::middleware::auth::{authorize, AuthGuard};
+use crate::models::alert::{parse_channels, AlertChannel, AlertCondition, AlertState};
+use crate::models::response::{
+    empty_response, new_err_resp, new_err_resp_from_err, new_response, new_response_with_status,
+    ApiError, ApiResponse, Empty,
+};
+use crate::models::token::Scope;
+use crate::prisma::{alert_rule, instance, PrismaClient};
+use crate::routes::dashboards::find_instance;
+use crate::snowflake;
+
+#[derive(Deserialize)]
+pub struct CreateAlertRuleRequest {
+    pub name: String,
+    pub expression: String,
+
+    #[serde(default)]
+    pub channels: Vec<AlertChannel>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateAlertRuleRequest {
+    pub name: Option<String>,
+    pub expression: Option<String>,
+    pub channels: Option<Vec<AlertChannel>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AlertRuleResponse {
+    pub id: String,
+    pub instance: String,
+    pub name: String,
+    pub expression: String,
+    pub channels: Vec<AlertChannel>,
+    pub state: AlertState,
+    pub active_since: Option<DateTime<FixedOffset>>,
+    pub last_value: Option<f64>,
+    pub last_evaluated_at: Option<DateTime<FixedOffset>>,
+    pub created_at: DateTime<FixedOffset>,
+}
+
+impl AlertRuleResponse {
+    fn from_data(instance: &instance::Data, data: alert_rule::Data) -> Self {
+        AlertRuleResponse {
+            id: data.id.to_string(),
+            instance: instance.uuid.clone(),
+            name: data.name,
+            expression: data.expression,
+            channels: parse_channels(&data.channels),
+            state: data.state.parse().unwrap_or(AlertState::Inactive),
+            active_since: data.active_since,
+            last_value: data.last_value,
+            last_evaluated_at: data.last_evaluated_at,
+            created_at: data.created_at,
+        }
+    }
+}
+
+/// Resolves an alert rule by its ID, as long as it belongs to the given instance.
+async fn find_rule<T: Serialize + Debug>(
+    prisma: &PrismaClient,
+    instance: &instance::Data,
+    id: &str,
+) -> Result<alert_rule::Data, ApiResponse<T>> {
+    let id = match id.parse::<i64>() {
+        Ok(id) => id,
+        Err(_) => return Err(new_err_resp(400, "Bad alert rule ID")),
+    };
+
+    match prisma
+        .alert_rule()
+        .find_first(vec![
+            alert_rule::id::equals(id),
+            alert_rule::instance_id::equals(instance.id),
+        ])
+        .exec()
+        .await
+    {
+        Ok(Some(rule)) => Ok(rule),
+        Ok(None) => Err(new_err_resp(404, format!("Unknown alert rule {id}"))),
+        Err(e) => {
+            error!("unable to find alert rule {id}: {e}");
+            Err(new_err_resp(500, "Unable to find alert rule"))
+        }
+    }
+}
+
+fn validate_name<T: Serialize + Debug>(name: &str) -> Result<(), ApiResponse<T>> {
+    if name.trim().is_empty() || name.len() > 64 {
+        return Err(new_err_resp(
+            400,
+            "`name` must be between 1 and 64 characters",
+        ));
+    }
+
+    // names end up in notifications, like the subject of emails
+    if name.chars().any(char::is_control) {
+        return Err(new_err_resp(400, "`name` can't contain control characters"));
+    }
+
+    Ok(())
+}
+
+fn validate_expression<T: Serialize + Debug>(expression: &str) -> Result<(), ApiResponse<T>> {
+    match AlertCondition::parse(expression) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(new_err_resp(400, format!("`expression` is invalid: {e}"))),
+    }
+}
+
+/// Makes sure that notifications of every channel can be sent, and returns them as they
+/// are stored.
+fn validate_channels<T: Serialize + Debug>(
+    notifiers: &Notifiers,
+    channels: &[AlertChannel],
+) -> Result<Vec<String>, ApiResponse<T>> {
+    match channels.iter().find(|c| !notifiers.has(**c)) {
+        Some(channel) => Err(new_err_resp(
+            400,
+            format!("The `{channel}` channel isn't configured in `alerts.{channel}`"),
+        )),
+        None => Ok(channels.iter().map(|c| c.as_str().to_string()).collect()),
+    }
+}
+
+#[get("/<uuid>/alerts")]
+pub async fn list_alert_rules(
+    auth: Result<AuthGuard, ApiError>,
+    uuid: String,
+    prisma: &State<Arc<PrismaClient>>,
+) -> ApiResponse<Vec<AlertRuleResponse>> {
+    let principal = match authorize(auth, Scope::StatsRead) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    let instance = match find_instance(prisma, &principal, &uuid).await {
+        Ok(instance) => instance,
+        Err(resp) => return resp,
+    };
+
+    match prisma
+        .alert_rule()
+        .find_many(vec![alert_rule::instance_id::equals(instance.id)])
+        .exec()
+        .await
+    {
+        Ok(rules) => new_response(
+            rules
+                .into_iter()
+                .map(|r| AlertRuleResponse::from_data(&instance, r))
+                .collect(),
+        ),
+        Err(e) => {
+            error!("unable to list alert rules for instance {uuid}: {e}");
+            new_err_resp(500, "Unable to list alert rules")
+        }
+    }
+}
+
+#[post("/<uuid>/alerts", format = "json", data = "<body>")]
+pub async fn create_alert_rule(
+    auth: Result<AuthGuard, ApiError>,
+    uuid: String,
+    body: Json<CreateAlertRuleRequest>,
+    prisma: &State<Arc<PrismaClient>>,
+    notifiers: &State<Notifiers>,
+) -> ApiResponse<AlertRuleResponse> {
+    let principal = match authorize(auth, Scope::AlertsWrite) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    let instance = match find_instance(prisma, &principal, &uuid).await {
+        Ok(instance) => instance,
+        Err(resp) => return resp,
+    };
+
+    let body = body.into_inner();
+    if let Err(resp) = validate_name(&body.name) {
+        return resp;
+    }
+
+    if let Err(resp) = validate_expression(&body.expression) {
+        return resp;
+    }
+
+    let channels = match validate_channels(notifiers, &body.channels) {
+        Ok(channels) => channels,
+        Err(resp) => return resp,
+    };
+
+    match prisma
+        .alert_rule()
+        .create(
+            body.name,
+            body.expression,
+            instance::id::equals(instance.id),
+            snowflake::generate(),
+            vec![alert_rule::channels::set(channels)],
+        )
+        .exec()
+        .await
+    {
+        Ok(rule) => new_response_with_status(
+            Status::Created.code,
+            AlertRuleResponse::from_data(&instance, rule),
+        ),
+        Err(e) => {
+            error!("unable to create alert rule for instance {uuid}: {e}");
+            new_err_resp(500, "Unable to create alert rule")
+        }
+    }
+}
+
+#[get("/<uuid>/alerts/<id>")]
+pub async fn get_alert_rule(
+    auth: Result<AuthGuard, ApiError>,
+    uuid: String,
+    id: String,
+    prisma: &State<Arc<PrismaClient>>,
+) -> ApiResponse<AlertRuleResponse> {
+    let principal = match authorize(auth, Scope::StatsRead) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    let instance = match find_instance(prisma, &principal, &uuid).await {
+        Ok(instance) => instance,
+        Err(resp) => return resp,
+    };
+
+    match find_rule(prisma, &instance, &id).await {
+        Ok(rule) => new_response(AlertRuleResponse::from_data(&instance, rule)),
+        Err(resp) => resp,
+    }
+}
+
+/// Updates the rule. Changing the expression starts the rule over as `inactive`, since
+/// its state was about the old condition.
+#[patch("/<uuid>/alerts/<id>", format = "json", data = "<body>")]
+pub async fn update_alert_rule(
+    auth: Result<AuthGuard, ApiError>,
+    uuid: String,
+    id: String,
+    body: Json<UpdateAlertRuleRequest>,
+    prisma: &State<Arc<PrismaClient>>,
+    notifiers: &State<Notifiers>,
+) -> ApiResponse<AlertRuleResponse> {
+    let principal = match authorize(auth, Scope::AlertsWrite) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    let instance = match find_instance(prisma, &principal, &uuid).await {
+        Ok(instance) => instance,
+        Err(resp) => return resp,
+    };
+
+    let existing = match find_rule(prisma, &instance, &id).await {
+        Ok(rule) => rule,
+        Err(resp) => return resp,
+    };
+
+    let body = body.into_inner();
+    let mut params = vec![];
+    if let Some(name) = body.name {
+        if let Err(resp) = validate_name(&name) {
+            return resp;
+        }
+
+        params.push(alert_rule::name::set(name));
+    }
+
+    if let Some(expression) = body.expression {
+        if let Err(resp) = validate_expression(&expression) {
+            return resp;
+        }
+
+        if expression != existing.expression {
+            params.push(alert_rule::expression::set(expression));
+            params.push(alert_rule::state::set(
+                AlertState::Inactive.as_str().to_string(),
+            ));
+            params.push(alert_rule::active_since::set(None));
+        }
+    }
+
+    if let Some(channels) = body.channels {
+        match validate_channels(notifiers, &channels) {
+            Ok(channels) => params.push(alert_rule::channels::set(channels)),
+            Err(resp) => return resp,
+        }
+    }
+
+    if params.is_empty() {
+        return new_response(AlertRuleResponse::from_data(&instance, existing));
+    }
+
+    match prisma
+        .alert_rule()
+        .update(alert_rule::id::equals(existing.id), params)
+        .exec()
+        .await
+    {
+        Ok(rule) => new_response(AlertRuleResponse::from_data(&instance, rule)),
+        Err(e) => {
+            error!("unable to update alert rule {}: {e}", existing.id);
+            new_err_resp(500, "Unable to update alert rule")
+        }
+    }
+}
+
+#[delete("/<uuid>/alerts/<id>")]
+pub async fn delete_alert_rule(
+    auth: Result<AuthGuard, ApiError>,
+    uuid: String,
+    id: String,
+    prisma: &State<Arc<PrismaClient>>,
+) -> ApiResponse<Empty> {
+    let principal = match authorize(auth, Scope::AlertsWrite) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    let instance = match find_instance(prisma, &principal, &uuid).await {
+        Ok(instance) => instance,
+        Err(resp) => return resp,
+    };
+
+    let existing = match find_rule(prisma, &instance, &id).await {
+        Ok(rule) => rule,
+        Err(resp) => return resp,
+    };
+
+    match prisma
+        .alert_rule()
+        .delete(alert_rule::id::equals(existing.id))
+        .exec()
+        .await
+    {
+        Ok(_) => empty_response(Some(Status::NoContent)),
+        Err(e) => {
+            error!("unable to delete alert rule {}: {e}", existing.id);
+            new_err_resp(500, "Unable to delete alert rule")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::auth::Principal;
+    use crate::routes::dashboards::check_owner;
+
+    const INSTANCE: &str = "4d9e9b4c-9d3a-4a4e-8a4c-1f5b8f0e2a10";
+
+    fn user(id: i64, scopes: Vec<Scope>) -> Principal {
+        Principal::User {
+            id,
+            token_id: 1,
+            scopes,
+        }
+    }
+
+    /// Returns the error code that an alert route responds with if the principal tries to
+    /// reach the rules of an instance with the given owner.
+    fn rejection(principal: &Principal, owner: Option<i64>) -> Option<String> {
+        check_owner::<Vec<AlertRuleResponse>>(principal, INSTANCE, owner)
+            .err()
+            .map(|resp| {
+                serde_json::to_value(resp).unwrap()["errors"][0]["code"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+    }
+
+    #[test]
+    fn hides_rules_of_instances_owned_by_someone_else() {
+        let owner = user(1, vec![Scope::StatsRead, Scope::AlertsWrite]);
+        assert_eq!(rejection(&owner, Some(1)), None);
+
+        let other = user(2, vec![Scope::StatsRead, Scope::AlertsWrite]);
+        assert_eq!(rejection(&other, Some(1)).as_deref(), Some("404"));
+        assert_eq!(rejection(&other, None).as_deref(), Some("404"));
+
+        let admin = user(3, vec![Scope::Admin]);
+        assert_eq!(rejection(&admin, Some(1)), None);
+        assert_eq!(rejection(&Principal::Root, None), None);
+    }
+
+    #[test]
+    fn rejects_names_with_control_characters() {
+        assert!(validate_name::<Empty>("heap usage").is_ok());
+        assert!(validate_name::<Empty>("heap\r\nBcc: everyone@noelware.org").is_err());
+        assert!(validate_name::<Empty>("heap\tusage").is_err());
+        assert!(validate_name::<Empty>(" ").is_err());
+    }
+}
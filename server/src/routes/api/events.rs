@@ -0,0 +1,133 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use rocket::data::ToByteUnit;
+use rocket::http::ContentType;
+use rocket::{post, Data, State};
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::config::Config;
+use crate::endpoints::endpoint_manager::EndpointManager;
+use crate::events::buffer::{BufferFull, EventBuffer};
+use crate::middleware::auth::{authorize, AuthGuard};
+use crate::models::event::parse_events;
+use crate::models::response::{
+    new_err_resp, new_err_resp_from_err, new_err_resp_from_errs, new_response_with_errors,
+    ApiError, ApiResponse,
+};
+use crate::models::token::Scope;
+use crate::routes::instances::accessible_instances;
+
+#[derive(Serialize, Debug)]
+pub struct IngestEventsResponse {
+    pub accepted: usize,
+    pub rejected: usize,
+}
+
+/// Ingests a single event, a JSON array of events or a newline-delimited JSON batch of
+/// events if the `Content-Type` is `application/x-ndjson`. Every event that was rejected
+/// has its reason listed in the `errors` array.
+#[post("/events", data = "<body>")]
+pub async fn ingest_events(
+    auth: Result<AuthGuard, ApiError>,
+    content_type: Option<&ContentType>,
+    body: Data<'_>,
+    config: &State<Config>,
+    buffer: &State<Arc<EventBuffer>>,
+    manager: &State<Arc<Mutex<EndpointManager>>>,
+) -> ApiResponse<IngestEventsResponse> {
+    let principal = match authorize(auth, Scope::InstancesWrite) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    let limit = config
+        .events
+        .clone()
+        .unwrap_or_default()
+        .max_body_size
+        .unwrap_or(5 * 1024 * 1024);
+
+    let body = match body.open(limit.bytes()).into_string().await {
+        Ok(body) if body.is_complete() => body.into_inner(),
+        Ok(_) => return new_err_resp(413, format!("Body can't be larger than {limit} bytes")),
+        Err(e) => return new_err_resp(400, format!("Unable to read body: {e}")),
+    };
+
+    let ndjson = content_type
+        .map(|ct| ct.sub() == "x-ndjson" || ct.sub() == "jsonl")
+        .unwrap_or(false);
+
+    let parsed = match parse_events(&body, ndjson) {
+        Ok(parsed) => parsed,
+        Err(e) => return new_err_resp(400, e),
+    };
+
+    if parsed.is_empty() {
+        return new_err_resp(400, "No events were sent");
+    }
+
+    // more events than the buffer can ever hold would be rejected on every retry
+    if parsed.len() > buffer.capacity() {
+        return new_err_resp(
+            413,
+            format!("Can't send more than {} events at once", buffer.capacity()),
+        );
+    }
+
+    // events for instances that the principal doesn't own are reported as unknown, the
+    // same way as the instance routes do
+    let accessible = accessible_instances(
+        manager,
+        &principal,
+        parsed.iter().flatten().map(|event| event.instance),
+    )
+    .await;
+
+    let mut events = Vec::with_capacity(parsed.len());
+    let mut errors = Vec::new();
+    for (index, result) in parsed.into_iter().enumerate() {
+        match result {
+            Ok(event) if accessible.contains(&event.instance) => events.push(event),
+            Ok(event) => errors.push(ApiError {
+                code: "404".into(),
+                message: format!("event #{index}: unknown instance {}", event.instance),
+            }),
+            Err(reason) => errors.push(ApiError {
+                code: "400".into(),
+                message: format!("event #{index}: {reason}"),
+            }),
+        }
+    }
+
+    let accepted = events.len();
+    let rejected = errors.len();
+    if accepted == 0 {
+        return new_err_resp_from_errs(errors);
+    }
+
+    if let Err(e) = buffer.push(events).await {
+        if e.downcast_ref::<BufferFull>().is_some() {
+            return new_err_resp(503, "Events buffer is full, try again later");
+        }
+
+        error!("unable to insert events into clickhouse: {e}");
+    }
+
+    new_response_with_errors(202, IngestEventsResponse { accepted, rejected }, errors)
+}
@@ -0,0 +1,217 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use rocket::data::ToByteUnit;
+use rocket::http::ContentType;
+use rocket::{get, post, Data, State};
+use serde::Serialize;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::clickhouse::client::ClickHouse;
+use crate::endpoints::endpoint_manager::EndpointManager;
+use crate::logs::store::find;
+use crate::middleware::auth::{authorize, AuthGuard};
+use crate::models::log::{parse_logs, LogSearch, LogSearchResults};
+use crate::models::response::{
+    new_err_resp, new_err_resp_from_err, new_err_resp_from_errs, new_response,
+    new_response_with_errors, ApiError, ApiResponse,
+};
+use crate::models::token::Scope;
+use crate::routes::instances::{accessible_instances, find_endpoint};
+use crate::sinks::sink::{Batch, Sinks};
+
+/// Largest body that can be sent to `POST /api/logs`.
+const MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+
+#[derive(Serialize, Debug)]
+pub struct IngestLogsResponse {
+    pub accepted: usize,
+    pub rejected: usize,
+}
+
+/// Ingests a single log line, a JSON array of lines or a newline-delimited JSON batch of
+/// lines if the `Content-Type` is `application/x-ndjson`, like what the Logstash formatter
+/// writes. Lines without an `instance` belong to the `instance` query parameter. Every
+/// line that was rejected has its reason listed in the `errors` array.
+#[post("/logs?<instance>", data = "<body>")]
+pub async fn ingest_logs(
+    auth: Result<AuthGuard, ApiError>,
+    instance: Option<String>,
+    content_type: Option<&ContentType>,
+    body: Data<'_>,
+    sinks: &State<Sinks>,
+    manager: &State<Arc<Mutex<EndpointManager>>>,
+) -> ApiResponse<IngestLogsResponse> {
+    let principal = match authorize(auth, Scope::InstancesWrite) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    let instance = match instance.map(|i| Uuid::parse_str(&i)).transpose() {
+        Ok(instance) => instance,
+        Err(_) => return new_err_resp(400, "Bad Uuid"),
+    };
+
+    let body = match body.open(MAX_BODY_SIZE.bytes()).into_string().await {
+        Ok(body) if body.is_complete() => body.into_inner(),
+        Ok(_) => {
+            return new_err_resp(
+                413,
+                format!("Body can't be larger than {MAX_BODY_SIZE} bytes"),
+            )
+        }
+        Err(e) => return new_err_resp(400, format!("Unable to read body: {e}")),
+    };
+
+    let ndjson = content_type
+        .map(|ct| ct.sub() == "x-ndjson" || ct.sub() == "jsonl")
+        .unwrap_or(false);
+
+    let parsed = match parse_logs(&body, ndjson, instance) {
+        Ok(parsed) => parsed,
+        Err(e) => return new_err_resp(400, e),
+    };
+
+    if parsed.is_empty() {
+        return new_err_resp(400, "No log lines were sent");
+    }
+
+    // lines of instances that the principal doesn't own are reported as unknown, the same
+    // way as the search does
+    let accessible = accessible_instances(
+        manager,
+        &principal,
+        parsed.iter().flatten().map(|line| line.instance),
+    )
+    .await;
+
+    let mut lines = Vec::with_capacity(parsed.len());
+    let mut errors = Vec::new();
+    for (index, result) in parsed.into_iter().enumerate() {
+        match result {
+            Ok(line) if accessible.contains(&line.instance) => lines.push(line),
+            Ok(line) => errors.push(ApiError {
+                code: "404".into(),
+                message: format!("line #{index}: unknown instance {}", line.instance),
+            }),
+            Err(reason) => errors.push(ApiError {
+                code: "400".into(),
+                message: format!("line #{index}: {reason}"),
+            }),
+        }
+    }
+
+    let accepted = lines.len();
+    let rejected = errors.len();
+    if accepted == 0 {
+        return new_err_resp_from_errs(errors);
+    }
+
+    if let Err(e) = sinks.write(Batch::Logs(&lines)).await {
+        error!("unable to store log lines: {e}");
+        return new_err_resp(500, "Unable to store log lines");
+    }
+
+    new_response_with_errors(200, IngestLogsResponse { accepted, rejected }, errors)
+}
+
+/// Searches the log lines of the instances that the principal can see, newest first.
+/// Every `field` parameter is a `key:value` pair that lines must have.
+#[get("/logs/search?<instance>&<from>&<to>&<level>&<q>&<mode>&<field>&<limit>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn search_logs(
+    auth: Result<AuthGuard, ApiError>,
+    instance: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    level: Option<String>,
+    q: Option<String>,
+    mode: Option<String>,
+    field: Vec<String>,
+    limit: Option<usize>,
+    manager: &State<Arc<Mutex<EndpointManager>>>,
+    clickhouse: &State<Arc<ClickHouse>>,
+) -> ApiResponse<LogSearchResults> {
+    let principal = match authorize(auth, Scope::StatsRead) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    let instances = {
+        let mut endpoint_manager = manager.lock().await;
+        match instance {
+            Some(id) => {
+                let id = match Uuid::parse_str(id.as_str()) {
+                    Ok(id) => id.to_string(),
+                    Err(_) => return new_err_resp(400, "Bad Uuid"),
+                };
+
+                if find_endpoint(&mut endpoint_manager, &principal, &id)
+                    .await
+                    .is_none()
+                {
+                    return new_err_resp(404, format!("Unknown instance {id}"));
+                }
+
+                Some(vec![id])
+            }
+            None if principal.has_scope(Scope::Admin) => None,
+            None => match endpoint_manager.get_endpoints().await {
+                Ok(endpoints) => Some(
+                    endpoints
+                        .into_iter()
+                        .filter(|e| principal.can_access_owned(e.owner_id))
+                        .map(|e| e.instance_name)
+                        .collect(),
+                ),
+                Err(e) => {
+                    error!("unable to list endpoints: {e}");
+                    return new_err_resp(500, "Unable to list instances");
+                }
+            },
+        }
+    };
+
+    let search = match LogSearch::parse(
+        instances,
+        from.as_deref(),
+        to.as_deref(),
+        level.as_deref(),
+        q.as_deref(),
+        mode.as_deref(),
+        &field,
+        limit,
+    ) {
+        Ok(search) => search,
+        Err(e) => return new_err_resp(400, e),
+    };
+
+    let logs = match find(clickhouse, &search).await {
+        Ok(logs) => logs,
+        Err(e) => {
+            error!("unable to search log lines: {e}");
+            return new_err_resp(500, "Unable to search logs");
+        }
+    };
+
+    new_response(LogSearchResults {
+        from: search.from,
+        to: search.to,
+        logs,
+    })
+}
@@ -0,0 +1,69 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use rocket::data::ToByteUnit;
+use rocket::http::Status;
+use rocket::{post, Data, State};
+
+use crate::middleware::instance_auth::InstanceGuard;
+use crate::models::response::{
+    empty_response, new_err_resp, new_err_resp_from_err, ApiError, ApiResponse, Empty,
+};
+use crate::prometheus::remote_write::{decode, to_samples};
+use crate::sinks::sink::{Batch, Sinks};
+
+/// Largest snappy compressed body that is accepted.
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// Receives samples from Prometheus' `remote_write`, as a snappy compressed `WriteRequest`.
+/// The instance authenticates with its service token, and every sample is tagged with it.
+///
+/// Prometheus retries `5xx` responses and drops the batch on `4xx` ones, so only failing
+/// to store the samples is a `500`.
+#[post("/v1/write", data = "<body>")]
+pub async fn remote_write(
+    auth: Result<InstanceGuard, ApiError>,
+    body: Data<'_>,
+    sinks: &State<Sinks>,
+) -> ApiResponse<Empty> {
+    let instance = match auth {
+        Ok(guard) => guard.endpoint.instance_name,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    let body = match body.open(MAX_BODY_SIZE.bytes()).into_bytes().await {
+        Ok(body) if body.is_complete() => body.into_inner(),
+        Ok(_) => {
+            return new_err_resp(
+                413,
+                format!("Body can't be larger than {MAX_BODY_SIZE} bytes"),
+            )
+        }
+        Err(e) => return new_err_resp(400, format!("Unable to read body: {e}")),
+    };
+
+    let request = match decode(&body) {
+        Ok(request) => request,
+        Err(e) => return new_err_resp(400, e.to_string()),
+    };
+
+    let samples = to_samples(&instance, request);
+    if let Err(e) = sinks.write(Batch::RemoteWrite(&samples)).await {
+        error!("unable to store remote_write samples of instance {instance}: {e}");
+        return new_err_resp(500, "Unable to store samples");
+    }
+
+    empty_response(Some(Status::NoContent))
+}
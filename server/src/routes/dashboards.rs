@@ -0,0 +1,385 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{delete, get, patch, post, State};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::middleware::auth::{authorize, AuthGuard, Principal};
+use crate::models::dashboard::Dashboard;
+use crate::models::response::{
+    empty_response, new_err_resp, new_err_resp_from_err, new_err_resp_from_errs, new_response,
+    new_response_with_status, ApiError, ApiResponse, Empty,
+};
+use crate::models::token::Scope;
+use crate::prisma::{dashboard, instance, PrismaClient};
+use crate::snowflake;
+
+#[derive(Deserialize)]
+pub struct CreateDashboardRequest {
+    pub display_name: String,
+    pub data: Value,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateDashboardRequest {
+    pub display_name: Option<String>,
+    pub data: Option<Value>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DashboardResponse {
+    pub id: String,
+    pub instance: String,
+    pub display_name: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl DashboardResponse {
+    fn from_data(instance: &instance::Data, data: dashboard::Data, with_data: bool) -> Self {
+        DashboardResponse {
+            id: data.id.to_string(),
+            instance: instance.uuid.clone(),
+            display_name: data.display_name,
+            data: if with_data { Some(data.data) } else { None },
+        }
+    }
+}
+
+/// Checks that the principal can see an instance with the given owner. Instances owned by
+/// someone else are reported as missing, so their UUIDs can't be probed.
+pub(crate) fn check_owner<T: Serialize + Debug>(
+    principal: &Principal,
+    uuid: &str,
+    owner_id: Option<i64>,
+) -> Result<(), ApiResponse<T>> {
+    if principal.can_access_owned(owner_id) {
+        Ok(())
+    } else {
+        Err(new_err_resp(404, format!("Unknown instance {uuid}")))
+    }
+}
+
+/// Resolves the instance that a dashboard route is scoped to by its UUID, as long as the
+/// principal is allowed to see it.
+pub(crate) async fn find_instance<T: Serialize + Debug>(
+    prisma: &PrismaClient,
+    principal: &Principal,
+    uuid: &str,
+) -> Result<instance::Data, ApiResponse<T>> {
+    if Uuid::parse_str(uuid).is_err() {
+        return Err(new_err_resp(400, "Bad Uuid"));
+    }
+
+    match prisma
+        .instance()
+        .find_unique(instance::uuid::equals(uuid.to_string()))
+        .exec()
+        .await
+    {
+        Ok(Some(instance)) => check_owner(principal, uuid, instance.owner_id).map(|_| instance),
+        Ok(None) => Err(new_err_resp(404, format!("Unknown instance {uuid}"))),
+        Err(e) => {
+            error!("unable to find instance {uuid}: {e}");
+            Err(new_err_resp(500, "Unable to find instance"))
+        }
+    }
+}
+
+/// Resolves a dashboard by its ID, as long as it belongs to the given instance.
+async fn find_dashboard<T: Serialize + Debug>(
+    prisma: &PrismaClient,
+    instance: &instance::Data,
+    id: &str,
+) -> Result<dashboard::Data, ApiResponse<T>> {
+    let id = match id.parse::<i64>() {
+        Ok(id) => id,
+        Err(_) => return Err(new_err_resp(400, "Bad dashboard ID")),
+    };
+
+    match prisma
+        .dashboard()
+        .find_first(vec![
+            dashboard::id::equals(id),
+            dashboard::instance_id::equals(instance.id),
+        ])
+        .exec()
+        .await
+    {
+        Ok(Some(dashboard)) => Ok(dashboard),
+        Ok(None) => Err(new_err_resp(404, format!("Unknown dashboard {id}"))),
+        Err(e) => {
+            error!("unable to find dashboard {id}: {e}");
+            Err(new_err_resp(500, "Unable to find dashboard"))
+        }
+    }
+}
+
+/// Validates the dashboard's `data` and turns every problem into an [`ApiError`].
+fn validate_data<T: Serialize + Debug>(data: Value) -> Result<Value, ApiResponse<T>> {
+    match Dashboard::from_json(data) {
+        Ok(dashboard) => Ok(serde_json::to_value(dashboard).unwrap()),
+        Err(errors) => Err(new_err_resp_from_errs(
+            errors
+                .into_iter()
+                .map(|message| ApiError {
+                    code: "400".into(),
+                    message,
+                })
+                .collect(),
+        )),
+    }
+}
+
+fn validate_display_name<T: Serialize + Debug>(name: &str) -> Result<(), ApiResponse<T>> {
+    if name.trim().is_empty() || name.len() > 64 {
+        return Err(new_err_resp(
+            400,
+            "`display_name` must be between 1 and 64 characters",
+        ));
+    }
+
+    Ok(())
+}
+
+#[get("/<uuid>/dashboards")]
+pub async fn list_dashboards(
+    auth: Result<AuthGuard, ApiError>,
+    uuid: String,
+    prisma: &State<Arc<PrismaClient>>,
+) -> ApiResponse<Vec<DashboardResponse>> {
+    let principal = match authorize(auth, Scope::StatsRead) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    let instance = match find_instance(prisma, &principal, &uuid).await {
+        Ok(instance) => instance,
+        Err(resp) => return resp,
+    };
+
+    match prisma
+        .dashboard()
+        .find_many(vec![dashboard::instance_id::equals(instance.id)])
+        .exec()
+        .await
+    {
+        Ok(dashboards) => new_response(
+            dashboards
+                .into_iter()
+                .map(|d| DashboardResponse::from_data(&instance, d, false))
+                .collect(),
+        ),
+        Err(e) => {
+            error!("unable to list dashboards for instance {uuid}: {e}");
+            new_err_resp(500, "Unable to list dashboards")
+        }
+    }
+}
+
+#[post("/<uuid>/dashboards", format = "json", data = "<body>")]
+pub async fn create_dashboard(
+    auth: Result<AuthGuard, ApiError>,
+    uuid: String,
+    body: Json<CreateDashboardRequest>,
+    prisma: &State<Arc<PrismaClient>>,
+) -> ApiResponse<DashboardResponse> {
+    let principal = match authorize(auth, Scope::DashboardsWrite) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    let instance = match find_instance(prisma, &principal, &uuid).await {
+        Ok(instance) => instance,
+        Err(resp) => return resp,
+    };
+
+    let body = body.into_inner();
+    if let Err(resp) = validate_display_name(&body.display_name) {
+        return resp;
+    }
+
+    let data = match validate_data(body.data) {
+        Ok(data) => data,
+        Err(resp) => return resp,
+    };
+
+    match prisma
+        .dashboard()
+        .create(
+            body.display_name,
+            instance::id::equals(instance.id),
+            data,
+            snowflake::generate(),
+            vec![],
+        )
+        .exec()
+        .await
+    {
+        Ok(dashboard) => new_response_with_status(
+            Status::Created.code,
+            DashboardResponse::from_data(&instance, dashboard, true),
+        ),
+        Err(e) => {
+            error!("unable to create dashboard for instance {uuid}: {e}");
+            new_err_resp(500, "Unable to create dashboard")
+        }
+    }
+}
+
+#[get("/<uuid>/dashboards/<id>")]
+pub async fn get_dashboard(
+    auth: Result<AuthGuard, ApiError>,
+    uuid: String,
+    id: String,
+    prisma: &State<Arc<PrismaClient>>,
+) -> ApiResponse<DashboardResponse> {
+    let principal = match authorize(auth, Scope::StatsRead) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    let instance = match find_instance(prisma, &principal, &uuid).await {
+        Ok(instance) => instance,
+        Err(resp) => return resp,
+    };
+
+    match find_dashboard(prisma, &instance, &id).await {
+        Ok(dashboard) => new_response(DashboardResponse::from_data(&instance, dashboard, true)),
+        Err(resp) => resp,
+    }
+}
+
+/// Returns the dashboard's layout as-is, so it can be loaded by the web client or
+/// imported into another instance.
+#[get("/<uuid>/dashboards/<id>/dashboard.json")]
+pub async fn export_dashboard(
+    auth: Result<AuthGuard, ApiError>,
+    uuid: String,
+    id: String,
+    prisma: &State<Arc<PrismaClient>>,
+) -> Result<Json<Value>, ApiResponse<Empty>> {
+    let principal = match authorize(auth, Scope::StatsRead) {
+        Ok(principal) => principal,
+        Err(e) => return Err(new_err_resp_from_err(e)),
+    };
+
+    let instance = find_instance(prisma, &principal, &uuid).await?;
+    let dashboard = find_dashboard(prisma, &instance, &id).await?;
+
+    Ok(Json(dashboard.data))
+}
+
+#[patch("/<uuid>/dashboards/<id>", format = "json", data = "<body>")]
+pub async fn update_dashboard(
+    auth: Result<AuthGuard, ApiError>,
+    uuid: String,
+    id: String,
+    body: Json<UpdateDashboardRequest>,
+    prisma: &State<Arc<PrismaClient>>,
+) -> ApiResponse<DashboardResponse> {
+    let principal = match authorize(auth, Scope::DashboardsWrite) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    let instance = match find_instance(prisma, &principal, &uuid).await {
+        Ok(instance) => instance,
+        Err(resp) => return resp,
+    };
+
+    let existing = match find_dashboard(prisma, &instance, &id).await {
+        Ok(dashboard) => dashboard,
+        Err(resp) => return resp,
+    };
+
+    let body = body.into_inner();
+    let mut params = vec![];
+    if let Some(display_name) = body.display_name {
+        if let Err(resp) = validate_display_name(&display_name) {
+            return resp;
+        }
+
+        params.push(dashboard::display_name::set(display_name));
+    }
+
+    if let Some(data) = body.data {
+        match validate_data(data) {
+            Ok(data) => params.push(dashboard::data::set(data)),
+            Err(resp) => return resp,
+        }
+    }
+
+    if params.is_empty() {
+        return new_response(DashboardResponse::from_data(&instance, existing, true));
+    }
+
+    match prisma
+        .dashboard()
+        .update(dashboard::id::equals(existing.id), params)
+        .exec()
+        .await
+    {
+        Ok(dashboard) => new_response(DashboardResponse::from_data(&instance, dashboard, true)),
+        Err(e) => {
+            error!("unable to update dashboard {}: {e}", existing.id);
+            new_err_resp(500, "Unable to update dashboard")
+        }
+    }
+}
+
+#[delete("/<uuid>/dashboards/<id>")]
+pub async fn delete_dashboard(
+    auth: Result<AuthGuard, ApiError>,
+    uuid: String,
+    id: String,
+    prisma: &State<Arc<PrismaClient>>,
+) -> ApiResponse<Empty> {
+    let principal = match authorize(auth, Scope::DashboardsWrite) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    let instance = match find_instance(prisma, &principal, &uuid).await {
+        Ok(instance) => instance,
+        Err(resp) => return resp,
+    };
+
+    let existing = match find_dashboard(prisma, &instance, &id).await {
+        Ok(dashboard) => dashboard,
+        Err(resp) => return resp,
+    };
+
+    match prisma
+        .dashboard()
+        .delete(dashboard::id::equals(existing.id))
+        .exec()
+        .await
+    {
+        Ok(_) => empty_response(Some(Status::NoContent)),
+        Err(e) => {
+            error!("unable to delete dashboard {}: {e}", existing.id);
+            new_err_resp(500, "Unable to delete dashboard")
+        }
+    }
+}
@@ -0,0 +1,159 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use rocket::{get, State};
+use serde::Serialize;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::clickhouse::client::ClickHouse;
+use crate::endpoints::endpoint_manager::EndpointManager;
+use crate::endpoints::health::{transitions_sql, uptime_sql, HealthCheck, HealthStatus};
+use crate::middleware::auth::{authorize, AuthGuard};
+use crate::models::response::{
+    new_err_resp, new_err_resp_from_err, new_response, ApiError, ApiResponse,
+};
+use crate::models::stats::parse_step;
+use crate::models::token::Scope;
+use crate::routes::instances::find_endpoint;
+
+/// How many status transitions are returned at most.
+const MAX_TRANSITIONS: usize = 100;
+
+#[derive(Serialize, Debug)]
+pub struct HealthTransition {
+    pub from: Option<HealthStatus>,
+    pub to: HealthStatus,
+    pub latency_ms: u64,
+    pub error: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InstanceHealth {
+    pub uuid: String,
+    pub current: Option<HealthCheck>,
+
+    /// The window (in seconds) that `checks`, `uptime` and `transitions` cover.
+    pub window: i64,
+    pub checks: u64,
+
+    /// The percentage of checks in the window where the instance wasn't down, or
+    /// `null` if it wasn't checked at all.
+    pub uptime: Option<f64>,
+    pub transitions: Vec<HealthTransition>,
+}
+
+#[get("/<id>/health?<window>")]
+pub async fn instance_health(
+    auth: Result<AuthGuard, ApiError>,
+    id: String,
+    window: Option<String>,
+    manager: &State<Arc<Mutex<EndpointManager>>>,
+    clickhouse: &State<Arc<ClickHouse>>,
+) -> ApiResponse<InstanceHealth> {
+    let principal = match authorize(auth, Scope::StatsRead) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    let id = match Uuid::parse_str(id.as_str()) {
+        Ok(id) => id.to_string(),
+        Err(_) => return new_err_resp(400, "Bad Uuid"),
+    };
+
+    let window = match parse_step(window.as_deref().unwrap_or("24h")) {
+        Ok(window) => window,
+        Err(_) => return new_err_resp(400, "`window` is not a valid duration"),
+    };
+
+    let current = {
+        let mut endpoint_manager = manager.lock().await;
+        if find_endpoint(&mut endpoint_manager, &principal, &id)
+            .await
+            .is_none()
+        {
+            return new_err_resp(404, format!("Unknown instance {id}"));
+        }
+
+        endpoint_manager.get_health(&id).await.unwrap_or_default()
+    };
+
+    let (checks, up) = match clickhouse.query(uptime_sql(&id, window)).await {
+        Ok(block) => match block.rows().next() {
+            Some(row) => (
+                row.get::<u64, _>("checks").unwrap_or(0),
+                row.get::<u64, _>("up").unwrap_or(0),
+            ),
+            None => (0, 0),
+        },
+        Err(e) => {
+            error!("unable to query uptime of instance {id}: {e}");
+            return new_err_resp(500, "Unable to query instance health");
+        }
+    };
+
+    let block = match clickhouse
+        .query(transitions_sql(&id, window, MAX_TRANSITIONS))
+        .await
+    {
+        Ok(block) => block,
+        Err(e) => {
+            error!("unable to query health transitions of instance {id}: {e}");
+            return new_err_resp(500, "Unable to query instance health");
+        }
+    };
+
+    let mut transitions = Vec::with_capacity(block.row_count());
+    for row in block.rows() {
+        let status: String = row.get("status").unwrap_or_default();
+        let previous: String = row.get("previous_status").unwrap_or_default();
+        let error: String = row.get("error").unwrap_or_default();
+        let at: Result<DateTime<Tz>, _> = row.get("checked_at");
+
+        let (to, at) = match (status.parse::<HealthStatus>(), at) {
+            (Ok(to), Ok(at)) => (to, at),
+            _ => {
+                warn!("skipping malformed health record of instance {id}");
+                continue;
+            }
+        };
+
+        transitions.push(HealthTransition {
+            from: previous.parse().ok(),
+            to,
+            latency_ms: row.get("latency_ms").unwrap_or(0),
+            error: Some(error).filter(|e| !e.is_empty()),
+            at: at.with_timezone(&Utc),
+        });
+    }
+
+    new_response(InstanceHealth {
+        uuid: id,
+        current,
+        window,
+        checks,
+        uptime: if checks == 0 {
+            None
+        } else {
+            Some(up as f64 / checks as f64 * 100.0)
+        },
+        transitions,
+    })
+}
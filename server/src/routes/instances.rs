@@ -13,18 +13,25 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::config::Config;
+use crate::endpoints::capabilities::{Capabilities, NegotiationError};
 use crate::endpoints::endpoint::Endpoint;
 use crate::endpoints::endpoint_manager::EndpointManager;
-use crate::middleware::auth::AuthGuard;
+use crate::endpoints::health::HealthCheck;
+use crate::endpoints::tls::{EndpointTls, TlsSettings};
+use crate::middleware::auth::{authorize, AuthGuard, Principal};
 use crate::models::response::{
     empty_response, new_err_resp, new_err_resp_from_err, new_response, ApiError, ApiResponse, Empty,
 };
+use crate::models::token::Scope;
+use crate::prisma::{instance, user, PrismaClient};
 use rocket::http::Status;
 use rocket::serde::json::Json;
-use rocket::{post, State};
+use rocket::{delete, get, post, State};
 use rsa::pkcs8::{EncodePublicKey, LineEnding};
 use rsa::PaddingScheme;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -33,6 +40,14 @@ use uuid::Uuid;
 #[derive(Deserialize)]
 pub struct InstanceInitRequest {
     pub addr: String,
+
+    /// The ID of the user that owns this instance. Defaults to the user that the API
+    /// token belongs to.
+    pub owner: Option<String>,
+
+    /// How the server should connect to the instance. Defaults to `grpc_tls` from the
+    /// configuration.
+    pub tls: Option<EndpointTls>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -46,18 +61,202 @@ pub struct InstanceFinalizeRequest {
     pub api_token: String,
 }
 
+/// Whether an instance has only called `init`, or also `finalize`d with its service token.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationState {
+    Pending,
+    Finalized,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InstanceResponse {
+    pub uuid: String,
+    pub address: String,
+    pub state: RegistrationState,
+    pub owner: Option<String>,
+    pub health: Option<HealthCheck>,
+    pub capabilities: Option<Capabilities>,
+
+    /// The last few characters of the service token, so it can be told apart without
+    /// exposing it.
+    pub token: Option<String>,
+}
+
+impl InstanceResponse {
+    fn new(endpoint: Endpoint, health: Option<HealthCheck>) -> Self {
+        InstanceResponse {
+            uuid: endpoint.instance_name,
+            address: endpoint.addr.to_string(),
+            state: if endpoint.api_token.is_some() {
+                RegistrationState::Finalized
+            } else {
+                RegistrationState::Pending
+            },
+            owner: endpoint.owner_id.map(|id| id.to_string()),
+            health,
+            capabilities: endpoint.capabilities,
+            token: endpoint.api_token.as_deref().map(redact_token),
+        }
+    }
+}
+
+fn redact_token(token: &str) -> String {
+    let visible = token.chars().count().min(16) / 4;
+    let suffix: String = token
+        .chars()
+        .skip(token.chars().count() - visible)
+        .collect();
+
+    format!("****{suffix}")
+}
+
+/// Looks up an endpoint that the principal is allowed to see. Instances owned by someone
+/// else are reported as missing, so their UUIDs can't be probed.
+pub(crate) async fn find_endpoint(
+    manager: &mut EndpointManager,
+    principal: &Principal,
+    id: &str,
+) -> Option<Endpoint> {
+    match manager.get_endpoint(id.to_string()).await {
+        Ok(endpoint) if principal.can_access_owned(endpoint.owner_id) => Some(endpoint),
+        _ => None,
+    }
+}
+
+/// Returns which of the given instances the principal can write data for, looking every
+/// instance up only once. Administrators can write for any instance.
+pub(crate) async fn accessible_instances(
+    manager: &Mutex<EndpointManager>,
+    principal: &Principal,
+    ids: impl IntoIterator<Item = Uuid>,
+) -> HashSet<Uuid> {
+    let ids: HashSet<Uuid> = ids.into_iter().collect();
+    if principal.has_scope(Scope::Admin) {
+        return ids;
+    }
+
+    let mut endpoint_manager = manager.lock().await;
+    let mut accessible = HashSet::with_capacity(ids.len());
+    for id in ids {
+        if find_endpoint(&mut endpoint_manager, principal, &id.to_string())
+            .await
+            .is_some()
+        {
+            accessible.insert(id);
+        }
+    }
+
+    accessible
+}
+
+#[get("/")]
+pub async fn list_instances(
+    auth: Result<AuthGuard, ApiError>,
+    manager: &State<Arc<Mutex<EndpointManager>>>,
+) -> ApiResponse<Vec<InstanceResponse>> {
+    let principal = match authorize(auth, Scope::StatsRead) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    let mut endpoint_manager = manager.lock().await;
+    let endpoints = match endpoint_manager.get_endpoints().await {
+        Ok(endpoints) => endpoints,
+        Err(e) => {
+            error!("unable to list endpoints: {e}");
+            return new_err_resp(500, "Unable to list instances");
+        }
+    };
+
+    let mut instances = vec![];
+    for endpoint in endpoints
+        .into_iter()
+        .filter(|e| principal.can_access_owned(e.owner_id))
+    {
+        let health = endpoint_manager
+            .get_health(&endpoint.instance_name)
+            .await
+            .unwrap_or_default();
+
+        instances.push(InstanceResponse::new(endpoint, health));
+    }
+
+    instances.sort_by(|a, b| a.uuid.cmp(&b.uuid));
+    new_response(instances)
+}
+
+#[get("/<id>")]
+pub async fn get_instance(
+    auth: Result<AuthGuard, ApiError>,
+    id: String,
+    manager: &State<Arc<Mutex<EndpointManager>>>,
+) -> ApiResponse<InstanceResponse> {
+    let principal = match authorize(auth, Scope::StatsRead) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    if Uuid::parse_str(id.as_str()).is_err() {
+        return new_err_resp(400, "Bad Uuid");
+    }
+
+    let mut endpoint_manager = manager.lock().await;
+    match find_endpoint(&mut endpoint_manager, &principal, &id).await {
+        Some(endpoint) => {
+            let health = endpoint_manager.get_health(&id).await.unwrap_or_default();
+            new_response(InstanceResponse::new(endpoint, health))
+        }
+        None => new_err_resp(404, format!("Unknown instance {id}")),
+    }
+}
+
+#[delete("/<id>")]
+pub async fn delete_instance(
+    auth: Result<AuthGuard, ApiError>,
+    id: String,
+    manager: &State<Arc<Mutex<EndpointManager>>>,
+) -> ApiResponse<Empty> {
+    let principal = match authorize(auth, Scope::InstancesWrite) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    if Uuid::parse_str(id.as_str()).is_err() {
+        return new_err_resp(400, "Bad Uuid");
+    }
+
+    let mut endpoint_manager = manager.lock().await;
+    if find_endpoint(&mut endpoint_manager, &principal, &id)
+        .await
+        .is_none()
+    {
+        return new_err_resp(404, format!("Unknown instance {id}"));
+    }
+
+    match endpoint_manager.delete_endpoint(id.clone()).await {
+        Ok(_) => empty_response(Some(Status::NoContent)),
+        Err(e) => {
+            error!("unable to delete instance {id}: {e}");
+            new_err_resp(500, "Unable to delete instance")
+        }
+    }
+}
+
 #[post("/<id>/init", format = "json", data = "<body>")]
 pub async fn instance_init(
     auth: Result<AuthGuard, ApiError>,
     id: String,
     body: Json<InstanceInitRequest>,
     manager: &State<Arc<Mutex<EndpointManager>>>,
+    prisma: &State<Arc<PrismaClient>>,
 ) -> ApiResponse<InstanceInitResponse> {
     return match Uuid::parse_str(id.as_str()) {
         Ok(id) => {
-            if let Err(e) = auth {
-                return new_err_resp_from_err(e);
-            }
+            let principal = match authorize(auth, Scope::InstancesWrite) {
+                Ok(principal) => principal,
+                Err(e) => return new_err_resp_from_err(e),
+            };
             let mut endpoint_manager = manager.lock().await;
             let parsed = body.addr.parse::<SocketAddr>();
             if parsed.is_err() {
@@ -66,11 +265,64 @@ pub async fn instance_init(
                     "Invalid address specified, must be ip:port".into(),
                 );
             }
+            match TlsSettings::global() {
+                Ok(settings) => {
+                    if let Err(e) = settings.check(body.tls.as_ref()) {
+                        return new_err_resp(400, e);
+                    }
+                }
+                Err(e) => return new_err_resp(500, e.to_string()),
+            }
+            let owner_id = match &body.owner {
+                Some(owner) => match owner.parse::<i64>() {
+                    Ok(owner_id) => Some(owner_id),
+                    Err(_) => return new_err_resp(400, "Bad owner ID"),
+                },
+                None => principal.user_id(),
+            };
+            if owner_id != principal.user_id() && !principal.has_scope(Scope::Admin) {
+                return new_err_resp(403, "You can only register instances for yourself");
+            }
+            if let Some(owner_id) = owner_id {
+                match prisma
+                    .user()
+                    .find_unique(user::id::equals(owner_id))
+                    .exec()
+                    .await
+                {
+                    Ok(Some(_)) => {}
+                    Ok(None) => return new_err_resp(404, format!("Unknown user {owner_id}")),
+                    Err(e) => return new_err_resp(500, e.to_string()),
+                }
+            }
+            // don't let someone take over an instance that is owned by somebody else
+            match prisma
+                .instance()
+                .find_unique(instance::uuid::equals(id.to_string()))
+                .exec()
+                .await
+            {
+                Ok(Some(instance))
+                    if instance.owner_id.is_some() && instance.owner_id != owner_id =>
+                {
+                    return new_err_resp(403, "Instance is owned by another user");
+                }
+                Ok(_) => {}
+                Err(e) => return new_err_resp(500, e.to_string()),
+            }
+            // pending instances only live in redis until they are finalized, so their
+            // owner has to be checked there as well
             let existing = endpoint_manager.get_endpoint(id.to_string()).await;
             if let Ok(e) = existing {
-                endpoint_manager.drop_key(e.instance_name);
+                if e.owner_id.is_some() && e.owner_id != owner_id {
+                    return new_err_resp(403, "Instance is owned by another user");
+                }
+
+                endpoint_manager.drop_key(e.instance_name).await;
             }
-            let endpoint = Endpoint::new(id.to_string(), parsed.unwrap());
+            let mut endpoint = Endpoint::new(id.to_string(), parsed.unwrap());
+            endpoint.owner_id = owner_id;
+            endpoint.tls = body.tls.clone();
             return match endpoint_manager.add_endpoint(endpoint.clone()).await {
                 Ok(keys) => {
                     let pub_key = keys.public.to_public_key_pem(LineEnding::default());
@@ -101,45 +353,113 @@ pub async fn instance_finalize(
     id: String,
     body: Json<InstanceFinalizeRequest>,
     manager: &State<Arc<Mutex<EndpointManager>>>,
+    config: &State<Config>,
 ) -> ApiResponse<Empty> {
-    if let Err(e) = auth {
-        return new_err_resp_from_err(e);
+    let principal = match authorize(auth, Scope::InstancesWrite) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    // the manager is shared with every other route and background job, so it isn't held
+    // while the instance is negotiated with below
+    let (endpoint, keys) = {
+        let mut endpoint_manager = manager.lock().await;
+        let endpoint = match find_endpoint(&mut endpoint_manager, &principal, &id).await {
+            Some(endpoint) => endpoint,
+            None => return empty_response(Some(Status::NotFound)),
+        };
+
+        match endpoint_manager
+            .get_keys(endpoint.instance_name.clone())
+            .await
+        {
+            Ok(keys) => (endpoint, keys),
+            Err(_) => {
+                return new_err_resp(
+                    409,
+                    "No key pair exists for this instance, call init again!",
+                )
+            }
+        }
+    };
+
+    let dec = match base64::decode(body.api_token.clone()) {
+        Ok(dec) => dec,
+        Err(_) => return new_err_resp(400, "Failed to decode base64 encoded token!"),
+    };
+
+    if keys
+        .private
+        .decrypt(PaddingScheme::new_pkcs1v15_encrypt(), &dec[..])
+        .is_err()
+    {
+        return new_err_resp(
+            400,
+            "Failed to decrypt API token, check your signing method!",
+        );
     }
+
+    // negotiate before the token is stored, so instances that speak an
+    // incompatible protocol version are refused right away
+    let mut candidate = endpoint.clone();
+    candidate.api_token = Some(body.api_token.clone());
+    candidate.keys = Some(keys.clone());
+    let health_cfg = config.health.clone().unwrap_or_default();
+    let (health, negotiated) = candidate
+        .check_health(health_cfg.timeout(), health_cfg.degraded_latency())
+        .await;
+    let capabilities = match negotiated {
+        Ok(capabilities) => Some(capabilities),
+        Err(err) => {
+            if let Some(err) = err.downcast_ref::<NegotiationError>() {
+                return new_err_resp(400, err.to_string());
+            }
+            warn!(
+                "unable to negotiate with instance {}, retrying on the next health check: {err}",
+                endpoint.instance_name
+            );
+            None
+        }
+    };
+
+    // the instance could have been deleted or initialized again in the meantime, in
+    // which case the token was encrypted with a key pair that is gone
     let mut endpoint_manager = manager.lock().await;
-    return match endpoint_manager.get_endpoint(id.clone()).await {
-        Err(_) => empty_response(Some(Status::NotFound)),
-        Ok(mut e) => {
-            let keys = endpoint_manager.get_keys(e.clone().instance_name).unwrap();
-            match base64::decode(body.api_token.clone()) {
-                Err(_) => new_err_resp(400, "Failed to decode base64 encoded token!"),
-                Ok(dec) => {
-                    let decrypt_res = keys
-                        .private
-                        .decrypt(PaddingScheme::new_pkcs1v15_encrypt(), &dec[..]);
-                    if decrypt_res.is_err() {
-                        return new_err_resp(
-                            400,
-                            "Failed to decrypt API token, check your signing method!",
-                        );
-                    }
-                    match endpoint_manager
-                        .store_api_key(&mut e, body.api_token.clone())
-                        .await
-                    {
-                        Ok(v) => {
-                            if !v {
-                                new_err_resp::<Empty, &str>(500, "Failed to update redis entry!")
-                            } else {
-                                tokio::spawn(async move {
-                                    info!("{:?}", e.is_healthy().await);
-                                });
-                                empty_response(Some(Status::Accepted))
-                            }
-                        }
-                        Err(e) => new_err_resp(500, e.to_string()),
-                    }
+    let mut e = match find_endpoint(&mut endpoint_manager, &principal, &id).await {
+        Some(e) => e,
+        None => return empty_response(Some(Status::NotFound)),
+    };
+    match endpoint_manager.get_keys(e.instance_name.clone()).await {
+        Ok(current) if current.public == keys.public => {}
+        _ => {
+            return new_err_resp(
+                409,
+                "Instance was initialized again while it was finalized, call finalize again!",
+            )
+        }
+    }
+    if capabilities.is_some() {
+        e.capabilities = capabilities;
+    }
+
+    match endpoint_manager
+        .store_api_key(&mut e, body.api_token.clone())
+        .await
+    {
+        Ok(v) => {
+            if !v {
+                new_err_resp::<Empty, &str>(500, "Failed to update redis entry!")
+            } else {
+                info!("{:?}", health);
+                if let Err(err) = endpoint_manager
+                    .record_health(&e.instance_name, &health)
+                    .await
+                {
+                    warn!("unable to record health of {}: {err}", e.instance_name);
                 }
+                empty_response(Some(Status::Accepted))
             }
         }
-    };
+        Err(e) => new_err_resp(500, e.to_string()),
+    }
 }
@@ -0,0 +1,181 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use rocket::{get, http::ContentType, State};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::clickhouse::client::ClickHouse;
+use crate::collector::metrics::MetricKind;
+use crate::endpoints::endpoint_manager::EndpointManager;
+use crate::middleware::auth::{authorize, AuthGuard};
+use crate::models::response::{new_err_resp, new_err_resp_from_err, ApiError, ApiResponse, Empty};
+use crate::models::token::Scope;
+use crate::prometheus::exposition::Exposition;
+use crate::prometheus::instances::render;
+use crate::prometheus::registry::ServerMetrics;
+use crate::routes::instances::find_endpoint;
+use crate::{COMMIT_HASH, VERSION};
+
+/// Content type of the Prometheus text exposition format.
+pub fn exposition_content_type() -> ContentType {
+    ContentType::new("text", "plain").with_params([("version", "0.0.4"), ("charset", "utf-8")])
+}
+
+#[get("/")]
+pub async fn server_metrics(
+    auth: Result<AuthGuard, ApiError>,
+    manager: &State<Arc<Mutex<EndpointManager>>>,
+    clickhouse: &State<Arc<ClickHouse>>,
+) -> Result<(ContentType, String), ApiResponse<Empty>> {
+    authorize(auth, Scope::Admin).map_err(new_err_resp_from_err)?;
+
+    let mut exposition = Exposition::new();
+    ServerMetrics::global().render(&mut exposition);
+
+    if let Some(family) = exposition.family(
+        "analytics_server_info",
+        MetricKind::Gauge,
+        Some("Information about the analytics server, always 1."),
+    ) {
+        family.push(
+            vec![
+                ("version".into(), VERSION.into()),
+                ("commit".into(), COMMIT_HASH.into()),
+            ],
+            1.0,
+        );
+    }
+
+    let (pool_min, pool_max) = clickhouse.pool_size();
+    let clickhouse_metrics = [
+        (
+            "analytics_clickhouse_calls_total",
+            MetricKind::Counter,
+            "Calls that were made to ClickHouse.",
+            clickhouse.calls() as f64,
+        ),
+        (
+            "analytics_clickhouse_errors_total",
+            MetricKind::Counter,
+            "Calls to ClickHouse that failed.",
+            clickhouse.errors() as f64,
+        ),
+        (
+            "analytics_clickhouse_in_flight_calls",
+            MetricKind::Gauge,
+            "Calls to ClickHouse that are running or waiting on a connection.",
+            clickhouse.in_flight() as f64,
+        ),
+        (
+            "analytics_clickhouse_pool_min_connections",
+            MetricKind::Gauge,
+            "The minimum amount of connections in the ClickHouse pool.",
+            pool_min as f64,
+        ),
+        (
+            "analytics_clickhouse_pool_max_connections",
+            MetricKind::Gauge,
+            "The maximum amount of connections in the ClickHouse pool.",
+            pool_max as f64,
+        ),
+    ];
+
+    for (name, kind, help, value) in clickhouse_metrics {
+        if let Some(family) = exposition.family(name, kind, Some(help)) {
+            family.push(vec![], value);
+        }
+    }
+
+    match manager.lock().await.get_endpoints().await {
+        Ok(endpoints) => {
+            if let Some(family) = exposition.family(
+                "analytics_registered_endpoints",
+                MetricKind::Gauge,
+                Some("Instances that are registered with the server."),
+            ) {
+                family.push(vec![], endpoints.len() as f64);
+            }
+        }
+        Err(e) => warn!("unable to count endpoints for metrics: {e}"),
+    }
+
+    Ok((exposition_content_type(), exposition.render()))
+}
+
+#[get("/instances")]
+pub async fn instances_metrics(
+    auth: Result<AuthGuard, ApiError>,
+    manager: &State<Arc<Mutex<EndpointManager>>>,
+    clickhouse: &State<Arc<ClickHouse>>,
+) -> Result<(ContentType, String), ApiResponse<Empty>> {
+    let principal = authorize(auth, Scope::StatsRead).map_err(new_err_resp_from_err)?;
+    let ids = {
+        let mut endpoint_manager = manager.lock().await;
+        match endpoint_manager.get_endpoints().await {
+            Ok(endpoints) => endpoints
+                .into_iter()
+                .filter(|e| principal.can_access_owned(e.owner_id))
+                .map(|e| e.instance_name)
+                .collect::<Vec<_>>(),
+            Err(e) => {
+                error!("unable to list endpoints: {e}");
+                return Err(new_err_resp(500, "Unable to list instances"));
+            }
+        }
+    };
+
+    match render(clickhouse, &ids).await {
+        Ok(body) => Ok((exposition_content_type(), body)),
+        Err(e) => {
+            error!("unable to render metrics of instances: {e}");
+            Err(new_err_resp(500, "Unable to render instance metrics"))
+        }
+    }
+}
+
+#[get("/<id>/metrics")]
+pub async fn instance_metrics(
+    auth: Result<AuthGuard, ApiError>,
+    id: String,
+    manager: &State<Arc<Mutex<EndpointManager>>>,
+    clickhouse: &State<Arc<ClickHouse>>,
+) -> Result<(ContentType, String), ApiResponse<Empty>> {
+    let principal = authorize(auth, Scope::StatsRead).map_err(new_err_resp_from_err)?;
+    let id = match Uuid::parse_str(id.as_str()) {
+        Ok(id) => id.to_string(),
+        Err(_) => return Err(new_err_resp(400, "Bad Uuid")),
+    };
+
+    {
+        let mut endpoint_manager = manager.lock().await;
+        if find_endpoint(&mut endpoint_manager, &principal, &id)
+            .await
+            .is_none()
+        {
+            return Err(new_err_resp(404, format!("Unknown instance {id}")));
+        }
+    }
+
+    match render(clickhouse, &[id.clone()]).await {
+        Ok(body) => Ok((exposition_content_type(), body)),
+        Err(e) => {
+            error!("unable to render metrics of instance {id}: {e}");
+            Err(new_err_resp(500, "Unable to render instance metrics"))
+        }
+    }
+}
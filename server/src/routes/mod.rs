@@ -13,7 +13,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod alerts;
 pub mod api;
+pub mod dashboards;
+pub mod health;
 pub mod instances;
 pub mod main;
+pub mod metrics;
+pub mod otlp;
 pub mod stats;
+pub mod tokens;
+pub mod users;
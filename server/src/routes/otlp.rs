@@ -0,0 +1,119 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use opentelemetry_proto::tonic::collector::logs::v1::{
+    ExportLogsServiceRequest, ExportLogsServiceResponse,
+};
+use opentelemetry_proto::tonic::collector::metrics::v1::{
+    ExportMetricsServiceRequest, ExportMetricsServiceResponse,
+};
+use prost::Message;
+use rocket::data::ToByteUnit;
+use rocket::http::ContentType;
+use rocket::{post, Data, State};
+
+use crate::middleware::auth::{authorize, AuthGuard};
+use crate::models::response::{new_err_resp, new_err_resp_from_err, ApiError, ApiResponse, Empty};
+use crate::models::token::Scope;
+use crate::otlp::receiver::OtlpReceiver;
+
+/// Largest body that is accepted by the OTLP/HTTP receivers.
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// Content type of binary encoded OTLP/HTTP requests and responses.
+fn protobuf_content_type() -> ContentType {
+    ContentType::new("application", "x-protobuf")
+}
+
+/// Reads and decodes a binary encoded OTLP request. Only the binary encoding is supported,
+/// so JSON encoded requests are rejected with a `415`.
+async fn decode_body<M: Message + Default>(
+    content_type: Option<&ContentType>,
+    body: Data<'_>,
+) -> Result<M, ApiResponse<Empty>> {
+    if content_type != Some(&protobuf_content_type()) {
+        return Err(new_err_resp(
+            415,
+            "Only `application/x-protobuf` encoded requests are supported",
+        ));
+    }
+
+    let body = match body.open(MAX_BODY_SIZE.bytes()).into_bytes().await {
+        Ok(body) if body.is_complete() => body.into_inner(),
+        Ok(_) => {
+            return Err(new_err_resp(
+                413,
+                format!("Body can't be larger than {MAX_BODY_SIZE} bytes"),
+            ))
+        }
+        Err(e) => return Err(new_err_resp(400, format!("Unable to read body: {e}"))),
+    };
+
+    M::decode(body.as_slice()).map_err(|e| new_err_resp(400, format!("Unable to decode body: {e}")))
+}
+
+/// Receives OTLP metrics over HTTP, authenticated with an API token that has the
+/// `instances:write` scope.
+#[post("/v1/metrics", data = "<body>")]
+pub async fn export_metrics(
+    auth: Result<AuthGuard, ApiError>,
+    content_type: Option<&ContentType>,
+    body: Data<'_>,
+    receiver: &State<OtlpReceiver>,
+) -> Result<(ContentType, Vec<u8>), ApiResponse<Empty>> {
+    let principal = authorize(auth, Scope::InstancesWrite).map_err(new_err_resp_from_err)?;
+    let request = decode_body::<ExportMetricsServiceRequest>(content_type, body).await?;
+
+    receiver
+        .export_metrics(&principal, request)
+        .await
+        .map_err(|e| {
+            error!("unable to store OTLP metrics: {e}");
+            new_err_resp(503, "Unable to store metrics")
+        })?;
+
+    let response = ExportMetricsServiceResponse {
+        partial_success: None,
+    };
+
+    Ok((protobuf_content_type(), response.encode_to_vec()))
+}
+
+/// Receives OTLP logs over HTTP, authenticated with an API token that has the
+/// `instances:write` scope.
+#[post("/v1/logs", data = "<body>")]
+pub async fn export_logs(
+    auth: Result<AuthGuard, ApiError>,
+    content_type: Option<&ContentType>,
+    body: Data<'_>,
+    receiver: &State<OtlpReceiver>,
+) -> Result<(ContentType, Vec<u8>), ApiResponse<Empty>> {
+    let principal = authorize(auth, Scope::InstancesWrite).map_err(new_err_resp_from_err)?;
+    let request = decode_body::<ExportLogsServiceRequest>(content_type, body).await?;
+
+    receiver
+        .export_logs(&principal, request)
+        .await
+        .map_err(|e| {
+            error!("unable to store OTLP logs: {e}");
+            new_err_resp(503, "Unable to store logs")
+        })?;
+
+    let response = ExportLogsServiceResponse {
+        partial_success: None,
+    };
+
+    Ok((protobuf_content_type(), response.encode_to_vec()))
+}
@@ -13,7 +13,93 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::Arc;
 
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use rocket::{get, State};
+use uuid::Uuid;
 
+use crate::clickhouse::client::ClickHouse;
+use crate::middleware::auth::{authorize, AuthGuard};
+use crate::models::response::{
+    new_err_resp, new_err_resp_from_err, new_response, ApiError, ApiResponse,
+};
+use crate::models::stats::{StatsPoint, StatsQuery, StatsSeries};
+use crate::models::token::Scope;
+use crate::prisma::PrismaClient;
+use crate::routes::dashboards::find_instance;
 
+#[get("/<id>/stats?<from>&<to>&<step>&<aggregate>&<field>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn instance_stats(
+    auth: Result<AuthGuard, ApiError>,
+    id: String,
+    from: Option<String>,
+    to: Option<String>,
+    step: Option<String>,
+    aggregate: Option<String>,
+    field: Option<String>,
+    clickhouse: &State<Arc<ClickHouse>>,
+    prisma: &State<Arc<PrismaClient>>,
+) -> ApiResponse<StatsSeries> {
+    let principal = match authorize(auth, Scope::StatsRead) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
 
+    let id = match Uuid::parse_str(id.as_str()) {
+        Ok(id) => id,
+        Err(_) => return new_err_resp(400, "Bad Uuid"),
+    };
+
+    if let Err(e) = find_instance(prisma, &principal, &id.to_string()).await {
+        return e;
+    }
+
+    let query = match StatsQuery::parse(
+        id.to_string(),
+        from.as_deref(),
+        to.as_deref(),
+        step.as_deref(),
+        aggregate.as_deref(),
+        field.as_deref(),
+    ) {
+        Ok(query) => query,
+        Err(e) => return new_err_resp(400, e),
+    };
+
+    let block = match clickhouse.query(query.to_sql()).await {
+        Ok(block) => block,
+        Err(e) => {
+            error!("unable to query stats for instance {id}: {e}");
+            return new_err_resp(500, "Unable to query stats");
+        }
+    };
+
+    let mut points = Vec::with_capacity(block.row_count());
+    for row in block.rows() {
+        let bucket: Result<DateTime<Tz>, _> = row.get("bucket");
+        let value: Result<f64, _> = row.get("value");
+        match bucket {
+            Ok(bucket) => points.push(StatsPoint {
+                timestamp: bucket.with_timezone(&Utc),
+                value: value.ok().filter(|v| v.is_finite()),
+            }),
+            Err(e) => {
+                error!("unable to read stats bucket: {e}");
+                return new_err_resp(500, "Unable to query stats");
+            }
+        }
+    }
+
+    new_response(StatsSeries {
+        instance: query.instance,
+        field: query.field.map(|path| path.join(".")),
+        aggregate: query.aggregate,
+        from: query.from,
+        to: query.to,
+        step: query.step,
+        points,
+    })
+}
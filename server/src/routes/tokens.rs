@@ -0,0 +1,210 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, FixedOffset};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{delete, get, post, State};
+use serde::{Deserialize, Serialize};
+
+use crate::middleware::auth::{AuthGuard, Principal};
+use crate::models::response::{
+    empty_response, new_err_resp, new_err_resp_from_err, new_response, new_response_with_status,
+    ApiError, ApiResponse, Empty,
+};
+use crate::models::token::{generate_token, hash_token, parse_scopes, Scope};
+use crate::prisma::{api_token, user, PrismaClient};
+use crate::snowflake;
+
+#[derive(Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TokenResponse {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime<FixedOffset>,
+    pub last_used_at: Option<DateTime<FixedOffset>>,
+
+    /// The token itself, which is only returned once when it is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl From<api_token::Data> for TokenResponse {
+    fn from(data: api_token::Data) -> Self {
+        TokenResponse {
+            id: data.id.to_string(),
+            name: data.name,
+            scopes: parse_scopes(&data.scopes),
+            created_at: data.created_at,
+            last_used_at: data.last_used_at,
+            token: None,
+        }
+    }
+}
+
+/// Resolves the principal and the user ID that a token route is scoped to, which
+/// is only allowed for the user themselves and administrators.
+fn resolve_user<T: Serialize + std::fmt::Debug>(
+    auth: Result<AuthGuard, ApiError>,
+    id: &str,
+) -> Result<(Principal, i64), ApiResponse<T>> {
+    let principal = auth.map_err(new_err_resp_from_err)?.principal;
+    let id = id
+        .parse::<i64>()
+        .map_err(|_| new_err_resp(400, "Bad user ID"))?;
+
+    if !principal.can_access_user(id) {
+        return Err(new_err_resp(403, "You can only manage your own API tokens"));
+    }
+
+    Ok((principal, id))
+}
+
+#[post("/<id>/tokens", format = "json", data = "<body>")]
+pub async fn create_token(
+    auth: Result<AuthGuard, ApiError>,
+    id: String,
+    body: Json<CreateTokenRequest>,
+    prisma: &State<Arc<PrismaClient>>,
+) -> ApiResponse<TokenResponse> {
+    let (principal, id) = match resolve_user(auth, &id) {
+        Ok(resolved) => resolved,
+        Err(resp) => return resp,
+    };
+
+    let body = body.into_inner();
+    if body.name.trim().is_empty() || body.name.len() > 64 {
+        return new_err_resp(400, "`name` must be between 1 and 64 characters");
+    }
+
+    if body.scopes.is_empty() {
+        return new_err_resp(400, "`scopes` must contain at least one scope");
+    }
+
+    // tokens can't be used to hand out more than what they were granted
+    if let Some(scope) = body.scopes.iter().find(|s| !principal.has_scope(**s)) {
+        return new_err_resp(403, format!("Can't grant the `{scope}` scope"));
+    }
+
+    match prisma.user().find_unique(user::id::equals(id)).exec().await {
+        Ok(Some(_)) => {}
+        Ok(None) => return new_err_resp(404, format!("Unknown user {id}")),
+        Err(e) => {
+            error!("unable to find user {id}: {e}");
+            return new_err_resp(500, "Unable to find user");
+        }
+    }
+
+    let token = generate_token();
+    let mut scopes = body
+        .scopes
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+
+    scopes.sort();
+    scopes.dedup();
+    match prisma
+        .api_token()
+        .create(
+            body.name,
+            hash_token(&token),
+            user::id::equals(id),
+            snowflake::generate(),
+            vec![api_token::scopes::set(scopes)],
+        )
+        .exec()
+        .await
+    {
+        Ok(data) => {
+            let mut resp = TokenResponse::from(data);
+            resp.token = Some(token);
+
+            new_response_with_status(Status::Created.code, resp)
+        }
+        Err(e) => {
+            error!("unable to create API token for user {id}: {e}");
+            new_err_resp(500, "Unable to create API token")
+        }
+    }
+}
+
+#[get("/<id>/tokens")]
+pub async fn list_tokens(
+    auth: Result<AuthGuard, ApiError>,
+    id: String,
+    prisma: &State<Arc<PrismaClient>>,
+) -> ApiResponse<Vec<TokenResponse>> {
+    let (_, id) = match resolve_user(auth, &id) {
+        Ok(resolved) => resolved,
+        Err(resp) => return resp,
+    };
+
+    match prisma
+        .api_token()
+        .find_many(vec![api_token::user_id::equals(id)])
+        .exec()
+        .await
+    {
+        Ok(tokens) => new_response(tokens.into_iter().map(TokenResponse::from).collect()),
+        Err(e) => {
+            error!("unable to list API tokens for user {id}: {e}");
+            new_err_resp(500, "Unable to list API tokens")
+        }
+    }
+}
+
+#[delete("/<id>/tokens/<token_id>")]
+pub async fn revoke_token(
+    auth: Result<AuthGuard, ApiError>,
+    id: String,
+    token_id: String,
+    prisma: &State<Arc<PrismaClient>>,
+) -> ApiResponse<Empty> {
+    let (_, id) = match resolve_user(auth, &id) {
+        Ok(resolved) => resolved,
+        Err(resp) => return resp,
+    };
+
+    let token_id = match token_id.parse::<i64>() {
+        Ok(token_id) => token_id,
+        Err(_) => return new_err_resp(400, "Bad token ID"),
+    };
+
+    match prisma
+        .api_token()
+        .delete_many(vec![
+            api_token::id::equals(token_id),
+            api_token::user_id::equals(id),
+        ])
+        .exec()
+        .await
+    {
+        Ok(0) => new_err_resp(404, format!("Unknown API token {token_id}")),
+        Ok(_) => empty_response(Some(Status::NoContent)),
+        Err(e) => {
+            error!("unable to revoke API token {token_id}: {e}");
+            new_err_resp(500, "Unable to revoke API token")
+        }
+    }
+}
@@ -0,0 +1,201 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{get, post, State};
+use serde::{Deserialize, Serialize};
+
+use crate::middleware::auth::{authorize, AuthGuard};
+use crate::models::response::{
+    new_err_resp, new_err_resp_from_err, new_response, new_response_with_status, ApiError,
+    ApiResponse,
+};
+use crate::models::token::Scope;
+use crate::prisma::{instance, user, PrismaClient};
+use crate::snowflake;
+
+#[derive(Deserialize)]
+pub struct CreateUserRequest {
+    pub display_name: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UserResponse {
+    pub id: String,
+    pub display_name: String,
+}
+
+impl From<user::Data> for UserResponse {
+    fn from(data: user::Data) -> Self {
+        UserResponse {
+            id: data.id.to_string(),
+            display_name: data.display_name,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct OwnedInstanceResponse {
+    pub uuid: String,
+    pub display_name: String,
+    pub grpc_endpoint: String,
+    pub owner: Option<String>,
+}
+
+impl From<instance::Data> for OwnedInstanceResponse {
+    fn from(data: instance::Data) -> Self {
+        OwnedInstanceResponse {
+            uuid: data.uuid,
+            display_name: data.display_name,
+            grpc_endpoint: data.grpc_endpoint,
+            owner: data.owner_id.map(|id| id.to_string()),
+        }
+    }
+}
+
+fn parse_user_id<T: Serialize + std::fmt::Debug>(id: &str) -> Result<i64, ApiResponse<T>> {
+    id.parse::<i64>()
+        .map_err(|_| new_err_resp(400, "Bad user ID"))
+}
+
+#[post("/", format = "json", data = "<body>")]
+pub async fn create_user(
+    auth: Result<AuthGuard, ApiError>,
+    body: Json<CreateUserRequest>,
+    prisma: &State<Arc<PrismaClient>>,
+) -> ApiResponse<UserResponse> {
+    if let Err(e) = authorize(auth, Scope::Admin) {
+        return new_err_resp_from_err(e);
+    }
+
+    let display_name = body.into_inner().display_name;
+    if display_name.trim().is_empty() || display_name.len() > 64 {
+        return new_err_resp(400, "`display_name` must be between 1 and 64 characters");
+    }
+
+    match prisma
+        .user()
+        .create(display_name, snowflake::generate(), vec![])
+        .exec()
+        .await
+    {
+        Ok(user) => new_response_with_status(Status::Created.code, user.into()),
+        Err(e) => {
+            error!("unable to create user: {e}");
+            new_err_resp(500, "Unable to create user")
+        }
+    }
+}
+
+#[get("/")]
+pub async fn list_users(
+    auth: Result<AuthGuard, ApiError>,
+    prisma: &State<Arc<PrismaClient>>,
+) -> ApiResponse<Vec<UserResponse>> {
+    if let Err(e) = authorize(auth, Scope::Admin) {
+        return new_err_resp_from_err(e);
+    }
+
+    match prisma.user().find_many(vec![]).exec().await {
+        Ok(users) => new_response(users.into_iter().map(UserResponse::from).collect()),
+        Err(e) => {
+            error!("unable to list users: {e}");
+            new_err_resp(500, "Unable to list users")
+        }
+    }
+}
+
+#[get("/<id>")]
+pub async fn get_user(
+    auth: Result<AuthGuard, ApiError>,
+    id: String,
+    prisma: &State<Arc<PrismaClient>>,
+) -> ApiResponse<UserResponse> {
+    let principal = match auth {
+        Ok(guard) => guard.principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    let id = match parse_user_id(&id) {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+
+    if !principal.can_access_user(id) {
+        return new_err_resp(403, "You can only access your own user");
+    }
+
+    match prisma.user().find_unique(user::id::equals(id)).exec().await {
+        Ok(Some(user)) => new_response(user.into()),
+        Ok(None) => new_err_resp(404, format!("Unknown user {id}")),
+        Err(e) => {
+            error!("unable to find user {id}: {e}");
+            new_err_resp(500, "Unable to find user")
+        }
+    }
+}
+
+/// Lists the instances that are owned by a single user, so teams that share an
+/// analytics server only see their own instances.
+#[get("/<id>/instances")]
+pub async fn list_user_instances(
+    auth: Result<AuthGuard, ApiError>,
+    id: String,
+    prisma: &State<Arc<PrismaClient>>,
+) -> ApiResponse<Vec<OwnedInstanceResponse>> {
+    let principal = match authorize(auth, Scope::StatsRead) {
+        Ok(principal) => principal,
+        Err(e) => return new_err_resp_from_err(e),
+    };
+
+    let id = match parse_user_id(&id) {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+
+    if !principal.can_access_user(id) {
+        return new_err_resp(403, "You can only access your own user");
+    }
+
+    match prisma.user().find_unique(user::id::equals(id)).exec().await {
+        Ok(Some(_)) => {}
+        Ok(None) => return new_err_resp(404, format!("Unknown user {id}")),
+        Err(e) => {
+            error!("unable to find user {id}: {e}");
+            return new_err_resp(500, "Unable to find user");
+        }
+    }
+
+    match prisma
+        .instance()
+        .find_many(vec![instance::owner_id::equals(Some(id))])
+        .exec()
+        .await
+    {
+        Ok(instances) => new_response(
+            instances
+                .into_iter()
+                .map(OwnedInstanceResponse::from)
+                .collect(),
+        ),
+        Err(e) => {
+            error!("unable to list instances for user {id}: {e}");
+            new_err_resp(500, "Unable to list instances")
+        }
+    }
+}
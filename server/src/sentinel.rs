@@ -14,6 +14,7 @@
 // limitations under the License.
 
 use crate::config::{Config, RedisConfig};
+use crate::prometheus::registry::ServerMetrics;
 use crate::to_redis_err;
 use async_recursion::async_recursion;
 use redis::{Client, FromRedisValue, RedisResult};
@@ -63,10 +64,12 @@ impl SentinelManager {
                     info!("Currently we have a bad connection, reconnecting...");
                     let client = self.find_healthy_sentinel();
                     if client.is_err() {
+                        ServerMetrics::global().record_failover(false);
                         return Err(client.unwrap_err());
                     }
                     let master_addr = self.get_master_addr(client.unwrap()).await;
                     if master_addr.is_none() {
+                        ServerMetrics::global().record_failover(false);
                         return Err(to_redis_err!("No new master found!"));
                     }
                     let client = Client::open(self.format_url(
@@ -76,6 +79,7 @@ impl SentinelManager {
                     ))
                     .unwrap();
                     self.master.replace(client.clone());
+                    ServerMetrics::global().record_failover(true);
                     Ok(client)
                 }
                 _ => Ok(client.clone()),
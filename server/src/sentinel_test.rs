@@ -18,6 +18,7 @@ mod tests {
     use crate::config::Config;
     use crate::endpoints::endpoint::Endpoint;
     use crate::endpoints::endpoint_manager::EndpointManager;
+    use crate::prisma::new_client;
     use crate::sentinel::SentinelManager;
     use crate::setup_utils::setup_logging;
     use dotenv::var;
@@ -36,7 +37,9 @@ mod tests {
         block_on(async move {
             let sentinel_manager = Arc::new(Mutex::new(SentinelManager::new(config.clone())));
             sentinel_manager.lock().await.setup().await;
-            let endpoint_manager = Arc::new(Mutex::new(EndpointManager::new(sentinel_manager)));
+            let prisma = Arc::new(new_client().await.expect("Failed to connect to postgres"));
+            let endpoint_manager =
+                Arc::new(Mutex::new(EndpointManager::new(sentinel_manager, prisma)));
             let mut em = endpoint_manager.lock().await;
             info!("Test endpoint creation...");
             info!(
@@ -49,7 +52,7 @@ mod tests {
             );
             info!("Test get endpoints...");
             info!("{:?}", em.get_endpoints().await);
-            info!("Keys: {:?}", em.get_keys("waff"));
+            info!("Keys: {:?}", em.get_keys("waff").await);
             info!("Test delete endpoint...");
             info!("{:?}", em.delete_endpoint("waff".into()).await);
         });
@@ -13,24 +13,36 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use rocket::{catchers, routes, Error, Ignite, Rocket};
 use tokio::sync::Mutex;
 
 use crate::{
+    alerts::{evaluator::AlertEvaluator, notifier::Notifiers},
     catchers::*,
-    clickhouse::client::ClickHouse,
+    clickhouse::{client::ClickHouse, migrations::Migrator},
+    collector::scheduler::StatsCollector,
     config::Config,
+    events::buffer::EventBuffer,
+    grpc::{self, ingest::IngestService, otlp::OtlpService},
+    otlp::receiver::OtlpReceiver,
     prisma::{new_client, PrismaClient},
     routes::*,
     setup_utils,
+    sinks::sink::Sinks,
+    snowflake::MAX_WORKER,
 };
 
-use crate::endpoints::endpoint_manager::EndpointManager;
+use crate::endpoints::{
+    endpoint_manager::EndpointManager, health::HealthChecker, reconciler::Reconciler,
+    service_token::ServiceTokens, tls::TlsSettings,
+};
+use crate::middleware::metrics::RequestMetrics;
 use crate::sentinel::SentinelManager;
 
 #[derive(Debug, Clone)]
@@ -43,7 +55,20 @@ pub struct Server {
 impl Server {
     pub async fn new() -> Result<Server> {
         let config = Config::get().unwrap();
-        let clickhouse = ClickHouse::new(config.clickhouse.clone().unwrap_or_default())?;
+        let worker_id = config
+            .server
+            .as_ref()
+            .and_then(|server| server.worker_id)
+            .unwrap_or(0);
+
+        // snowflakes only have room for 10 bits of the worker ID, so a bigger one would
+        // silently collide with another server's
+        if worker_id > MAX_WORKER {
+            bail!("`server.worker_id` must be between 0 and {MAX_WORKER}, but was {worker_id}");
+        }
+
+        let clickhouse_cfg = config.clickhouse.clone().unwrap_or_default();
+        let clickhouse = ClickHouse::new(clickhouse_cfg.clone())?;
 
         info!("connecting to postgres!");
         let prisma = new_client().await?;
@@ -51,6 +76,11 @@ impl Server {
         info!("connected to postgres, now connecting to clickhouse!");
         clickhouse.ping().await?;
 
+        info!("connected to clickhouse, now running migrations!");
+        Migrator::new(clickhouse.clone(), clickhouse_cfg.database())
+            .run(clickhouse_cfg.dry_run_migrations.unwrap_or(false))
+            .await?;
+
         Ok(Server {
             clickhouse: Arc::new(clickhouse.clone()),
             prisma: Arc::new(prisma),
@@ -63,6 +93,8 @@ impl Server {
         let clickhouse = self.clickhouse.clone();
         clickhouse.ping().await.expect("Clickhouse is not ready!");
 
+        TlsSettings::global().expect("Unable to load the gRPC TLS configuration!");
+
         info!("clickhouse seems stable! now launching server...");
         let config = self.config.clone();
         let server_cfg = config.server.unwrap_or_default();
@@ -74,7 +106,80 @@ impl Server {
 
         let sentinel_manager = Arc::new(Mutex::new(SentinelManager::new(self.config.clone())));
         sentinel_manager.lock().await.setup().await;
-        let endpoint_manager = Arc::new(Mutex::new(EndpointManager::new(sentinel_manager.clone())));
+        let endpoint_manager = Arc::new(Mutex::new(EndpointManager::new(
+            sentinel_manager.clone(),
+            self.prisma.clone(),
+        )));
+
+        let sinks = Sinks::from_config(
+            &config.sinks.clone().unwrap_or_default(),
+            self.clickhouse.clone(),
+            sentinel_manager.clone(),
+        )
+        .expect("Unable to create the configured sinks!");
+
+        info!(
+            "writing ingested data to sinks: {}",
+            sinks.names().join(", ")
+        );
+
+        let instances_cfg = config.instances.clone().unwrap_or_default();
+        Reconciler::new(
+            endpoint_manager.clone(),
+            Duration::from_secs(instances_cfg.reconcile_interval.unwrap_or(60).max(1)),
+        )
+        .spawn();
+
+        let collector_cfg = config.collector.clone().unwrap_or_default();
+        if collector_cfg.enabled.unwrap_or(true) {
+            info!("starting stats collector!");
+            StatsCollector::new(sinks.clone(), endpoint_manager.clone(), collector_cfg).spawn();
+        }
+
+        let health_cfg = config.health.clone().unwrap_or_default();
+        if health_cfg.enabled.unwrap_or(true) {
+            info!("starting health checker!");
+            HealthChecker::new(
+                self.clickhouse.clone(),
+                endpoint_manager.clone(),
+                health_cfg,
+            )
+            .spawn();
+        }
+
+        let alerts_cfg = config.alerts.clone().unwrap_or_default();
+        let notifiers = Notifiers::from_config(&alerts_cfg)
+            .expect("Unable to create the configured alert notifiers!");
+
+        if alerts_cfg.enabled.unwrap_or(true) {
+            info!("starting alert evaluator!");
+            AlertEvaluator::new(
+                self.clickhouse.clone(),
+                self.prisma.clone(),
+                notifiers.clone(),
+                alerts_cfg,
+            )
+            .spawn();
+        }
+
+        let service_tokens = ServiceTokens::new();
+        let otlp_receiver = OtlpReceiver::new(sinks.clone(), endpoint_manager.clone());
+        grpc::serve(
+            SocketAddr::new(addr, server_cfg.grpc_port.unwrap_or(9293)),
+            IngestService::new(
+                sinks.clone(),
+                endpoint_manager.clone(),
+                service_tokens.clone(),
+            ),
+            OtlpService::new(otlp_receiver.clone(), self.prisma.clone()),
+        );
+
+        let event_buffer = Arc::new(EventBuffer::new(
+            sinks.clone(),
+            config.events.clone().unwrap_or_default(),
+        ));
+
+        event_buffer.clone().spawn_flusher();
 
         // setup panic handler
         info!("installing panic hook");
@@ -92,10 +197,67 @@ impl Server {
             .manage(self.config.clone())
             .manage(sentinel_manager)
             .manage(endpoint_manager)
+            .manage(event_buffer)
+            .manage(service_tokens)
+            .manage(otlp_receiver)
+            .manage(sinks)
+            .manage(notifiers)
+            .attach(RequestMetrics)
             .mount("/", routes![main::index, main::heartbeat, main::info])
+            .mount(
+                "/api",
+                routes![
+                    api::events::ingest_events,
+                    api::logs::ingest_logs,
+                    api::logs::search_logs,
+                    api::remote_write::remote_write
+                ],
+            )
+            .mount("/otlp", routes![otlp::export_metrics, otlp::export_logs])
+            .mount(
+                "/metrics",
+                routes![metrics::server_metrics, metrics::instances_metrics],
+            )
+            .mount(
+                "/api/instances",
+                routes![
+                    dashboards::list_dashboards,
+                    dashboards::create_dashboard,
+                    dashboards::get_dashboard,
+                    dashboards::export_dashboard,
+                    dashboards::update_dashboard,
+                    dashboards::delete_dashboard,
+                    alerts::list_alert_rules,
+                    alerts::create_alert_rule,
+                    alerts::get_alert_rule,
+                    alerts::update_alert_rule,
+                    alerts::delete_alert_rule
+                ],
+            )
             .mount(
                 "/instances",
-                routes![instances::instance_init, instances::instance_finalize],
+                routes![
+                    instances::list_instances,
+                    instances::get_instance,
+                    instances::delete_instance,
+                    instances::instance_init,
+                    instances::instance_finalize,
+                    stats::instance_stats,
+                    health::instance_health,
+                    metrics::instance_metrics
+                ],
+            )
+            .mount(
+                "/users",
+                routes![
+                    users::create_user,
+                    users::list_users,
+                    users::get_user,
+                    users::list_user_instances,
+                    tokens::create_token,
+                    tokens::list_tokens,
+                    tokens::revoke_token
+                ],
             )
             .register("/", catchers![malformed_entity])
             .launch()
@@ -0,0 +1,75 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::clickhouse::client::ClickHouse;
+use crate::collector::snapshot::store;
+use crate::events::buffer::{self, EVENTS_TABLE};
+use crate::logs::store as logs;
+use crate::otlp::logs::{self as otlp_logs, OTLP_LOGS_TABLE};
+use crate::otlp::metrics::{self as otlp_metrics, OTLP_METRICS_TABLE};
+use crate::prometheus::remote_write::{self, REMOTE_WRITE_TABLE};
+use crate::sinks::sink::{Batch, Sink};
+
+/// Inserts every batch into its ClickHouse table. This is the sink that the stats, logs and
+/// metrics APIs read from, so data that is only written to other sinks can't be queried.
+#[derive(Debug, Clone)]
+pub struct ClickHouseSink {
+    clickhouse: Arc<ClickHouse>,
+}
+
+impl ClickHouseSink {
+    pub fn new(clickhouse: Arc<ClickHouse>) -> ClickHouseSink {
+        ClickHouseSink { clickhouse }
+    }
+}
+
+#[async_trait]
+impl Sink for ClickHouseSink {
+    fn name(&self) -> &'static str {
+        "clickhouse"
+    }
+
+    async fn write(&self, batch: &Batch<'_>) -> Result<()> {
+        match *batch {
+            Batch::Stats(snapshots) => store(&self.clickhouse, snapshots).await,
+            Batch::Events(events) => {
+                self.clickhouse
+                    .insert(EVENTS_TABLE, buffer::to_block(events))
+                    .await
+            }
+            Batch::Logs(lines) => logs::store(&self.clickhouse, lines).await,
+            Batch::RemoteWrite(samples) => {
+                self.clickhouse
+                    .insert(REMOTE_WRITE_TABLE, remote_write::to_block(samples))
+                    .await
+            }
+            Batch::OtlpMetrics(rows) => {
+                self.clickhouse
+                    .insert(OTLP_METRICS_TABLE, otlp_metrics::to_block(rows))
+                    .await
+            }
+            Batch::OtlpLogs(rows) => {
+                self.clickhouse
+                    .insert(OTLP_LOGS_TABLE, otlp_logs::to_block(rows))
+                    .await
+            }
+        }
+    }
+}
@@ -0,0 +1,174 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::config::FileSinkConfig;
+use crate::sinks::sink::{Batch, Sink};
+
+/// Appends every record as a line of JSON to a file per kind of data, like `events.ndjson`.
+/// Once a file would grow past `sinks.file.max_size` it is rotated to `events.1.ndjson`,
+/// pushing the older files back and deleting the ones past `sinks.file.max_files`.
+#[derive(Debug)]
+pub struct FileSink {
+    directory: PathBuf,
+    max_size: u64,
+    max_files: usize,
+
+    /// The size of every file that was written to, so they don't have to be looked up on
+    /// every write. This also makes sure that only one batch is written at a time.
+    sizes: Mutex<HashMap<&'static str, u64>>,
+}
+
+impl FileSink {
+    pub fn new(config: &FileSinkConfig) -> FileSink {
+        let defaults = FileSinkConfig::default();
+
+        FileSink {
+            directory: PathBuf::from(config.directory.clone().or(defaults.directory).unwrap()),
+            max_size: config.max_size.or(defaults.max_size).unwrap().max(1),
+            max_files: config.max_files.or(defaults.max_files).unwrap(),
+            sizes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the path of a file, where `0` is the one that is currently written to.
+    fn path(&self, kind: &str, index: usize) -> PathBuf {
+        if index == 0 {
+            self.directory.join(format!("{kind}.ndjson"))
+        } else {
+            self.directory.join(format!("{kind}.{index}.ndjson"))
+        }
+    }
+
+    async fn rotate(&self, kind: &str) -> Result<()> {
+        let oldest = self.path(kind, self.max_files);
+        if exists(&oldest).await {
+            fs::remove_file(oldest).await?;
+        }
+
+        for index in (0..self.max_files).rev() {
+            let path = self.path(kind, index);
+            if exists(&path).await {
+                fs::rename(path, self.path(kind, index + 1)).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn exists(path: &Path) -> bool {
+    fs::metadata(path).await.is_ok()
+}
+
+#[async_trait]
+impl Sink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn write(&self, batch: &Batch<'_>) -> Result<()> {
+        let mut body = batch.documents()?.join("\n");
+        body.push('\n');
+
+        let kind = batch.kind();
+        let mut sizes = self.sizes.lock().await;
+        let size = match sizes.get(kind) {
+            Some(size) => *size,
+            None => {
+                fs::create_dir_all(&self.directory).await?;
+                fs::metadata(self.path(kind, 0))
+                    .await
+                    .map(|m| m.len())
+                    .unwrap_or(0)
+            }
+        };
+
+        // batches that are larger than a whole file still go into a file of their own
+        let size = if size > 0 && size + body.len() as u64 > self.max_size {
+            self.rotate(kind).await?;
+            0
+        } else {
+            size
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(kind, 0))
+            .await?;
+
+        file.write_all(body.as_bytes()).await?;
+        file.flush().await?;
+
+        sizes.insert(kind, size + body.len() as u64);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::event::Event;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn rotates_files_past_the_max_size() {
+        let directory =
+            std::env::temp_dir().join(format!("analytics-file-sink-{}", Uuid::new_v4()));
+        let sink = FileSink::new(&FileSinkConfig {
+            directory: Some(directory.to_string_lossy().to_string()),
+            max_size: Some(200),
+            max_files: Some(2),
+        });
+
+        let events = vec![Event {
+            name: "user.login".into(),
+            timestamp: Utc::now(),
+            instance: Uuid::nil(),
+            properties: Default::default(),
+        }];
+
+        for _ in 0..5 {
+            sink.write(&Batch::Events(&events)).await.unwrap();
+        }
+
+        let mut files = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+
+        files.sort();
+        assert_eq!(
+            files,
+            vec!["events.1.ndjson", "events.2.ndjson", "events.ndjson"]
+        );
+
+        let current = std::fs::read_to_string(directory.join("events.ndjson")).unwrap();
+        assert_eq!(current.lines().count(), 1);
+        assert!(current.contains(r#""name":"user.login""#));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,348 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::future::join_all;
+use serde::Serialize;
+use tokio::sync::{Mutex, Semaphore};
+
+use crate::clickhouse::client::ClickHouse;
+use crate::collector::snapshot::{StatsSnapshot, STATS_TABLE};
+use crate::config::{SinkKind, SinksConfig};
+use crate::events::buffer::EVENTS_TABLE;
+use crate::logs::store::LOGS_TABLE;
+use crate::models::event::Event;
+use crate::models::log::LogLine;
+use crate::otlp::logs::{OtlpLogRow, OTLP_LOGS_TABLE};
+use crate::otlp::metrics::{OtlpMetricRow, OTLP_METRICS_TABLE};
+use crate::prometheus::registry::ServerMetrics;
+use crate::prometheus::remote_write::{RemoteSample, REMOTE_WRITE_TABLE};
+use crate::sentinel::SentinelManager;
+use crate::sinks::clickhouse::ClickHouseSink;
+use crate::sinks::elasticsearch::ElasticsearchSink;
+use crate::sinks::file::FileSink;
+use crate::sinks::webhook::WebhookSink;
+
+/// A batch of ingested data of a single kind, which is written to every sink at once.
+#[derive(Debug, Clone, Copy)]
+pub enum Batch<'a> {
+    Stats(&'a [StatsSnapshot]),
+    Events(&'a [Event]),
+    Logs(&'a [LogLine]),
+    RemoteWrite(&'a [RemoteSample]),
+    OtlpMetrics(&'a [OtlpMetricRow]),
+    OtlpLogs(&'a [OtlpLogRow]),
+}
+
+impl Batch<'_> {
+    /// Returns the kind of data in this batch, which is the name of its ClickHouse table.
+    /// Other sinks use it to keep every kind apart, like as an index or file name.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Batch::Stats(_) => STATS_TABLE,
+            Batch::Events(_) => EVENTS_TABLE,
+            Batch::Logs(_) => LOGS_TABLE,
+            Batch::RemoteWrite(_) => REMOTE_WRITE_TABLE,
+            Batch::OtlpMetrics(_) => OTLP_METRICS_TABLE,
+            Batch::OtlpLogs(_) => OTLP_LOGS_TABLE,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Batch::Stats(records) => records.len(),
+            Batch::Events(records) => records.len(),
+            Batch::Logs(records) => records.len(),
+            Batch::RemoteWrite(records) => records.len(),
+            Batch::OtlpMetrics(records) => records.len(),
+            Batch::OtlpLogs(records) => records.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Serializes every record in this batch as a single line of JSON, for the sinks that
+    /// store documents instead of rows.
+    pub fn documents(&self) -> Result<Vec<String>> {
+        match self {
+            Batch::Stats(records) => documents(records),
+            Batch::Events(records) => documents(records),
+            Batch::Logs(records) => documents(records),
+            Batch::RemoteWrite(records) => documents(records),
+            Batch::OtlpMetrics(records) => documents(records),
+            Batch::OtlpLogs(records) => documents(records),
+        }
+    }
+}
+
+/// A copy of a [`Batch`] that is written to the secondary sinks in the background, after
+/// whoever wrote the batch moved on.
+#[derive(Debug)]
+enum OwnedBatch {
+    Stats(Vec<StatsSnapshot>),
+    Events(Vec<Event>),
+    Logs(Vec<LogLine>),
+    RemoteWrite(Vec<RemoteSample>),
+    OtlpMetrics(Vec<OtlpMetricRow>),
+    OtlpLogs(Vec<OtlpLogRow>),
+}
+
+impl OwnedBatch {
+    fn new(batch: &Batch<'_>) -> OwnedBatch {
+        match batch {
+            Batch::Stats(records) => OwnedBatch::Stats(records.to_vec()),
+            Batch::Events(records) => OwnedBatch::Events(records.to_vec()),
+            Batch::Logs(records) => OwnedBatch::Logs(records.to_vec()),
+            Batch::RemoteWrite(records) => OwnedBatch::RemoteWrite(records.to_vec()),
+            Batch::OtlpMetrics(records) => OwnedBatch::OtlpMetrics(records.to_vec()),
+            Batch::OtlpLogs(records) => OwnedBatch::OtlpLogs(records.to_vec()),
+        }
+    }
+
+    fn batch(&self) -> Batch<'_> {
+        match self {
+            OwnedBatch::Stats(records) => Batch::Stats(records),
+            OwnedBatch::Events(records) => Batch::Events(records),
+            OwnedBatch::Logs(records) => Batch::Logs(records),
+            OwnedBatch::RemoteWrite(records) => Batch::RemoteWrite(records),
+            OwnedBatch::OtlpMetrics(records) => Batch::OtlpMetrics(records),
+            OwnedBatch::OtlpLogs(records) => Batch::OtlpLogs(records),
+        }
+    }
+}
+
+fn documents<T: Serialize>(records: &[T]) -> Result<Vec<String>> {
+    Ok(records
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<serde_json::Result<Vec<_>>>()?)
+}
+
+/// Somewhere that ingested data is written to.
+#[async_trait]
+pub trait Sink: Debug + Send + Sync {
+    /// Name of the sink, which is used in logs and the `analytics_sink_writes_total` metric.
+    fn name(&self) -> &'static str;
+
+    /// Writes every record of the batch, failing if any of them couldn't be written.
+    async fn write(&self, batch: &Batch<'_>) -> Result<()>;
+}
+
+/// How many batches can be waiting to be written to the secondary sinks before new ones
+/// are dropped, so a slow secondary sink can't grow the server's memory forever.
+const MAX_PENDING_SECONDARY_WRITES: usize = 64;
+
+/// Every configured sink. The first one is the primary sink, which is the only one whose
+/// failures are returned to whoever wrote the batch so that it can be retried or reported.
+/// The rest are written to on a best effort basis in the background, which makes it possible
+/// to dual-write while moving to a new storage without the new one affecting ingestion.
+#[derive(Debug, Clone)]
+pub struct Sinks {
+    sinks: Vec<Arc<dyn Sink>>,
+    pending: Arc<Semaphore>,
+}
+
+impl Sinks {
+    pub fn new(sinks: Vec<Arc<dyn Sink>>) -> Sinks {
+        Sinks {
+            sinks,
+            pending: Arc::new(Semaphore::new(MAX_PENDING_SECONDARY_WRITES)),
+        }
+    }
+
+    /// Creates the sinks in `sinks.outputs`, in the order they were configured.
+    pub fn from_config(
+        config: &SinksConfig,
+        clickhouse: Arc<ClickHouse>,
+        redis: Arc<Mutex<SentinelManager>>,
+    ) -> Result<Sinks> {
+        let outputs = config.outputs.clone().unwrap_or_default();
+        if outputs.is_empty() {
+            return Err(anyhow!(
+                "at least one sink has to be configured in `sinks.outputs`"
+            ));
+        }
+
+        // the primary sink has to fail writes that weren't stored, which the webhook sink
+        // can't since it delivers in the background
+        if outputs[0] == SinkKind::Webhook {
+            return Err(anyhow!(
+                "the `webhook` sink can't be the first sink in `sinks.outputs`"
+            ));
+        }
+
+        let mut sinks: Vec<Arc<dyn Sink>> = Vec::with_capacity(outputs.len());
+        for kind in outputs {
+            if sinks.iter().any(|s| s.name() == kind.as_str()) {
+                return Err(anyhow!("sink `{kind}` was configured more than once"));
+            }
+
+            sinks.push(match kind {
+                SinkKind::ClickHouse => Arc::new(ClickHouseSink::new(clickhouse.clone())),
+                SinkKind::Elasticsearch => Arc::new(ElasticsearchSink::new(
+                    &config.elasticsearch.clone().unwrap_or_default(),
+                )?),
+                SinkKind::File => Arc::new(FileSink::new(&config.file.clone().unwrap_or_default())),
+                SinkKind::Webhook => Arc::new(WebhookSink::new(
+                    &config.webhook.clone().unwrap_or_default(),
+                    redis.clone(),
+                )?),
+            });
+        }
+
+        Ok(Sinks::new(sinks))
+    }
+
+    /// Returns the names of every sink, starting with the primary one.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.sinks.iter().map(|s| s.name()).collect()
+    }
+
+    /// Writes the batch to the primary sink, and returns its error if it failed. Once the
+    /// primary sink stored the batch, it is written to the other sinks in the background,
+    /// so they neither slow down whoever wrote the batch nor get it twice when a failed
+    /// write is retried. Failures of the other sinks are only logged.
+    pub async fn write(&self, batch: Batch<'_>) -> Result<()> {
+        let (primary, secondaries) = match self.sinks.split_first() {
+            Some(sinks) if !batch.is_empty() => sinks,
+            _ => return Ok(()),
+        };
+
+        let result = primary.write(&batch).await;
+        ServerMetrics::global().record_sink_write(primary.name(), result.is_ok());
+        result?;
+
+        if secondaries.is_empty() {
+            return Ok(());
+        }
+
+        let permit = match self.pending.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                warn!(
+                    "too many batches are waiting for the secondary sinks, dropping {} {}",
+                    batch.len(),
+                    batch.kind()
+                );
+
+                for sink in secondaries {
+                    ServerMetrics::global().record_sink_write(sink.name(), false);
+                }
+
+                return Ok(());
+            }
+        };
+
+        let secondaries = secondaries.to_vec();
+        let batch = OwnedBatch::new(&batch);
+        tokio::spawn(async move {
+            let batch = batch.batch();
+            let results = join_all(secondaries.iter().map(|sink| sink.write(&batch))).await;
+            for (sink, result) in secondaries.iter().zip(results) {
+                ServerMetrics::global().record_sink_write(sink.name(), result.is_ok());
+                if let Err(e) = result {
+                    warn!(
+                        "unable to write {} {} to the {} sink: {e}",
+                        batch.len(),
+                        batch.kind(),
+                        sink.name()
+                    );
+                }
+            }
+
+            drop(permit);
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default)]
+    struct MemorySink {
+        name: &'static str,
+        fail: bool,
+        written: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Sink for MemorySink {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn write(&self, batch: &Batch<'_>) -> Result<()> {
+            if self.fail {
+                return Err(anyhow!("{} is down", self.name));
+            }
+
+            self.written.lock().unwrap().extend(batch.documents()?);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn only_the_primary_sink_fails_writes() {
+        let lines = [LogLine {
+            instance: uuid::Uuid::nil(),
+            timestamp: chrono::Utc::now(),
+            level: crate::models::log::LogLevel::Info,
+            message: "hello".into(),
+            fields: Default::default(),
+        }];
+
+        let primary = Arc::new(MemorySink {
+            name: "primary",
+            ..Default::default()
+        });
+
+        let secondary = Arc::new(MemorySink {
+            name: "secondary",
+            ..Default::default()
+        });
+
+        let down = Arc::new(MemorySink {
+            name: "down",
+            fail: true,
+            ..Default::default()
+        });
+
+        let sinks = Sinks::new(vec![primary.clone(), down.clone(), secondary.clone()]);
+        assert!(sinks.write(Batch::Logs(&lines)).await.is_ok());
+        assert_eq!(primary.written.lock().unwrap().len(), 1);
+
+        // secondary sinks are written to in the background
+        while secondary.written.lock().unwrap().is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        // and only get batches that the primary sink stored, so retries don't duplicate them
+        let sinks = Sinks::new(vec![down, secondary.clone()]);
+        let err = sinks.write(Batch::Logs(&lines)).await.unwrap_err();
+        assert_eq!(err.to_string(), "down is down");
+        tokio::task::yield_now().await;
+        assert_eq!(secondary.written.lock().unwrap().len(), 1);
+    }
+}
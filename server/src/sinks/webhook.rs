@@ -0,0 +1,559 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use redis::Commands;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, StatusCode};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
+use tokio::time::sleep;
+use uuid::Uuid;
+
+use crate::config::{WebhookEndpointConfig, WebhookSinkConfig};
+use crate::sentinel::SentinelManager;
+use crate::sinks::sink::{Batch, Sink};
+
+/// The Redis list that deliveries are pushed to once every retry failed.
+pub const DEAD_LETTERS_LIST: &str = "webhooks:dead_letters";
+
+/// How many dead letters are kept, the oldest ones are dropped first.
+const MAX_DEAD_LETTERS: isize = 10_000;
+
+/// The longest that a delivery waits before it is retried.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How many deliveries can be in flight at once, including the ones waiting to be retried.
+const MAX_PENDING_DELIVERIES: usize = 1024;
+
+/// A webhook that batches are sent to, along with the filters of what it wants.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: String,
+    secret: String,
+    events: Option<HashSet<String>>,
+    instances: Option<HashSet<String>>,
+    stats: bool,
+}
+
+impl Webhook {
+    pub fn new(config: &WebhookEndpointConfig) -> Webhook {
+        Webhook {
+            url: config.url.clone(),
+            secret: config.secret.clone(),
+            events: config.events.clone().map(HashSet::from_iter),
+            instances: config.instances.clone().map(HashSet::from_iter),
+            stats: config.stats.unwrap_or(true),
+        }
+    }
+
+    fn wants_instance(&self, instance: &str) -> bool {
+        self.instances
+            .as_ref()
+            .map(|instances| instances.contains(instance))
+            .unwrap_or(true)
+    }
+
+    /// Builds the body that is sent to this webhook out of the records it wants, or
+    /// `None` if it doesn't want any of them. Only events and stats snapshots are sent.
+    pub fn payload(&self, batch: &Batch<'_>) -> Result<Option<String>> {
+        let records = match *batch {
+            Batch::Events(events) => to_values(events.iter().filter(|e| {
+                let name = self
+                    .events
+                    .as_ref()
+                    .map(|names| names.contains(&e.name))
+                    .unwrap_or(true);
+
+                name && self.wants_instance(&e.instance.to_string())
+            }))?,
+            Batch::Stats(snapshots) if self.stats => to_values(
+                snapshots
+                    .iter()
+                    .filter(|s| self.wants_instance(&s.instance_uuid)),
+            )?,
+            _ => return Ok(None),
+        };
+
+        if records.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            json!({ "kind": batch.kind(), "records": records }).to_string(),
+        ))
+    }
+}
+
+fn to_values<'a, T: Serialize + 'a>(records: impl Iterator<Item = &'a T>) -> Result<Vec<Value>> {
+    Ok(records
+        .map(serde_json::to_value)
+        .collect::<serde_json::Result<Vec<_>>>()?)
+}
+
+/// Signs the body of a delivery with HMAC-SHA256, returning it hex encoded. The timestamp
+/// is part of what is signed so that receivers can reject deliveries that are replayed
+/// long after they were sent.
+pub fn sign(secret: &str, timestamp: u64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+
+    mac.update(format!("{timestamp}.{body}").as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Derives the ID of a delivery from what is delivered and where to, so that a batch which
+/// is delivered again has the same ID and can be de-duplicated by the receiver.
+fn delivery_id(webhook: &Webhook, kind: &str, body: &str) -> Uuid {
+    let digest = Sha256::new()
+        .chain_update(webhook.url.as_bytes())
+        .chain_update([0])
+        .chain_update(kind.as_bytes())
+        .chain_update([0])
+        .chain_update(body.as_bytes())
+        .finalize();
+
+    Uuid::from_slice(&digest[..16]).expect("SHA-256 digests are longer than 16 bytes")
+}
+
+/// Makes the attempts of deliveries, retrying them with exponential backoff.
+#[derive(Debug, Clone)]
+struct Deliverer {
+    client: Client,
+    max_retries: u32,
+    retry_backoff: Duration,
+}
+
+impl Deliverer {
+    /// Sends the body to the webhook until it is accepted or the retries run out, returning
+    /// the last error and how many attempts were made if it never was.
+    async fn deliver(
+        &self,
+        webhook: &Webhook,
+        delivery: &str,
+        body: &str,
+    ) -> std::result::Result<(), (String, u32)> {
+        let mut attempt = 0;
+        loop {
+            let (error, retryable) = match self.send(webhook, delivery, body).await {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+
+            if !retryable || attempt >= self.max_retries {
+                return Err((error, attempt + 1));
+            }
+
+            let backoff = self
+                .retry_backoff
+                .saturating_mul(2u32.saturating_pow(attempt));
+            debug!(
+                "delivery {delivery} to webhook {} failed, retrying in {backoff:?}: {error}",
+                webhook.url
+            );
+
+            sleep(backoff.min(MAX_BACKOFF)).await;
+            attempt += 1;
+        }
+    }
+
+    /// Makes a single attempt at a delivery. Failures come with whether they are worth
+    /// retrying, which is everything but the webhook rejecting the delivery itself.
+    async fn send(
+        &self,
+        webhook: &Webhook,
+        delivery: &str,
+        body: &str,
+    ) -> std::result::Result<(), (String, bool)> {
+        let timestamp = unix_timestamp();
+        let response = self
+            .client
+            .post(&webhook.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header("X-Analytics-Delivery", delivery)
+            .header("X-Analytics-Timestamp", timestamp.to_string())
+            .header(
+                "X-Analytics-Signature",
+                format!("sha256={}", sign(&webhook.secret, timestamp, body)),
+            )
+            .body(body.to_string())
+            .send()
+            .await
+            .map_err(|e| (e.to_string(), true))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+
+        let retryable = status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS;
+        Err((format!("webhook responded with {status}"), retryable))
+    }
+}
+
+/// POSTs events and stats snapshots to every configured webhook, so that they are notified
+/// as soon as they are ingested. Writes never wait on the webhooks: every delivery happens in
+/// the background and is retried with exponential backoff, and deliveries that still failed
+/// are pushed to the [`DEAD_LETTERS_LIST`] list in Redis to be inspected or replayed. Since
+/// writes succeed before anything was delivered, this sink can't be the primary sink.
+///
+/// At most [`MAX_PENDING_DELIVERIES`] deliveries are in flight at once, the ones that don't
+/// fit anymore are dead-lettered right away.
+///
+/// Every delivery has these headers:
+///
+/// * `X-Analytics-Delivery`, which is derived from the body, so it stays the same across
+///   retries and when the same batch is delivered again.
+/// * `X-Analytics-Timestamp`, the UNIX timestamp (in seconds) of the attempt.
+/// * `X-Analytics-Signature`, `sha256=` followed by the HMAC-SHA256 of `<timestamp>.<body>`
+///   with the webhook's secret.
+#[derive(Debug, Clone)]
+pub struct WebhookSink {
+    deliverer: Deliverer,
+    webhooks: Arc<Vec<Webhook>>,
+    pending: Arc<Semaphore>,
+    redis: Arc<Mutex<SentinelManager>>,
+}
+
+impl WebhookSink {
+    pub fn new(
+        config: &WebhookSinkConfig,
+        redis: Arc<Mutex<SentinelManager>>,
+    ) -> Result<WebhookSink> {
+        let defaults = WebhookSinkConfig::default();
+        let endpoints = config.endpoints.clone().unwrap_or_default();
+        if endpoints.is_empty() {
+            return Err(anyhow!(
+                "at least one webhook has to be configured in `sinks.webhook.endpoints`"
+            ));
+        }
+
+        let timeout = config.timeout.or(defaults.timeout).unwrap().max(1);
+
+        Ok(WebhookSink {
+            deliverer: Deliverer {
+                client: Client::builder()
+                    .timeout(Duration::from_secs(timeout))
+                    .build()?,
+                max_retries: config.max_retries.or(defaults.max_retries).unwrap(),
+                retry_backoff: Duration::from_millis(
+                    config.retry_backoff.or(defaults.retry_backoff).unwrap(),
+                ),
+            },
+            webhooks: Arc::new(endpoints.iter().map(Webhook::new).collect()),
+            pending: Arc::new(Semaphore::new(MAX_PENDING_DELIVERIES)),
+            redis,
+        })
+    }
+
+    /// Delivers the body to the webhook, and dead-letters it if it never was accepted.
+    async fn deliver(
+        self,
+        webhook: Webhook,
+        kind: &'static str,
+        body: String,
+        permit: OwnedSemaphorePermit,
+    ) {
+        let delivery = delivery_id(&webhook, kind, &body).to_string();
+        let result = self.deliverer.deliver(&webhook, &delivery, &body).await;
+        drop(permit);
+
+        if let Err((error, attempts)) = result {
+            warn!(
+                "giving up on delivery {delivery} to webhook {} after {attempts} attempts: {error}",
+                webhook.url
+            );
+
+            self.dead_letter(&webhook, kind, &body, &error, attempts)
+                .await;
+        }
+    }
+
+    async fn dead_letter(
+        &self,
+        webhook: &Webhook,
+        kind: &'static str,
+        body: &str,
+        error: &str,
+        attempts: u32,
+    ) {
+        let delivery = delivery_id(webhook, kind, body).to_string();
+        let letter = json!({
+            "delivery": delivery,
+            "url": webhook.url,
+            "kind": kind,
+            "body": body,
+            "error": error,
+            "attempts": attempts,
+            "failed_at": unix_timestamp(),
+        });
+
+        if let Err(e) = self.push_dead_letter(letter.to_string()).await {
+            error!("unable to dead-letter delivery {delivery}: {e}");
+        }
+    }
+
+    async fn push_dead_letter(&self, letter: String) -> Result<()> {
+        let mut client = self.redis.lock().await.get_master().await?;
+        client.rpush::<&str, String, i64>(DEAD_LETTERS_LIST, letter)?;
+        client.ltrim::<&str, ()>(DEAD_LETTERS_LIST, -MAX_DEAD_LETTERS, -1)?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for WebhookSink {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn write(&self, batch: &Batch<'_>) -> Result<()> {
+        for webhook in self.webhooks.iter() {
+            let body = match webhook.payload(batch)? {
+                Some(body) => body,
+                None => continue,
+            };
+
+            match self.pending.clone().try_acquire_owned() {
+                Ok(permit) => {
+                    tokio::spawn(
+                        self.clone()
+                            .deliver(webhook.clone(), batch.kind(), body, permit),
+                    );
+                }
+                Err(_) => {
+                    warn!(
+                        "too many deliveries are in flight, dead-lettering {} {} for webhook {}",
+                        batch.len(),
+                        batch.kind(),
+                        webhook.url
+                    );
+
+                    self.dead_letter(
+                        webhook,
+                        batch.kind(),
+                        &body,
+                        "too many deliveries were in flight",
+                        0,
+                    )
+                    .await;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before the UNIX epoch")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::event::Event;
+    use chrono::{TimeZone, Utc};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    fn event(name: &str, instance: Uuid) -> Event {
+        Event {
+            name: name.into(),
+            timestamp: Utc.timestamp_opt(1685577600, 0).unwrap(),
+            instance,
+            properties: Default::default(),
+        }
+    }
+
+    /// Answers a request with each of the given statuses in turn, and returns the requests
+    /// that it got.
+    async fn mock_server(statuses: Vec<u16>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hooks", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let mut requests = vec![];
+            for status in statuses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![];
+                let mut buf = [0; 4096];
+                loop {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|l| {
+                                l.to_lowercase()
+                                    .strip_prefix("content-length: ")
+                                    .map(String::from)
+                            })
+                            .and_then(|l| l.parse::<usize>().ok())
+                            .unwrap_or(0);
+
+                        if body.len() >= length {
+                            break;
+                        }
+                    }
+                }
+
+                stream
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 {status} Mock\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                        )
+                        .as_bytes(),
+                    )
+                    .await
+                    .unwrap();
+
+                requests.push(String::from_utf8(request).unwrap().to_lowercase());
+            }
+
+            requests
+        });
+
+        (url, handle)
+    }
+
+    fn webhook(url: String) -> Webhook {
+        Webhook::new(&WebhookEndpointConfig {
+            url,
+            secret: "s3cr3t".into(),
+            events: None,
+            instances: None,
+            stats: None,
+        })
+    }
+
+    fn deliverer() -> Deliverer {
+        Deliverer {
+            client: Client::new(),
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(1),
+        }
+    }
+
+    fn header<'a>(request: &'a str, name: &str) -> &'a str {
+        request
+            .lines()
+            .find_map(|l| l.strip_prefix(&format!("{name}: ")))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_until_the_delivery_is_accepted() {
+        let (url, server) = mock_server(vec![500, 503, 200]).await;
+        let webhook = webhook(url);
+        let body = r#"{"kind":"events","records":[]}"#;
+        let delivery = delivery_id(&webhook, "events", body).to_string();
+
+        assert!(deliverer().deliver(&webhook, &delivery, body).await.is_ok());
+
+        let requests = server.await.unwrap();
+        assert_eq!(requests.len(), 3);
+        for request in &requests {
+            assert_eq!(header(request, "x-analytics-delivery"), delivery);
+            let timestamp = header(request, "x-analytics-timestamp");
+            assert_eq!(
+                header(request, "x-analytics-signature"),
+                format!(
+                    "sha256={}",
+                    sign("s3cr3t", timestamp.parse().unwrap(), body)
+                )
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn doesnt_retry_rejected_deliveries() {
+        let (url, server) = mock_server(vec![400]).await;
+        let webhook = webhook(url);
+
+        let (error, attempts) = deliverer()
+            .deliver(&webhook, "delivery", "{}")
+            .await
+            .unwrap_err();
+
+        assert_eq!(attempts, 1);
+        assert_eq!(error, "webhook responded with 400 Bad Request");
+        assert_eq!(server.await.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn derives_delivery_ids_from_the_body() {
+        let webhook = webhook("http://localhost:3000/hooks".into());
+        let id = delivery_id(&webhook, "events", "{}");
+
+        assert_eq!(id, delivery_id(&webhook, "events", "{}"));
+        assert_ne!(id, delivery_id(&webhook, "events", "[]"));
+        assert_ne!(id, delivery_id(&webhook, "instance_stats", "{}"));
+    }
+
+    #[test]
+    fn filters_events_by_name_and_instance() {
+        let instance = Uuid::new_v4();
+        let webhook = Webhook::new(&WebhookEndpointConfig {
+            url: "http://localhost:3000/hooks".into(),
+            secret: "s3cr3t".into(),
+            events: Some(vec!["user.login".into()]),
+            instances: Some(vec![instance.to_string()]),
+            stats: Some(false),
+        });
+
+        let events = [
+            event("user.login", instance),
+            event("user.logout", instance),
+            event("user.login", Uuid::nil()),
+        ];
+
+        let payload = webhook.payload(&Batch::Events(&events)).unwrap().unwrap();
+        let payload = serde_json::from_str::<Value>(&payload).unwrap();
+        assert_eq!(payload["kind"], "events");
+        assert_eq!(payload["records"].as_array().unwrap().len(), 1);
+        assert_eq!(payload["records"][0]["name"], "user.login");
+
+        let others = [event("user.logout", instance)];
+        assert!(webhook.payload(&Batch::Events(&others)).unwrap().is_none());
+        assert!(webhook.payload(&Batch::Stats(&[])).unwrap().is_none());
+    }
+
+    #[test]
+    fn signs_the_timestamp_and_body() {
+        assert_eq!(
+            sign("s3cr3t", 1685577600, r#"{"kind":"events","records":[]}"#),
+            "3f8a92402f0c2297a269a8755e469725394bfe595993190bf10926705239c086"
+        );
+    }
+}
@@ -0,0 +1,110 @@
+// 🐻‍❄️🐾 Noelware Analytics: Platform to build upon metrics ingested from any source, from your HTTP server to system-level metrics
+// Copyright 2022-2023 Noelware <team@noelware.org>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+
+use crate::config::Config;
+
+/// The epoch that every snowflake is relative to, which is January 1st, 2022 (UTC).
+pub const EPOCH: u64 = 1_640_995_200_000;
+
+const SEQUENCE_BITS: u64 = 12;
+const WORKER_BITS: u64 = 10;
+const MAX_SEQUENCE: u64 = (1 << SEQUENCE_BITS) - 1;
+
+/// The highest worker ID that fits into a snowflake.
+pub const MAX_WORKER: u16 = (1 << WORKER_BITS) - 1;
+
+static GENERATOR: Lazy<Mutex<Snowflake>> = Lazy::new(|| {
+    let worker = Config::get()
+        .and_then(|config| config.server.as_ref())
+        .and_then(|server| server.worker_id)
+        .unwrap_or(0);
+
+    Mutex::new(Snowflake::new(worker))
+});
+
+/// Generator for the snowflake IDs that the Postgres models use as their primary key. A
+/// snowflake is made out of the milliseconds since [`EPOCH`], the worker ID and a
+/// per-millisecond sequence.
+#[derive(Debug)]
+pub struct Snowflake {
+    worker: u64,
+    last_timestamp: u64,
+    sequence: u64,
+}
+
+impl Snowflake {
+    pub fn new(worker: u16) -> Snowflake {
+        Snowflake {
+            worker: (worker & MAX_WORKER) as u64,
+            last_timestamp: 0,
+            sequence: 0,
+        }
+    }
+
+    /// Generates the next ID, waiting for the next millisecond if the sequence ran out.
+    pub fn generate(&mut self) -> i64 {
+        let mut timestamp = now();
+        if timestamp <= self.last_timestamp {
+            timestamp = self.last_timestamp;
+            self.sequence = (self.sequence + 1) & MAX_SEQUENCE;
+            if self.sequence == 0 {
+                timestamp += 1;
+                while now() < timestamp {
+                    std::hint::spin_loop();
+                }
+            }
+        } else {
+            self.sequence = 0;
+        }
+
+        self.last_timestamp = timestamp;
+        (((timestamp - EPOCH) << (WORKER_BITS + SEQUENCE_BITS))
+            | (self.worker << SEQUENCE_BITS)
+            | self.sequence) as i64
+    }
+}
+
+/// Generates a new snowflake from the global generator.
+pub fn generate() -> i64 {
+    GENERATOR.lock().unwrap().generate()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before the UNIX epoch")
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snowflakes_are_unique_and_increasing() {
+        let mut snowflake = Snowflake::new(1);
+        let ids = (0..10_000)
+            .map(|_| snowflake.generate())
+            .collect::<Vec<_>>();
+
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert!(ids.iter().all(|id| (id >> SEQUENCE_BITS) & 0x3ff == 1));
+    }
+}